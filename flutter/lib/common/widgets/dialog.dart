@@ -5,6 +5,7 @@ import 'package:bot_toast/bot_toast.dart';
 import 'package:flutter/material.dart';
 import 'package:flutter/services.dart';
 import 'package:flutter/widgets.dart';
+import 'package:flutter_hbb/common/formatter/id_formatter.dart';
 import 'package:flutter_hbb/common/shared_state.dart';
 import 'package:flutter_hbb/common/widgets/setting_widgets.dart';
 import 'package:flutter_hbb/consts.dart';
@@ -1289,6 +1290,53 @@ void showRestartRemoteDevice(PeerInfo pi, String id, SessionID sessionId,
   if (res == true) bind.sessionRestartRemoteDevice(sessionId: sessionId);
 }
 
+// The peer sends the magic packets to the discovered lan device, which is asleep on its lan.
+void showWakeLanDeviceDialog(
+    SessionID sessionId, OverlayDialogManager dialogManager) {
+  List<dynamic> peers = [];
+  try {
+    final lanPeers = jsonDecode(bind.mainLoadLanPeersSync());
+    peers = jsonDecode(lanPeers['peers'] ?? '[]');
+  } catch (e) {
+    debugPrint('Failed to load lan peers: $e');
+  }
+  dialogManager.show((setState, close, context) => CustomAlertDialog(
+        title: Text(translate('Wake up LAN device')),
+        content: ConstrainedBox(
+          constraints: const BoxConstraints(maxHeight: 300),
+          child: peers.isEmpty
+              ? Text(translate('wol-relay-empty-tip'))
+              : SingleChildScrollView(
+                  child: Column(
+                    mainAxisSize: MainAxisSize.min,
+                    children: peers
+                        .map((peer) => ListTile(
+                              title: Text(formatID(peer['id'] ?? '')),
+                              subtitle: Text('${peer['username'] ?? ''}'
+                                  '@${peer['hostname'] ?? ''}'),
+                              onTap: () {
+                                bind.sessionSendWol(
+                                    sessionId: sessionId,
+                                    id: peer['id'] ?? '');
+                                close();
+                              },
+                            ))
+                        .toList(),
+                  ),
+                ),
+        ),
+        actions: [
+          dialogButton(
+            "Cancel",
+            icon: Icon(Icons.close_rounded),
+            onPressed: close,
+            isOutline: true,
+          ),
+        ],
+        onCancel: close,
+      ));
+}
+
 showSetOSPassword(
   SessionID sessionId,
   bool login,
@@ -2500,3 +2548,321 @@ Widget trustedDevicesTable(
         )),
   );
 }
+
+class AccessCode {
+  late final int expire;
+  late final bool viewOnly;
+  late final bool fileTransfer;
+  late final bool clipboard;
+
+  AccessCode.fromJson(Map<String, dynamic> json) {
+    expire = json['expire'];
+    viewOnly = json['view_only'] ?? false;
+    fileTransfer = json['file_transfer'] ?? false;
+    clipboard = json['clipboard'] ?? false;
+  }
+
+  String minutesRemaining() {
+    final remaining = expire - DateTime.now().millisecondsSinceEpoch;
+    if (remaining < 0) {
+      return '0';
+    }
+    return (remaining / (60 * 1000)).ceil().toString();
+  }
+
+  String scope() {
+    return [
+      translate(viewOnly ? 'View only' : 'Full access'),
+      if (fileTransfer) translate('File transfer'),
+      if (clipboard) translate('Clipboard'),
+    ].join(', ');
+  }
+
+  // The codes themselves are only shown once on creation.
+  static Future<List<AccessCode>> get() async {
+    final List<AccessCode> codes = List.empty(growable: true);
+    try {
+      final codesJson = await bind.mainGetAccessCodes();
+      if (codesJson.isNotEmpty) {
+        final codesList = json.decode(codesJson);
+        if (codesList is List) {
+          for (var code in codesList) {
+            codes.add(AccessCode.fromJson(code));
+          }
+        }
+      }
+    } catch (e) {
+      print(e.toString());
+    }
+    codes.sort((a, b) => a.expire.compareTo(b.expire));
+    return codes;
+  }
+}
+
+void manageAccessCodesDialog() async {
+  RxList<AccessCode> codes = (await AccessCode.get()).obs;
+  String minutes = '60';
+  RxBool viewOnly = false.obs;
+  RxBool fileTransfer = false.obs;
+  RxBool clipboard = true.obs;
+  gFFI.dialogManager.show((setState, close, context) {
+    generate() async {
+      final code = await bind.mainGenerateAccessCode(
+          minutes: int.parse(minutes),
+          viewOnly: viewOnly.value,
+          fileTransfer: fileTransfer.value,
+          clipboard: clipboard.value);
+      codes.value = await AccessCode.get();
+      showAccessCodeDialog(code);
+    }
+
+    checkbox(String label, RxBool value) {
+      return Obx(() => CheckboxListTile(
+            contentPadding: EdgeInsets.zero,
+            visualDensity: VisualDensity.compact,
+            controlAffinity: ListTileControlAffinity.leading,
+            value: value.value,
+            onChanged: (v) {
+              if (v != null) value.value = v;
+            },
+            title: Text(translate(label)),
+          ));
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate("Manage access codes")),
+      content: Column(
+        mainAxisSize: MainAxisSize.min,
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Text(translate('access-code-tip')),
+          Row(
+            children: [
+              Text(translate('Valid for')).marginOnly(right: 10),
+              ComboBox(
+                  keys: ['15', '60', '240', '1440'],
+                  values: [
+                    '15 ${translate('minutes')}',
+                    '1 ${translate('hour')}',
+                    '4 ${translate('hours')}',
+                    '1 ${translate('day')}',
+                  ],
+                  initialKey: minutes,
+                  onChanged: (value) {
+                    minutes = value;
+                  }),
+            ],
+          ).marginOnly(top: 12),
+          checkbox('View only', viewOnly),
+          checkbox('File transfer', fileTransfer),
+          checkbox('Clipboard', clipboard),
+          const Divider(),
+          Obx(() => codes.isEmpty
+              ? Text(translate('No active access codes'))
+              : FittedBox(
+                  child: DataTable(
+                    columns: [
+                      DataColumn(label: Text(translate('Permissions'))),
+                      DataColumn(label: Text(translate('Minutes remaining'))),
+                    ],
+                    rows: codes.map((code) {
+                      return DataRow(cells: [
+                        DataCell(Text(code.scope())),
+                        DataCell(Text(code.minutesRemaining())),
+                      ]);
+                    }).toList(),
+                  ),
+                )),
+        ],
+      ),
+      actions: [
+        Obx(() => dialogButton(translate("Clear"),
+                onPressed: codes.isEmpty
+                    ? null
+                    : () async {
+                        await bind.mainClearAccessCodes();
+                        codes.clear();
+                      },
+                isOutline: true)
+            .marginOnly(top: 12)),
+        dialogButton(translate("Generate"), onPressed: generate)
+            .marginOnly(top: 12),
+        dialogButton(translate("Close"), onPressed: close, isOutline: true)
+            .marginOnly(top: 12),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
+void showAccessCodeDialog(String code) {
+  gFFI.dialogManager.show((setState, close, context) {
+    return CustomAlertDialog(
+      title: Text(translate("Access code")),
+      content: Column(
+        mainAxisSize: MainAxisSize.min,
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Text(translate('access-code-once-tip')),
+          SelectableText(code,
+                  style: const TextStyle(
+                      fontSize: 22, fontWeight: FontWeight.bold))
+              .marginOnly(top: 12),
+        ],
+      ),
+      actions: [
+        dialogButton(translate("Copy"), onPressed: () {
+          Clipboard.setData(ClipboardData(text: code));
+          showToast(translate('Copied'));
+        }, isOutline: true),
+        dialogButton(translate("OK"), onPressed: close),
+      ],
+      onSubmit: close,
+      onCancel: close,
+    );
+  });
+}
+
+void authorizedKeysDialog() async {
+  final myKey = bind.mainGetControllerPublicKey();
+  final controller = TextEditingController(
+      text: await bind.mainGetOption(key: kOptionAuthorizedKeys));
+  final isOptFixed = isOptionFixed(kOptionAuthorizedKeys);
+  gFFI.dialogManager.show((setState, close, context) {
+    submit() async {
+      await bind.mainSetOption(
+          key: kOptionAuthorizedKeys, value: controller.text.trim());
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate("Authorized keys")),
+      content: Column(
+        mainAxisSize: MainAxisSize.min,
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Text(translate('authorized-keys-tip')),
+          const SizedBox(
+            height: 8.0,
+          ),
+          TextField(
+                  maxLines: 6,
+                  minLines: 3,
+                  controller: controller,
+                  enabled: !isOptFixed,
+                  style: const TextStyle(fontFamily: 'monospace'),
+                  autofocus: true)
+              .workaroundFreezeLinuxMint(),
+          const Divider(),
+          Text(translate('my-public-key-tip')),
+          Row(
+            children: [
+              Expanded(
+                child: SelectableText(myKey,
+                    style: const TextStyle(fontFamily: 'monospace')),
+              ),
+              IconButton(
+                icon: const Icon(Icons.copy),
+                tooltip: translate('Copy'),
+                onPressed: () {
+                  Clipboard.setData(ClipboardData(text: myKey));
+                  showToast(translate('Copied'));
+                },
+              ),
+            ],
+          ),
+        ],
+      ),
+      actions: [
+        dialogButton("Cancel", onPressed: close, isOutline: true),
+        if (!isOptFixed) dialogButton("OK", onPressed: submit),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
+class LoginLockout {
+  late final String source;
+  late final int kind;
+  // 0 if the source is not locked, i.e. a peer id whose failures are only counted
+  late final int until;
+  late final int lockouts;
+
+  LoginLockout.fromJson(Map<String, dynamic> json) {
+    source = json['source'];
+    kind = json['kind'];
+    until = json['until'];
+    lockouts = json['lockouts'] ?? 0;
+  }
+
+  bool get locked => until > 0;
+
+  static Future<List<LoginLockout>> get() async {
+    final List<LoginLockout> lockouts = List.empty(growable: true);
+    try {
+      final lockoutsJson = await bind.mainGetLoginLockouts();
+      if (lockoutsJson.isNotEmpty) {
+        final lockoutsList = json.decode(lockoutsJson);
+        if (lockoutsList is List) {
+          for (var lockout in lockoutsList) {
+            lockouts.add(LoginLockout.fromJson(lockout));
+          }
+        }
+      }
+    } catch (e) {
+      print(e.toString());
+    }
+    lockouts.sort((a, b) => b.until.compareTo(a.until));
+    return lockouts;
+  }
+}
+
+void loginLockoutsDialog() async {
+  RxList<LoginLockout> lockouts = (await LoginLockout.get()).obs;
+  gFFI.dialogManager.show((setState, close, context) {
+    unlock(String source) async {
+      await bind.mainUnlockLoginSource(source: source);
+      lockouts.value = await LoginLockout.get();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate("Login lockouts")),
+      content: Obx(() => lockouts.isEmpty
+          ? Text(translate('login-lockouts-empty-tip'))
+          : FittedBox(
+              child: DataTable(
+                columns: [
+                  DataColumn(label: Text(translate('Source'))),
+                  DataColumn(label: Text(translate('Type'))),
+                  DataColumn(label: Text(translate('Thresholds reached'))),
+                  DataColumn(label: Text(translate('Locked until'))),
+                  DataColumn(label: Text('')),
+                ],
+                rows: lockouts.map((lockout) {
+                  final until =
+                      DateTime.fromMillisecondsSinceEpoch(lockout.until);
+                  return DataRow(cells: [
+                    DataCell(Text(lockout.source)),
+                    DataCell(Text(lockout.kind == 1
+                        ? translate('2FA')
+                        : translate('Password'))),
+                    DataCell(Text(lockout.lockouts.toString())),
+                    DataCell(Text(lockout.locked
+                        ? until.toString().split('.').first
+                        : translate('Not locked'))),
+                    DataCell(TextButton(
+                        onPressed: () => unlock(lockout.source),
+                        child: Text(translate(
+                            lockout.locked ? 'Unlock' : 'Clear')))),
+                  ]);
+                }).toList(),
+              ),
+            )),
+      actions: [
+        dialogButton(translate("Close"), onPressed: close, isOutline: true),
+      ],
+      onCancel: close,
+    );
+  });
+}
@@ -184,6 +184,7 @@ class _PeerCardState extends State<_PeerCard>
                           overflow: TextOverflow.ellipsis,
                           style: Theme.of(context).textTheme.titleSmall,
                         )),
+                        getLanUnverified(peer),
                       ]).marginOnly(top: isPortrait ? 0 : 2),
                       Align(
                         alignment: Alignment.centerLeft,
@@ -318,6 +319,7 @@ class _PeerCardState extends State<_PeerCard>
                           overflow: TextOverflow.ellipsis,
                           style: Theme.of(context).textTheme.titleSmall,
                         )),
+                        getLanUnverified(peer),
                       ]).paddingSymmetric(vertical: 8)),
                       checkBoxOrActionMoreLandscape(peer, isTile: false),
                     ],
@@ -663,6 +665,27 @@ abstract class BasePeerCard extends StatelessWidget {
     );
   }
 
+  // The key is only sent to the peers it is enabled for, it would identify us to all of them.
+  @protected
+  Future<MenuEntryBase<String>> _publicKeyAuthAction(String id) async {
+    return MenuEntrySwitch<String>(
+      switchType: SwitchType.scheckbox,
+      text: translate('Sign in with my key'),
+      getter: () async {
+        return (await bind.mainGetPeerOption(
+                id: id, key: kOptionPublicKeyAuth)) ==
+            'Y';
+      },
+      setter: (bool v) async {
+        await bind.mainSetPeerOption(
+            id: id, key: kOptionPublicKeyAuth, value: v ? 'Y' : '');
+        showToast(translate('Successful'));
+      },
+      padding: menuPadding,
+      dismissOnClicked: true,
+    );
+  }
+
   @protected
   MenuEntryBase<String> _renameAction(String id) {
     return MenuEntryButton<String>(
@@ -890,6 +913,7 @@ class RecentPeerCard extends BasePeerCard {
     // menuItems.add(await _openNewConnInOptAction(peer.id));
     if (!isWeb) {
       menuItems.add(await _forceAlwaysRelayAction(peer.id));
+      menuItems.add(await _publicKeyAuthAction(peer.id));
     }
     if (isWindows && peer.platform == kPeerPlatformWindows) {
       menuItems.add(_rdpAction(context, peer.id));
@@ -946,6 +970,7 @@ class FavoritePeerCard extends BasePeerCard {
     // menuItems.add(await _openNewConnInOptAction(peer.id));
     if (!isWeb) {
       menuItems.add(await _forceAlwaysRelayAction(peer.id));
+      menuItems.add(await _publicKeyAuthAction(peer.id));
     }
     if (isWindows && peer.platform == kPeerPlatformWindows) {
       menuItems.add(_rdpAction(context, peer.id));
@@ -1002,6 +1027,7 @@ class DiscoveredPeerCard extends BasePeerCard {
     // menuItems.add(await _openNewConnInOptAction(peer.id));
     if (!isWeb) {
       menuItems.add(await _forceAlwaysRelayAction(peer.id));
+      menuItems.add(await _publicKeyAuthAction(peer.id));
     }
     if (isWindows && peer.platform == kPeerPlatformWindows) {
       menuItems.add(_rdpAction(context, peer.id));
@@ -1332,6 +1358,17 @@ Widget getOnline(double rightPadding, bool online) {
               radius: 3, backgroundColor: online ? Colors.green : kColorWarn)));
 }
 
+// The lan peers which are not verified may be impersonated by another device.
+Widget getLanUnverified(Peer peer) {
+  return Offstage(
+      offstage: peer.lanVerified != false,
+      child: Tooltip(
+          message: translate('lan-unverified-tip'),
+          waitDuration: const Duration(seconds: 1),
+          child: Icon(Icons.gpp_maybe_outlined, size: 16, color: kColorWarn)
+              .marginOnly(left: 4)));
+}
+
 Widget build_more(BuildContext context, {bool invert = false}) {
   final RxBool hover = false.obs;
   return InkWell(
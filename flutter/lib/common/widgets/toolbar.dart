@@ -175,6 +175,15 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
               connectWithToken(isFileTransfer: false, isTcpTunneling: true)),
     );
   }
+  // wake up a device on the lan of the peer
+  if (!isWeb) {
+    v.add(
+      TTextMenu(
+          child: Text(translate('Wake up LAN device')),
+          onPressed: () =>
+              showWakeLanDeviceDialog(sessionId, ffi.dialogManager)),
+    );
+  }
   // note
   if (bind
       .sessionGetAuditServerSync(sessionId: sessionId, typ: "conn")
@@ -402,6 +411,35 @@ Future<List<TRadioMenu<String>>> toolbarCodec(
   ];
 }
 
+// the latency target of the jitter buffer in ms, the frames are presented on arrival if empty
+Future<List<TRadioMenu<String>>> toolbarJitterBuffer(
+    BuildContext context, String id, FFI ffi) async {
+  final sessionId = ffi.sessionId;
+  final groupValue = await bind.sessionGetOption(
+          sessionId: sessionId, arg: kOptionJitterBufferTarget) ??
+      '';
+  onChanged(String? value) async {
+    if (value == null) return;
+    await bind.sessionPeerOption(
+        sessionId: sessionId, name: kOptionJitterBufferTarget, value: value);
+  }
+
+  TRadioMenu<String> radio(String label, String value) {
+    return TRadioMenu<String>(
+        child: Text(label),
+        value: value,
+        groupValue: groupValue,
+        onChanged: onChanged);
+  }
+
+  return [
+    radio(translate('Off'), ''),
+    radio('50 ms', '50'),
+    radio('100 ms', '100'),
+    radio('200 ms', '200'),
+  ];
+}
+
 Future<List<TToggleMenu>> toolbarCursor(
     BuildContext context, String id, FFI ffi) async {
   List<TToggleMenu> v = [];
@@ -656,6 +694,35 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         child: Text(translate('True color (4:4:4)'))));
   }
 
+  // lossless text, ignored if either side does not support the lossless tiles
+  if (versionCmp(pi.version, "1.3.6") >= 0) {
+    final option = 'lossless-text';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) async {
+          if (value == null) return;
+          await bind.sessionToggleOption(sessionId: sessionId, value: option);
+          bind.sessionChangePreferCodec(sessionId: sessionId);
+        },
+        child: Text(translate('Lossless text'))));
+  }
+
+  // bypass the jitter buffer
+  {
+    final option = 'low-latency-mode';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) async {
+          if (value == null) return;
+          await bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Low latency mode'))));
+  }
+
   if (isMobile) {
     v.addAll(toolbarKeyboardToggles(ffi));
   }
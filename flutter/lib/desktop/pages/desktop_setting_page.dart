@@ -623,6 +623,9 @@ class _GeneralState extends State<_General> {
         if (!bind.isOutgoingOnly())
           _OptionCheckBox(context, 'Automatically record incoming sessions',
               kOptionAllowAutoRecordIncoming),
+        if (!bind.isOutgoingOnly())
+          _OptionCheckBox(context, 'allow-record-input-text-tip',
+              kOptionAllowRecordInputText),
         if (!bind.isIncomingOnly())
           _OptionCheckBox(context, 'Automatically record outgoing sessions',
               kOptionAllowAutoRecordOutgoing,
@@ -1103,6 +1106,9 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
             // if (usePassword)
             //   hide_cm(!locked).marginOnly(left: _kContentHSubMargin - 6),
             if (usePassword) radios[2],
+            if (usePassword)
+              _SubButton('Manage access codes', manageAccessCodesDialog,
+                  !locked),
           ]);
         })));
   }
@@ -1113,8 +1119,13 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
       shareRdp(context, enabled),
       _OptionCheckBox(context, 'Deny LAN discovery', 'enable-lan-discovery',
           reverse: true, enabled: enabled),
+      _OptionCheckBox(context, 'allow-wol-relay-tip', kOptionAllowWolRelay,
+          reverse: false, enabled: enabled),
       ...directIp(context),
       whitelist(),
+      _Button('Authorized keys', authorizedKeysDialog,
+          enabled: enabled, tip: 'authorized-keys-tip'),
+      _Button('Login lockouts', loginLockoutsDialog, enabled: enabled),
       ...autoDisconnect(context),
       if (bind.mainIsInstalled())
         _OptionCheckBox(context, 'allow-only-conn-window-open-tip',
@@ -1043,6 +1043,7 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         scrollStyle(),
         imageQuality(),
         codec(),
+        jitterBuffer(),
         _ResolutionsMenu(
           id: widget.id,
           ffi: widget.ffi,
@@ -1199,6 +1200,25 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         });
   }
 
+  jitterBuffer() {
+    return futureBuilder(
+        future: toolbarJitterBuffer(context, id, ffi),
+        hasData: (data) {
+          final v = data as List<TRadioMenu<String>>;
+          return _SubmenuButton(
+              ffi: widget.ffi,
+              child: Text(translate('Jitter buffer')),
+              menuChildren: v
+                  .map((e) => RdoMenuButton(
+                      value: e.value,
+                      groupValue: e.groupValue,
+                      onChanged: e.onChanged,
+                      child: e.child,
+                      ffi: ffi))
+                  .toList());
+        });
+  }
+
   cursorToggles() {
     return futureBuilder(
         future: toolbarCursor(context, id, ffi),
@@ -20,6 +20,8 @@ class Peer {
   bool online = false;
   String loginName; //login username
   bool? sameServer;
+  // null if it is not a lan peer
+  bool? lanVerified;
 
   String getId() {
     if (alias != '') {
@@ -41,7 +43,9 @@ class Peer {
         rdpPort = json['rdpPort'] ?? '',
         rdpUsername = json['rdpUsername'] ?? '',
         loginName = json['loginName'] ?? '',
-        sameServer = json['same_server'];
+        sameServer = json['same_server'],
+        lanVerified =
+            json['verified'] == null ? null : json['verified'] == 'true';
 
   Map<String, dynamic> toJson() {
     return <String, dynamic>{
@@ -100,6 +104,7 @@ class Peer {
     required this.rdpUsername,
     required this.loginName,
     this.sameServer,
+    this.lanVerified,
   });
 
   Peer.loading()
@@ -146,7 +151,8 @@ class Peer {
             rdpPort: other.rdpPort,
             rdpUsername: other.rdpUsername,
             loginName: other.loginName,
-            sameServer: other.sameServer);
+            sameServer: other.sameServer,
+            lanVerified: other.lanVerified);
 }
 
 enum UpdateEvent { online, load }
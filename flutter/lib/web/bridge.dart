@@ -1341,6 +1341,11 @@ class RustdeskImpl {
     throw UnimplementedError("mainWol");
   }
 
+  Future<void> sessionSendWol(
+      {required UuidValue sessionId, required String id, dynamic hint}) {
+    throw UnimplementedError("sessionSendWol");
+  }
+
   Future<void> mainCreateShortcut({required String id, dynamic hint}) {
     throw UnimplementedError("mainCreateShortcut");
   }
@@ -1776,6 +1781,35 @@ class RustdeskImpl {
     throw UnimplementedError("mainClearTrustedDevices");
   }
 
+  Future<String> mainGetAccessCodes({dynamic hint}) {
+    throw UnimplementedError("mainGetAccessCodes");
+  }
+
+  Future<String> mainGenerateAccessCode(
+      {required int minutes,
+      required bool viewOnly,
+      required bool fileTransfer,
+      required bool clipboard,
+      dynamic hint}) {
+    throw UnimplementedError("mainGenerateAccessCode");
+  }
+
+  Future<void> mainClearAccessCodes({dynamic hint}) {
+    throw UnimplementedError("mainClearAccessCodes");
+  }
+
+  String mainGetControllerPublicKey({dynamic hint}) {
+    throw UnimplementedError("mainGetControllerPublicKey");
+  }
+
+  Future<String> mainGetLoginLockouts({dynamic hint}) {
+    throw UnimplementedError("mainGetLoginLockouts");
+  }
+
+  Future<void> mainUnlockLoginSource({required String source, dynamic hint}) {
+    throw UnimplementedError("mainUnlockLoginSource");
+  }
+
   Future<String> getVoiceCallInputDevice({required bool isCm, dynamic hint}) {
     throw UnimplementedError("getVoiceCallInputDevice");
   }
@@ -54,6 +54,7 @@ lazy_static::lazy_static! {
     static ref CONFIG2: RwLock<Config2> = RwLock::new(Config2::load());
     static ref LOCAL_CONFIG: RwLock<LocalConfig> = RwLock::new(LocalConfig::load());
     static ref TRUSTED_DEVICES: RwLock<(Vec<TrustedDevice>, bool)> = Default::default();
+    static ref ACCESS_CODES: RwLock<(Vec<AccessCode>, bool)> = Default::default();
     static ref ONLINE: Mutex<HashMap<String, i64>> = Default::default();
     pub static ref PROD_RENDEZVOUS_SERVER: RwLock<String> = RwLock::new(match option_env!("RENDEZVOUS_SERVER") {
         Some(key) if !key.is_empty() => key,
@@ -219,6 +220,8 @@ pub struct Config2 {
     unlock_pin: String,
     #[serde(default, deserialize_with = "deserialize_string")]
     trusted_devices: String,
+    #[serde(default, deserialize_with = "deserialize_string")]
+    access_codes: String,
 
     #[serde(default)]
     socks: Option<Socks5Server>,
@@ -281,6 +284,9 @@ pub struct PeerConfig {
     pub port_forwards: Vec<(i32, String, i32)>,
     #[serde(default, deserialize_with = "deserialize_i32")]
     pub direct_failures: i32,
+    // base64 of the key of the peer, verified with the rendezvous server on the last connection
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub pk: String,
     #[serde(flatten)]
     pub disable_audio: DisableAudio,
     #[serde(flatten)]
@@ -363,6 +369,7 @@ impl Default for PeerConfig {
             allow_swap_key: Default::default(),
             port_forwards: Default::default(),
             direct_failures: Default::default(),
+            pk: Default::default(),
             disable_audio: Default::default(),
             disable_clipboard: Default::default(),
             enable_file_copy_paste: Default::default(),
@@ -1182,6 +1189,79 @@ impl Config {
         Self::set_trusted_devices(Default::default());
     }
 
+    // The codes are shown once on creation, only their expiry and scope are listed.
+    pub fn get_access_codes_json() -> String {
+        let codes: Vec<_> = Self::get_access_codes()
+            .into_iter()
+            .map(|c| {
+                serde_json::json!({
+                    "expire": c.expire,
+                    "view_only": c.view_only,
+                    "file_transfer": c.file_transfer,
+                    "clipboard": c.clipboard,
+                })
+            })
+            .collect();
+        serde_json::to_string(&codes).unwrap_or_default()
+    }
+
+    pub fn get_access_codes() -> Vec<AccessCode> {
+        let (codes, synced) = ACCESS_CODES.read().unwrap().clone();
+        if synced {
+            // codes expire in minutes, the outdated ones are dropped on the next store
+            return codes.into_iter().filter(|c| !c.outdate()).collect();
+        }
+        let codes = CONFIG2.read().unwrap().access_codes.clone();
+        let (codes, succ, store) = decrypt_str_or_original(&codes, PASSWORD_ENC_VERSION);
+        if succ {
+            let mut codes: Vec<AccessCode> = serde_json::from_str(&codes).unwrap_or_default();
+            let len = codes.len();
+            codes.retain(|c| !c.outdate());
+            if store || codes.len() != len {
+                Self::set_access_codes(codes.clone());
+            }
+            *ACCESS_CODES.write().unwrap() = (codes.clone(), true);
+            codes
+        } else {
+            Default::default()
+        }
+    }
+
+    fn set_access_codes(mut access_codes: Vec<AccessCode>) {
+        access_codes.retain(|c| !c.outdate());
+        let codes = serde_json::to_string(&access_codes).unwrap_or_default();
+        let max_len = 64 * 1024;
+        if codes.bytes().len() > max_len {
+            log::error!("Access codes too large: {}", codes.bytes().len());
+            return;
+        }
+        let codes = encrypt_str_or_original(&codes, PASSWORD_ENC_VERSION, max_len);
+        let mut config = CONFIG2.write().unwrap();
+        config.access_codes = codes;
+        config.store();
+        *ACCESS_CODES.write().unwrap() = (access_codes, true);
+    }
+
+    pub fn add_access_code(code: AccessCode) {
+        let mut codes = Self::get_access_codes();
+        codes.retain(|c| c.code != code.code);
+        codes.push(code);
+        Self::set_access_codes(codes);
+    }
+
+    // Returns the removed code, an access code can only be used once.
+    pub fn take_access_code(code: &str) -> Option<AccessCode> {
+        let mut codes = Self::get_access_codes();
+        let pos = codes.iter().position(|c| c.code == code)?;
+        let code = codes.remove(pos);
+        Self::set_access_codes(codes);
+        Some(code)
+    }
+
+    pub fn clear_access_codes() {
+        Self::set_access_codes(Default::default());
+    }
+
     pub fn get() -> Config {
         return CONFIG.read().unwrap().clone();
     }
@@ -1499,6 +1579,9 @@ pub struct LocalConfig {
     // Various data for flutter ui
     #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
     ui_flutter: HashMap<String, String>,
+    // used to sign the login challenge when the peer authorizes it by public key
+    #[serde(default, deserialize_with = "deserialize_keypair")]
+    controller_key_pair: KeyPair,
 }
 
 impl LocalConfig {
@@ -1514,6 +1597,16 @@ impl LocalConfig {
         LOCAL_CONFIG.read().unwrap().kb_layout_type.clone()
     }
 
+    pub fn get_controller_key_pair() -> KeyPair {
+        let mut config = LOCAL_CONFIG.write().unwrap();
+        if config.controller_key_pair.0.is_empty() {
+            let (pk, sk) = sign::gen_keypair();
+            config.controller_key_pair = (sk.0.to_vec(), pk.0.into());
+            config.store();
+        }
+        config.controller_key_pair.clone()
+    }
+
     pub fn set_kb_layout_type(kb_layout_type: String) {
         let mut config = LOCAL_CONFIG.write().unwrap();
         config.kb_layout_type = kb_layout_type;
@@ -1646,6 +1739,12 @@ pub struct DiscoveryPeer {
     pub online: bool,
     #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
     pub ip_mac: HashMap<String, String>,
+    // base64 of the public key which signed the pong, empty if it is not signed
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub pk: String,
+    // the key is the one of the id, verified with the rendezvous server on a connection
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub verified: bool,
 }
 
 impl DiscoveryPeer {
@@ -2053,6 +2152,25 @@ impl TrustedDevice {
     }
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AccessCode {
+    pub code: String,
+    // expiry time in milliseconds, see `crate::get_time`
+    pub expire: i64,
+    #[serde(default)]
+    pub view_only: bool,
+    #[serde(default)]
+    pub file_transfer: bool,
+    #[serde(default)]
+    pub clipboard: bool,
+}
+
+impl AccessCode {
+    pub fn outdate(&self) -> bool {
+        self.expire < crate::get_time()
+    }
+}
+
 deserialize_default!(deserialize_string, String);
 deserialize_default!(deserialize_bool, bool);
 deserialize_default!(deserialize_i32, i32);
@@ -2196,6 +2314,12 @@ pub mod keys {
     pub const OPTION_CUSTOM_IMAGE_QUALITY: &str = "custom_image_quality";
     pub const OPTION_CUSTOM_FPS: &str = "custom-fps";
     pub const OPTION_CODEC_PREFERENCE: &str = "codec-preference";
+    // latency target of the client jitter buffer in ms, empty to present frames on arrival
+    pub const OPTION_JITTER_BUFFER_TARGET: &str = "jitter-buffer-target";
+    // bypass the jitter buffer, for games
+    pub const OPTION_LOW_LATENCY_MODE: &str = "low-latency-mode";
+    // "Y" to sign the logins to the peer with the controller key, see `OPTION_AUTHORIZED_KEYS`
+    pub const OPTION_PUBLIC_KEY_AUTH: &str = "public-key-auth";
     pub const OPTION_SYNC_INIT_CLIPBOARD: &str = "sync-init-clipboard";
     pub const OPTION_THEME: &str = "theme";
     pub const OPTION_LANGUAGE: &str = "lang";
@@ -2229,14 +2353,33 @@ pub mod keys {
     pub const OPTION_ALLOW_ONLY_CONN_WINDOW_OPEN: &str = "allow-only-conn-window-open";
     pub const OPTION_ALLOW_AUTO_RECORD_INCOMING: &str = "allow-auto-record-incoming";
     pub const OPTION_ALLOW_AUTO_RECORD_OUTGOING: &str = "allow-auto-record-outgoing";
+    pub const OPTION_ALLOW_QUALITY_STATS_EXPORT: &str = "allow-quality-stats-export";
     pub const OPTION_VIDEO_SAVE_DIRECTORY: &str = "video-save-directory";
+    pub const OPTION_RECORD_MAX_AGE_DAYS: &str = "record-max-age-days";
+    pub const OPTION_RECORD_MAX_SIZE_MB: &str = "record-max-size-mb";
+    pub const OPTION_RECORD_MAX_COUNT: &str = "record-max-count";
+    // the typed text and the clipboard are masked in the event records if not enabled
+    pub const OPTION_ALLOW_RECORD_INPUT_TEXT: &str = "allow-record-input-text";
     pub const OPTION_ENABLE_ABR: &str = "enable-abr";
+    // kbps of all the incoming sessions and of each one, empty or 0 means unlimited
+    pub const OPTION_BANDWIDTH_LIMIT: &str = "bandwidth-limit";
+    pub const OPTION_CONN_BANDWIDTH_LIMIT: &str = "conn-bandwidth-limit";
+    pub const OPTION_ALLOW_CONTROLLER_PRIORITY: &str = "allow-controller-priority";
     pub const OPTION_ALLOW_REMOVE_WALLPAPER: &str = "allow-remove-wallpaper";
     pub const OPTION_ALLOW_ALWAYS_SOFTWARE_RENDER: &str = "allow-always-software-render";
     pub const OPTION_ALLOW_LINUX_HEADLESS: &str = "allow-linux-headless";
     pub const OPTION_ENABLE_HWCODEC: &str = "enable-hwcodec";
     pub const OPTION_APPROVE_MODE: &str = "approve-mode";
     pub const OPTION_VERIFICATION_METHOD: &str = "verification-method";
+    // weekly windows of unattended access, e.g. "mon-fri 08:00-18:00; sat 10:00-12:00"
+    pub const OPTION_ACCESS_SCHEDULE: &str = "access-schedule";
+    // "reject" to reject the logins outside the windows, click-to-approve otherwise
+    pub const OPTION_ACCESS_SCHEDULE_FALLBACK: &str = "access-schedule-fallback";
+    // lines of "[options] ed25519 <base64 public key> [comment]", options are comma separated:
+    // view-only, no-file-transfer, no-clipboard
+    pub const OPTION_AUTHORIZED_KEYS: &str = "authorized-keys";
+    // lines of "ed25519 <base64 public key> [comment]", the security keys of the second factor
+    pub const OPTION_2FA_SECURITY_KEYS: &str = "2fa-security-keys";
     pub const OPTION_CUSTOM_RENDEZVOUS_SERVER: &str = "custom-rendezvous-server";
     pub const OPTION_API_SERVER: &str = "api-server";
     pub const OPTION_KEY: &str = "key";
@@ -2246,6 +2389,7 @@ pub mod keys {
     pub const OPTION_ENABLE_ANDROID_SOFTWARE_ENCODING_HALF_SCALE: &str =
         "enable-android-software-encoding-half-scale";
     pub const OPTION_ENABLE_TRUSTED_DEVICES: &str = "enable-trusted-devices";
+    pub const OPTION_ALLOW_WOL_RELAY: &str = "allow-wol-relay";
     pub const OPTION_AV1_TEST: &str = "av1-test";
 
     // buildin options
@@ -2323,6 +2467,8 @@ pub mod keys {
         OPTION_CUSTOM_IMAGE_QUALITY,
         OPTION_CUSTOM_FPS,
         OPTION_CODEC_PREFERENCE,
+        OPTION_JITTER_BUFFER_TARGET,
+        OPTION_LOW_LATENCY_MODE,
         OPTION_SYNC_INIT_CLIPBOARD,
     ];
     // DEFAULT_LOCAL_SETTINGS, OVERWRITE_LOCAL_SETTINGS
@@ -2355,6 +2501,7 @@ pub mod keys {
         OPTION_PRE_ELEVATE_SERVICE,
         OPTION_ALLOW_REMOTE_CM_MODIFICATION,
         OPTION_ALLOW_AUTO_RECORD_OUTGOING,
+        OPTION_ALLOW_QUALITY_STATS_EXPORT,
         OPTION_VIDEO_SAVE_DIRECTORY,
     ];
     // DEFAULT_SETTINGS, OVERWRITE_SETTINGS
@@ -2377,13 +2524,24 @@ pub mod keys {
         OPTION_AUTO_DISCONNECT_TIMEOUT,
         OPTION_ALLOW_ONLY_CONN_WINDOW_OPEN,
         OPTION_ALLOW_AUTO_RECORD_INCOMING,
+        OPTION_RECORD_MAX_AGE_DAYS,
+        OPTION_RECORD_MAX_SIZE_MB,
+        OPTION_RECORD_MAX_COUNT,
+        OPTION_ALLOW_RECORD_INPUT_TEXT,
         OPTION_ENABLE_ABR,
+        OPTION_BANDWIDTH_LIMIT,
+        OPTION_CONN_BANDWIDTH_LIMIT,
+        OPTION_ALLOW_CONTROLLER_PRIORITY,
         OPTION_ALLOW_REMOVE_WALLPAPER,
         OPTION_ALLOW_ALWAYS_SOFTWARE_RENDER,
         OPTION_ALLOW_LINUX_HEADLESS,
         OPTION_ENABLE_HWCODEC,
         OPTION_APPROVE_MODE,
         OPTION_VERIFICATION_METHOD,
+        OPTION_ACCESS_SCHEDULE,
+        OPTION_ACCESS_SCHEDULE_FALLBACK,
+        OPTION_AUTHORIZED_KEYS,
+        OPTION_2FA_SECURITY_KEYS,
         OPTION_PROXY_URL,
         OPTION_PROXY_USERNAME,
         OPTION_PROXY_PASSWORD,
@@ -2395,6 +2553,7 @@ pub mod keys {
         OPTION_ENABLE_DIRECTX_CAPTURE,
         OPTION_ENABLE_ANDROID_SOFTWARE_ENCODING_HALF_SCALE,
         OPTION_ENABLE_TRUSTED_DEVICES,
+        OPTION_ALLOW_WOL_RELAY,
     ];
 
     // BUILDIN_SETTINGS
@@ -5,7 +5,10 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
-use tokio::{fs::File, io::*};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::*,
+};
 
 use crate::{anyhow::anyhow, bail, get_version_number, message_proto::*, ResultType, Stream};
 // https://doc.rust-lang.org/std/os/windows/fs/trait.MetadataExt.html
@@ -14,6 +17,9 @@ use crate::{
     config::Config,
 };
 
+mod delta;
+use delta::{DeltaOp, DeltaReader};
+
 pub fn read_dir(path: &Path, include_hidden: bool) -> ResultType<FileDirectory> {
     let mut dir = FileDirectory {
         path: get_string(path),
@@ -240,6 +246,11 @@ pub fn can_enable_overwrite_detection(version: i64) -> bool {
     version >= get_version_number("1.1.10")
 }
 
+/// Raw size of one `FileTransferBlock`, resume offsets are counted in these blocks.
+const BLOCK_SIZE: usize = 128 * 1024;
+/// The partial file is flushed and its journal updated every this many blocks.
+const JOURNAL_SYNC_BLOCKS: u32 = 64;
+
 #[derive(Default, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct TransferJob {
@@ -265,6 +276,20 @@ pub struct TransferJob {
     file_skipped: bool,
     file_is_waiting: bool,
     default_overwrite_strategy: Option<bool>,
+    // read side, the block offset confirmed by the peer to seek to
+    resume_blk: u32,
+    // write side, blocks written to the current file
+    written_blk: u32,
+    // write side, the journal of the file (by file_num) which can be resumed later
+    #[serde(skip_serializing)]
+    journal: Option<(i32, TransferJournal)>,
+    // the file (by file_num) whose existing copy can be delta synced, waiting for the overwrite
+    // confirmation before its block signatures are computed
+    #[serde(skip_serializing)]
+    delta_file: Option<i32>,
+    // read side, sending the current file by delta sync
+    #[serde(skip_serializing)]
+    delta: Option<DeltaReader>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -283,6 +308,46 @@ pub struct TransferJobMeta {
     pub is_remote: bool,
 }
 
+/// Persisted next to a partial `.download` file, so the transfer can continue
+/// from the last synced block after reconnecting.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TransferJournal {
+    #[serde(default)]
+    pub last_modified: u64,
+    #[serde(default)]
+    pub file_size: u64,
+    #[serde(default)]
+    pub confirmed_blk: u32,
+}
+
+impl TransferJournal {
+    #[inline]
+    fn path(download_path: &str) -> String {
+        format!("{}.journal", download_path)
+    }
+
+    pub fn load(download_path: &str) -> Option<Self> {
+        let content = std::fs::read_to_string(Self::path(download_path)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn store(&self, download_path: &str) -> ResultType<()> {
+        std::fs::write(Self::path(download_path), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn remove(download_path: &str) {
+        std::fs::remove_file(Self::path(download_path)).ok();
+    }
+
+    /// Whether the partial file was written from the same source file.
+    #[inline]
+    pub fn is_same_source(&self, digest: &FileTransferDigest) -> bool {
+        self.last_modified == digest.last_modified && self.file_size == digest.file_size
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct RemoveJobMeta {
     #[serde(default)]
@@ -403,7 +468,8 @@ impl TransferJob {
             let entry = &self.files[file_num];
             let path = self.join(&entry.name);
             let download_path = format!("{}.download", get_string(&path));
-            std::fs::rename(download_path, &path).ok();
+            std::fs::rename(&download_path, &path).ok();
+            TransferJournal::remove(&download_path);
             filetime::set_file_mtime(
                 &path,
                 filetime::FileTime::from_unix_time(entry.modified_time as _, 0),
@@ -418,7 +484,8 @@ impl TransferJob {
             let entry = &self.files[file_num];
             let path = self.join(&entry.name);
             let download_path = format!("{}.download", get_string(&path));
-            std::fs::remove_file(download_path).ok();
+            std::fs::remove_file(&download_path).ok();
+            TransferJournal::remove(&download_path);
         }
     }
 
@@ -442,7 +509,10 @@ impl TransferJob {
                 std::fs::create_dir_all(p).ok();
             }
             let path = format!("{}.download", get_string(&path));
-            self.file = Some(File::create(&path).await?);
+            self.file = Some(self.open_download(block.file_num, &path).await?);
+        }
+        if block.copy_blks > 0 {
+            return self.copy_blocks(block.blk_id, block.copy_blks).await;
         }
         if block.compressed {
             let tmp = decompress(&block.data);
@@ -461,9 +531,112 @@ impl TransferJob {
             self.finished_size += block.data.len() as u64;
         }
         self.transferred += block.data.len() as u64;
+        self.written_blk += 1;
+        if self.written_blk % JOURNAL_SYNC_BLOCKS == 0 {
+            self.sync_journal().await?;
+        }
+        Ok(())
+    }
+
+    // delta sync, copy the unchanged blocks from the existing file
+    async fn copy_blocks(&mut self, blk_id: u32, count: u32) -> ResultType<()> {
+        let entry = self
+            .files
+            .get(self.file_num as usize)
+            .ok_or(anyhow!("Wrong file number"))?;
+        let mut src = File::open(self.join(&entry.name)).await?;
+        src.seek(SeekFrom::Start(blk_id as u64 * BLOCK_SIZE as u64))
+            .await?;
+        let file = self.file.as_mut().ok_or(anyhow!("file is None"))?;
+        let mut buf = vec![0u8; BLOCK_SIZE];
+        for _ in 0..count {
+            src.read_exact(&mut buf).await?;
+            file.write_all(&buf).await?;
+        }
+        self.finished_size += count as u64 * BLOCK_SIZE as u64;
+        self.written_blk += count;
         Ok(())
     }
 
+    async fn open_download(&mut self, file_num: i32, path: &str) -> ResultType<File> {
+        self.written_blk = 0;
+        match self.journal.as_mut() {
+            Some((n, journal)) if *n == file_num => {
+                if journal.confirmed_blk > 0 {
+                    let mut file = OpenOptions::new().write(true).open(path).await?;
+                    let offset = journal.confirmed_blk as u64 * BLOCK_SIZE as u64;
+                    file.set_len(offset).await?;
+                    file.seek(SeekFrom::End(0)).await?;
+                    log::info!(
+                        "id: {}, file_num: {}, resume from block {}",
+                        self.id,
+                        file_num,
+                        journal.confirmed_blk
+                    );
+                    self.written_blk = journal.confirmed_blk;
+                    self.finished_size += offset;
+                    Ok(file)
+                } else {
+                    let file = File::create(path).await?;
+                    journal.store(path)?;
+                    Ok(file)
+                }
+            }
+            _ => {
+                self.journal = None;
+                Ok(File::create(path).await?)
+            }
+        }
+    }
+
+    async fn sync_journal(&mut self) -> ResultType<()> {
+        let file_num = self.file_num;
+        let path = match self.files.get(file_num as usize) {
+            Some(entry) => format!("{}.download", get_string(&self.join(&entry.name))),
+            None => return Ok(()),
+        };
+        if let Some((n, journal)) = self.journal.as_mut() {
+            if *n != file_num {
+                return Ok(());
+            }
+            if let Some(file) = self.file.as_mut() {
+                file.sync_data().await?;
+            }
+            journal.confirmed_blk = self.written_blk;
+            journal.store(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Write side, check whether the partial download of the file in `digest` can be
+    /// continued. Returns the block offset to confirm to the peer, `0` to start over.
+    pub fn prepare_resume(&mut self, digest: &FileTransferDigest) -> u32 {
+        self.journal = None;
+        if !digest.can_resume {
+            return 0;
+        }
+        let download_path = match self.files.get(digest.file_num as usize) {
+            Some(entry) => format!("{}.download", get_string(&self.join(&entry.name))),
+            None => return 0,
+        };
+        let mut journal = TransferJournal {
+            last_modified: digest.last_modified,
+            file_size: digest.file_size,
+            confirmed_blk: 0,
+        };
+        if let Some(last) = TransferJournal::load(&download_path) {
+            if last.is_same_source(digest) {
+                let len = std::fs::metadata(&download_path)
+                    .map(|m| m.len())
+                    .unwrap_or(0);
+                journal.confirmed_blk = last.confirmed_blk.min((len / BLOCK_SIZE as u64) as u32);
+            }
+        }
+        let offset = journal.confirmed_blk;
+        self.journal = Some((digest.file_num, journal));
+        offset
+    }
+
     #[inline]
     pub fn join(&self, name: &str) -> PathBuf {
         if name.is_empty() {
@@ -502,8 +675,20 @@ impl TransferJob {
             }
             return Ok(None);
         }
-        const BUF_SIZE: usize = 128 * 1024;
-        let mut buf: Vec<u8> = vec![0; BUF_SIZE];
+        if self.resume_blk > 0 {
+            let offset = self.resume_blk as u64 * BLOCK_SIZE as u64;
+            self.file
+                .as_mut()
+                .ok_or(anyhow!("file is None"))?
+                .seek(SeekFrom::Start(offset))
+                .await?;
+            self.finished_size += offset;
+            self.resume_blk = 0;
+        }
+        if self.delta.is_some() {
+            return self.read_delta(file_num).await;
+        }
+        let mut buf: Vec<u8> = vec![0; BLOCK_SIZE];
         let mut compressed = false;
         let mut offset: usize = 0;
         loop {
@@ -523,7 +708,7 @@ impl TransferJob {
                 }
                 Ok(n) => {
                     offset += n;
-                    if n == 0 || offset == BUF_SIZE {
+                    if n == 0 || offset == BLOCK_SIZE {
                         break;
                     }
                 }
@@ -555,6 +740,53 @@ impl TransferJob {
         }))
     }
 
+    async fn read_delta(&mut self, file_num: usize) -> ResultType<Option<FileTransferBlock>> {
+        let op = match (self.delta.as_mut(), self.file.as_mut()) {
+            (Some(delta), Some(file)) => delta.next(file).await,
+            _ => Ok(None),
+        };
+        let mut block = FileTransferBlock {
+            id: self.id,
+            file_num: file_num as _,
+            ..Default::default()
+        };
+        match op {
+            Err(err) => {
+                self.file_num += 1;
+                self.file = None;
+                self.delta = None;
+                self.file_confirmed = false;
+                self.file_is_waiting = false;
+                return Err(err);
+            }
+            Ok(None) => {
+                self.file_num += 1;
+                self.file = None;
+                self.delta = None;
+                self.file_confirmed = false;
+                self.file_is_waiting = false;
+            }
+            Ok(Some(DeltaOp::Copy { blk, count })) => {
+                block.blk_id = blk;
+                block.copy_blks = count;
+                self.finished_size += count as u64 * BLOCK_SIZE as u64;
+            }
+            Ok(Some(DeltaOp::Literal(mut buf))) => {
+                self.finished_size += buf.len() as u64;
+                if !is_compressed_file(&self.files[file_num].name) {
+                    let tmp = compress(&buf);
+                    if tmp.len() < buf.len() {
+                        buf = tmp;
+                        block.compressed = true;
+                    }
+                }
+                self.transferred += buf.len() as u64;
+                block.data = buf.into();
+            }
+        }
+        Ok(Some(block))
+    }
+
     async fn send_current_digest(&mut self, stream: &mut Stream) -> ResultType<()> {
         let mut msg = Message::new();
         let mut resp = FileResponse::new();
@@ -573,6 +805,8 @@ impl TransferJob {
             file_num: self.file_num,
             last_modified,
             file_size: meta.len(),
+            can_resume: true,
+            can_delta: true,
             ..Default::default()
         });
         msg.set_file_response(resp);
@@ -586,6 +820,15 @@ impl TransferJob {
         Ok(())
     }
 
+    /// Remember that the existing file of `file_num` can be delta synced once the overwrite is confirmed.
+    pub fn set_delta_file(&mut self, file_num: i32, can_delta: bool) {
+        self.delta_file = if can_delta { Some(file_num) } else { None };
+    }
+
+    pub fn take_delta_file(&mut self, file_num: i32) -> bool {
+        self.delta_file.take() == Some(file_num)
+    }
+
     pub fn set_overwrite_strategy(&mut self, overwrite_strategy: Option<bool>) {
         self.default_overwrite_strategy = overwrite_strategy;
     }
@@ -661,6 +904,7 @@ impl TransferJob {
         if self.file_num() != r.file_num {
             log::info!("file num truncated, ignoring");
         } else {
+            self.delta = None;
             match r.union {
                 Some(file_transfer_send_confirm_request::Union::Skip(s)) => {
                     if s {
@@ -669,8 +913,12 @@ impl TransferJob {
                         self.set_file_confirmed(true);
                     }
                 }
-                Some(file_transfer_send_confirm_request::Union::OffsetBlk(_offset)) => {
+                Some(file_transfer_send_confirm_request::Union::OffsetBlk(offset)) => {
                     self.set_file_confirmed(true);
+                    self.resume_blk = offset;
+                    if offset == 0 {
+                        self.delta = r.signatures.as_ref().and_then(DeltaReader::new);
+                    }
                 }
                 _ => {}
             }
@@ -737,6 +985,18 @@ pub fn new_send_confirm(r: FileTransferSendConfirmRequest) -> Message {
     msg_out
 }
 
+/// Read side, asks the write side for the block signatures of the existing file.
+#[inline]
+pub fn new_request_signatures(id: i32, file_num: i32) -> Message {
+    new_send_confirm(FileTransferSendConfirmRequest {
+        id,
+        file_num,
+        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+        request_signatures: true,
+        ..Default::default()
+    })
+}
+
 #[inline]
 pub fn new_receive(
     id: i32,
@@ -898,6 +1158,18 @@ pub fn transform_windows_path(entries: &mut Vec<FileEntry>) {
     }
 }
 
+/// Write side, the block signatures of the existing file for delta sync.
+pub async fn get_block_signatures(path: String) -> Option<FileTransferBlockSignatures> {
+    match tokio::task::spawn_blocking(move || delta::get_signatures(Path::new(&path))).await {
+        Ok(Ok(signatures)) => signatures,
+        Ok(Err(err)) => {
+            log::warn!("Failed to get block signatures: {}", err);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
 pub enum DigestCheckResult {
     IsSame,
     NeedConfirm(FileTransferDigest),
@@ -951,3 +1223,157 @@ pub fn serialize_transfer_job(job: &TransferJob, done: bool, cancel: bool, error
     value["error"] = json!(error);
     serde_json::to_string(&value).unwrap_or_default()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_resume() {
+        let dir = std::env::temp_dir().join(format!("hbb_fs_resume_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = vec![FileEntry {
+            name: "a.bin".to_owned(),
+            size: 10 * BLOCK_SIZE as u64,
+            ..Default::default()
+        }];
+        let mut job = TransferJob::new_write(
+            0,
+            "".to_owned(),
+            get_string(&dir),
+            0,
+            false,
+            false,
+            files,
+            true,
+        );
+        let download_path = format!("{}.download", get_string(&dir.join("a.bin")));
+        let digest = FileTransferDigest {
+            last_modified: 100,
+            file_size: 10 * BLOCK_SIZE as u64,
+            can_resume: true,
+            ..Default::default()
+        };
+        std::fs::write(&download_path, vec![0u8; 4 * BLOCK_SIZE + 10]).unwrap();
+
+        // round trip
+        let journal = TransferJournal {
+            last_modified: 100,
+            file_size: 10 * BLOCK_SIZE as u64,
+            confirmed_blk: 3,
+        };
+        journal.store(&download_path).unwrap();
+        assert_eq!(TransferJournal::load(&download_path), Some(journal.clone()));
+        assert_eq!(job.prepare_resume(&digest), 3);
+
+        // the partial file is shorter than journaled
+        TransferJournal {
+            confirmed_blk: 8,
+            ..journal.clone()
+        }
+        .store(&download_path)
+        .unwrap();
+        assert_eq!(job.prepare_resume(&digest), 4);
+
+        // the source changed since the journal was written
+        journal.store(&download_path).unwrap();
+        let changed = FileTransferDigest {
+            last_modified: 101,
+            ..digest.clone()
+        };
+        assert_eq!(job.prepare_resume(&changed), 0);
+        let changed = FileTransferDigest {
+            file_size: 11 * BLOCK_SIZE as u64,
+            ..digest.clone()
+        };
+        assert_eq!(job.prepare_resume(&changed), 0);
+
+        // the peer can not seek
+        let no_resume = FileTransferDigest {
+            can_resume: false,
+            ..digest.clone()
+        };
+        assert_eq!(job.prepare_resume(&no_resume), 0);
+
+        // truncated or corrupt journal
+        let content = serde_json::to_string(&journal).unwrap();
+        std::fs::write(
+            TransferJournal::path(&download_path),
+            &content[..content.len() / 2],
+        )
+        .unwrap();
+        assert_eq!(TransferJournal::load(&download_path), None);
+        assert_eq!(job.prepare_resume(&digest), 0);
+        std::fs::write(TransferJournal::path(&download_path), "\0\0\0").unwrap();
+        assert_eq!(job.prepare_resume(&digest), 0);
+
+        // no journal
+        TransferJournal::remove(&download_path);
+        assert_eq!(job.prepare_resume(&digest), 0);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_delta_sync() {
+        let dir = std::env::temp_dir().join(format!("hbb_fs_delta_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let old: Vec<u8> = (0..8 * BLOCK_SIZE as u32)
+            .map(|x| (x.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        // bytes inserted at the front, one block changed, a tail appended
+        let mut new = vec![1u8; 100];
+        new.extend_from_slice(&old);
+        new[100 + 4 * BLOCK_SIZE + 10] ^= 0xff;
+        new.extend_from_slice(&[2u8; 1000]);
+        let existing = dir.join("a.bin");
+        let source = dir.join("b.bin");
+        std::fs::write(&existing, &old).unwrap();
+        std::fs::write(&source, &new).unwrap();
+
+        let signatures = delta::get_signatures(&existing).unwrap().unwrap();
+        assert_eq!(signatures.weak.len(), 8);
+        let mut reader = DeltaReader::new(&signatures).unwrap();
+        let files = vec![FileEntry {
+            name: "a.bin".to_owned(),
+            size: new.len() as _,
+            ..Default::default()
+        }];
+        let mut job = TransferJob::new_write(
+            0,
+            "".to_owned(),
+            get_string(&dir),
+            0,
+            false,
+            false,
+            files,
+            true,
+        );
+        let mut src = File::open(&source).await.unwrap();
+        let mut copied = 0;
+        while let Some(op) = reader.next(&mut src).await.unwrap() {
+            let mut block = FileTransferBlock::new();
+            match op {
+                DeltaOp::Copy { blk, count } => {
+                    block.blk_id = blk;
+                    block.copy_blks = count;
+                    copied += count;
+                }
+                DeltaOp::Literal(data) => block.data = data.into(),
+            }
+            job.write(block).await.unwrap();
+        }
+        job.file.take().unwrap().sync_all().await.unwrap();
+        // all but the changed block are copied from the existing file
+        assert_eq!(copied, 7);
+        let written = std::fs::read(format!("{}.download", get_string(&existing))).unwrap();
+        assert!(written == new);
+
+        // too small for delta sync
+        std::fs::write(&existing, &old[..delta::DELTA_MIN_SIZE as usize - 1]).unwrap();
+        assert!(delta::get_signatures(&existing).unwrap().is_none());
+        assert!(DeltaReader::new(&FileTransferBlockSignatures::new()).is_none());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,232 @@
+// Block-level delta sync, like rsync.
+// The receiver sends the checksums of the full blocks of its existing file, the sender
+// rolls a weak checksum over its own file byte by byte, and replaces the windows which
+// match a block of the existing file with copy instructions.
+
+use std::{collections::HashMap, path::Path};
+
+use tokio::{fs::File, io::*};
+
+use super::BLOCK_SIZE;
+use crate::{message_proto::FileTransferBlockSignatures, ResultType};
+
+/// Files smaller than this are always sent as a whole.
+pub const DELTA_MIN_SIZE: u64 = 4 * BLOCK_SIZE as u64;
+/// Limit of blocks copied by one `FileTransferBlock`, so progress is still reported.
+const MAX_COPY_BLKS: u32 = 64;
+const STRONG_LEN: usize = 16;
+
+// adler-32 like checksum, which can be rolled forward by one byte
+#[derive(Debug, Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(data: &[u8]) -> Self {
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        for (i, x) in data.iter().enumerate() {
+            a = a.wrapping_add(*x as u32);
+            b = b.wrapping_add((data.len() - i) as u32 * *x as u32);
+        }
+        Self {
+            a: a & 0xffff,
+            b: b & 0xffff,
+            len: data.len() as _,
+        }
+    }
+
+    #[inline]
+    fn roll(&mut self, out: u8, input: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(input as u32) & 0xffff;
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a)
+            & 0xffff;
+    }
+
+    #[inline]
+    fn digest(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+#[inline]
+fn strong(data: &[u8]) -> Vec<u8> {
+    sodiumoxide::crypto::hash::sha256::hash(data).0[..STRONG_LEN].to_vec()
+}
+
+/// Checksums of the full blocks of `path`, `None` if the file is too small for delta sync.
+pub fn get_signatures(path: &Path) -> ResultType<Option<FileTransferBlockSignatures>> {
+    let mut file = std::fs::File::open(path)?;
+    if file.metadata()?.len() < DELTA_MIN_SIZE {
+        return Ok(None);
+    }
+    let mut signatures = FileTransferBlockSignatures::new();
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    loop {
+        let mut offset = 0;
+        while offset < BLOCK_SIZE {
+            let n = std::io::Read::read(&mut file, &mut buf[offset..])?;
+            if n == 0 {
+                break;
+            }
+            offset += n;
+        }
+        if offset < BLOCK_SIZE {
+            break;
+        }
+        signatures.weak.push(Rolling::new(&buf).digest());
+        signatures.strong.push(strong(&buf).into());
+    }
+    Ok(Some(signatures))
+}
+
+pub enum DeltaOp {
+    Copy { blk: u32, count: u32 },
+    Literal(Vec<u8>),
+}
+
+/// Read side of the delta sync, turns the file into a sequence of [`DeltaOp`].
+#[derive(Debug)]
+pub struct DeltaReader {
+    weak: HashMap<u32, Vec<u32>>,
+    strong: Vec<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+    rolling: Option<Rolling>,
+}
+
+impl DeltaReader {
+    pub fn new(signatures: &FileTransferBlockSignatures) -> Option<Self> {
+        if signatures.weak.is_empty() || signatures.weak.len() != signatures.strong.len() {
+            return None;
+        }
+        let mut weak: HashMap<u32, Vec<u32>> = HashMap::new();
+        for (i, w) in signatures.weak.iter().enumerate() {
+            weak.entry(*w).or_default().push(i as _);
+        }
+        Some(Self {
+            weak,
+            strong: signatures.strong.iter().map(|x| x.to_vec()).collect(),
+            buf: Vec::new(),
+            pos: 0,
+            eof: false,
+            rolling: None,
+        })
+    }
+
+    // keep at least one block and the byte after it in the buffer
+    async fn fill(&mut self, file: &mut File) -> ResultType<()> {
+        if self.pos >= 4 * BLOCK_SIZE {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        while !self.eof && self.buf.len() - self.pos <= BLOCK_SIZE {
+            let len = self.buf.len();
+            self.buf.resize(len + BLOCK_SIZE, 0);
+            let n = file.read(&mut self.buf[len..]).await?;
+            self.buf.truncate(len + n);
+            if n == 0 {
+                self.eof = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn find(&self, weak: u32, window: &[u8]) -> Option<u32> {
+        let blks = self.weak.get(&weak)?;
+        let s = strong(window);
+        blks.iter()
+            .find(|blk| self.strong[**blk as usize] == s)
+            .cloned()
+    }
+
+    /// The next operation, `None` when the whole file is read.
+    pub async fn next(&mut self, file: &mut File) -> ResultType<Option<DeltaOp>> {
+        let mut literal = Vec::new();
+        loop {
+            self.fill(file).await?;
+            let avail = self.buf.len() - self.pos;
+            if avail < BLOCK_SIZE {
+                // the tail is shorter than a block, it can not match
+                if avail == 0 {
+                    return Ok(if literal.is_empty() {
+                        None
+                    } else {
+                        Some(DeltaOp::Literal(literal))
+                    });
+                }
+                let n = avail.min(BLOCK_SIZE - literal.len());
+                literal.extend_from_slice(&self.buf[self.pos..self.pos + n]);
+                self.pos += n;
+                self.rolling = None;
+                if literal.len() == BLOCK_SIZE {
+                    return Ok(Some(DeltaOp::Literal(literal)));
+                }
+                continue;
+            }
+            let window = &self.buf[self.pos..self.pos + BLOCK_SIZE];
+            let rolling = self.rolling.unwrap_or_else(|| Rolling::new(window));
+            if let Some(blk) = self.find(rolling.digest(), window) {
+                if !literal.is_empty() {
+                    self.rolling = Some(rolling);
+                    return Ok(Some(DeltaOp::Literal(literal)));
+                }
+                self.pos += BLOCK_SIZE;
+                self.rolling = None;
+                let mut count = 1;
+                // the following blocks usually stay in place
+                while count < MAX_COPY_BLKS && ((blk + count) as usize) < self.strong.len() {
+                    self.fill(file).await?;
+                    if self.buf.len() - self.pos < BLOCK_SIZE
+                        || strong(&self.buf[self.pos..self.pos + BLOCK_SIZE])
+                            != self.strong[(blk + count) as usize]
+                    {
+                        break;
+                    }
+                    self.pos += BLOCK_SIZE;
+                    count += 1;
+                }
+                return Ok(Some(DeltaOp::Copy { blk, count }));
+            }
+            let out = self.buf[self.pos];
+            literal.push(out);
+            if let Some(input) = self.buf.get(self.pos + BLOCK_SIZE) {
+                let mut rolling = rolling;
+                rolling.roll(out, *input);
+                self.rolling = Some(rolling);
+            } else {
+                self.rolling = None;
+            }
+            self.pos += 1;
+            if literal.len() == BLOCK_SIZE {
+                return Ok(Some(DeltaOp::Literal(literal)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolling() {
+        let data: Vec<u8> = (0..1000u32).map(|x| (x * 7 % 251) as u8).collect();
+        let n = 100;
+        let mut rolling = Rolling::new(&data[..n]);
+        for i in 0..data.len() - n {
+            rolling.roll(data[i], data[i + n]);
+            assert_eq!(
+                rolling.digest(),
+                Rolling::new(&data[i + 1..i + 1 + n]).digest()
+            );
+        }
+    }
+}
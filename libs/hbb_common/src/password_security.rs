@@ -1,5 +1,6 @@
-use crate::config::Config;
-use sodiumoxide::base64;
+use crate::config::{keys, AccessCode, Config};
+use chrono::{Datelike, Local, Timelike};
+use sodiumoxide::{base64, crypto::sign};
 use std::sync::{Arc, RwLock};
 
 lazy_static::lazy_static! {
@@ -20,6 +21,15 @@ pub enum ApproveMode {
     Click,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleFallback {
+    Click,
+    Reject,
+}
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const ACCESS_CODE_LENGTH: usize = 8;
+
 // Should only be called in server
 pub fn update_temporary_password() {
     *TEMPORARY_PASSWORD.write().unwrap() = Config::get_auto_password(temporary_password_length());
@@ -63,6 +73,104 @@ pub fn permanent_enabled() -> bool {
 pub fn has_valid_password() -> bool {
     temporary_enabled() && !temporary_password().is_empty()
         || permanent_enabled() && !Config::get_permanent_password().is_empty()
+        || !Config::get_access_codes().is_empty()
+}
+
+// A one-time code, it is removed on the first successful login or after `minutes`.
+pub fn new_access_code(
+    minutes: u32,
+    view_only: bool,
+    file_transfer: bool,
+    clipboard: bool,
+) -> AccessCode {
+    AccessCode {
+        code: Config::get_auto_password(ACCESS_CODE_LENGTH),
+        expire: crate::get_time() + minutes.max(1) as i64 * 60 * 1000,
+        view_only,
+        file_transfer,
+        clipboard,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizedKey {
+    pub pk: Vec<u8>,
+    pub comment: String,
+    pub view_only: bool,
+    pub file_transfer: bool,
+    pub clipboard: bool,
+}
+
+pub fn authorized_keys() -> Vec<AuthorizedKey> {
+    parse_authorized_keys(&Config::get_option(keys::OPTION_AUTHORIZED_KEYS))
+}
+
+// Invalid lines are skipped, so that one bad key does not lock out the others.
+fn parse_authorized_keys(s: &str) -> Vec<AuthorizedKey> {
+    s.lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let mut tokens = line.split_whitespace();
+            let mut options = "";
+            let mut token = tokens.next()?;
+            if token != "ed25519" {
+                options = token;
+                token = tokens.next()?;
+            }
+            if token != "ed25519" {
+                log::warn!("Invalid authorized key: {}", line);
+                return None;
+            }
+            let pk = base64::decode(tokens.next()?, base64::Variant::Original).ok()?;
+            if pk.len() != sign::PUBLICKEYBYTES {
+                log::warn!("Invalid authorized key: {}", line);
+                return None;
+            }
+            let mut key = AuthorizedKey {
+                pk,
+                comment: tokens.collect::<Vec<_>>().join(" "),
+                view_only: false,
+                file_transfer: true,
+                clipboard: true,
+            };
+            for option in options.split(',').filter(|o| !o.is_empty()) {
+                match option {
+                    "view-only" => key.view_only = true,
+                    "no-file-transfer" => key.file_transfer = false,
+                    "no-clipboard" => key.clipboard = false,
+                    _ => {
+                        log::warn!("Unknown option of authorized key: {}", option);
+                        return None;
+                    }
+                }
+            }
+            Some(key)
+        })
+        .collect()
+}
+
+// The signature covers the id of the peer too, so that it can not be relayed to another peer.
+fn login_challenge_data(challenge: &str, peer_id: &str) -> Vec<u8> {
+    [challenge.as_bytes(), b"\0", peer_id.as_bytes()].concat()
+}
+
+pub fn sign_login_challenge(sk: &[u8], challenge: &str, peer_id: &str) -> Option<Vec<u8>> {
+    let sk = sign::SecretKey::from_slice(sk)?;
+    let data = login_challenge_data(challenge, peer_id);
+    Some(sign::sign_detached(&data, &sk).to_bytes().to_vec())
+}
+
+pub fn verify_login_challenge(pk: &[u8], signature: &[u8], challenge: &str, peer_id: &str) -> bool {
+    let Some(pk) = sign::PublicKey::from_slice(pk) else {
+        return false;
+    };
+    let Ok(signature) = sign::Signature::try_from(signature) else {
+        return false;
+    };
+    sign::verify_detached(&signature, &login_challenge_data(challenge, peer_id), &pk)
 }
 
 pub fn approve_mode() -> ApproveMode {
@@ -82,6 +190,92 @@ pub fn hide_cm() -> bool {
         && crate::config::option2bool("allow-hide-cm", &Config::get_option("allow-hide-cm"))
 }
 
+// Whether password logins are allowed now by the access schedule.
+pub fn in_access_schedule() -> bool {
+    let schedule = Config::get_option(keys::OPTION_ACCESS_SCHEDULE);
+    if schedule.trim().is_empty() {
+        return true;
+    }
+    let now = Local::now();
+    let minute = now.hour() * 60 + now.minute();
+    match schedule_allows(&schedule, now.weekday().num_days_from_monday(), minute) {
+        Some(allowed) => allowed,
+        None => {
+            log::error!("Invalid access schedule: {}", schedule);
+            false
+        }
+    }
+}
+
+pub fn schedule_fallback() -> ScheduleFallback {
+    if Config::get_option(keys::OPTION_ACCESS_SCHEDULE_FALLBACK) == "reject" {
+        ScheduleFallback::Reject
+    } else {
+        ScheduleFallback::Click
+    }
+}
+
+// Whether the `schedule` contains the minute of the day of the weekday, 0 for monday.
+// The windows are separated by ';', like "mon-fri 08:00-18:00; sat,sun 22:00-02:00", a window
+// ending before it starts ends on the next day.
+// Returns `None` if the schedule is invalid.
+fn schedule_allows(schedule: &str, weekday: u32, minute: u32) -> Option<bool> {
+    let mut allowed = false;
+    for window in schedule
+        .split(';')
+        .map(|w| w.trim())
+        .filter(|w| !w.is_empty())
+    {
+        let (days, times) = window.split_once(' ')?;
+        let days = parse_weekdays(days)?;
+        let (start, end) = times.trim().split_once('-')?;
+        let (start, end) = (parse_minute(start)?, parse_minute(end)?);
+        let yesterday = (weekday + 6) % 7;
+        allowed |= if start < end {
+            days[weekday as usize] && start <= minute && minute < end
+        } else {
+            (days[weekday as usize] && minute >= start)
+                || (days[yesterday as usize] && minute < end)
+        };
+    }
+    Some(allowed)
+}
+
+// "mon-fri,sun"
+fn parse_weekdays(s: &str) -> Option<[bool; 7]> {
+    let index = |d: &str| {
+        WEEKDAYS
+            .iter()
+            .position(|w| w.eq_ignore_ascii_case(d.trim()))
+    };
+    let mut days = [false; 7];
+    for part in s.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (index(first)?, index(last)?),
+            None => (index(part)?, index(part)?),
+        };
+        let mut d = first;
+        loop {
+            days[d] = true;
+            if d == last {
+                break;
+            }
+            d = (d + 1) % 7;
+        }
+    }
+    Some(days)
+}
+
+// "08:30", "24:00" is the end of the day
+fn parse_minute(s: &str) -> Option<u32> {
+    let (h, m) = s.trim().split_once(':')?;
+    let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+    if m >= 60 || h * 60 + m > 24 * 60 {
+        return None;
+    }
+    Some(h * 60 + m)
+}
+
 const VERSION_LEN: usize = 2;
 
 pub fn encrypt_str_or_original(s: &str, version: &str, max_len: usize) -> String {
@@ -189,6 +383,80 @@ pub fn symmetric_crypt(data: &[u8], encrypt: bool) -> Result<Vec<u8>, ()> {
 
 mod test {
 
+    #[test]
+    fn test_access_schedule() {
+        use super::*;
+
+        let at = |h: u32, m: u32| h * 60 + m;
+        let schedule = "mon-fri 08:00-18:00; sat,sun 22:00-02:00";
+        assert_eq!(schedule_allows(schedule, 0, at(8, 0)), Some(true));
+        assert_eq!(schedule_allows(schedule, 4, at(17, 59)), Some(true));
+        assert_eq!(schedule_allows(schedule, 4, at(18, 0)), Some(false));
+        assert_eq!(schedule_allows(schedule, 5, at(12, 0)), Some(false));
+        assert_eq!(schedule_allows(schedule, 5, at(23, 0)), Some(true));
+        // the window of sunday night ends on monday
+        assert_eq!(schedule_allows(schedule, 0, at(1, 0)), Some(true));
+        assert_eq!(schedule_allows(schedule, 5, at(1, 0)), Some(false));
+        assert_eq!(
+            schedule_allows("Fri-Mon 00:00-24:00", 6, at(12, 0)),
+            Some(true)
+        );
+        assert_eq!(
+            schedule_allows("fri-mon 00:00-24:00", 2, at(12, 0)),
+            Some(false)
+        );
+        assert_eq!(schedule_allows("mon 8:00-18:00", 1, at(12, 0)), Some(false));
+        assert_eq!(schedule_allows("mon 08:00", 0, at(12, 0)), None);
+        assert_eq!(schedule_allows("monday 08:00-18:00", 0, at(12, 0)), None);
+        assert_eq!(schedule_allows("mon 08:00-25:00", 0, at(12, 0)), None);
+    }
+
+    #[test]
+    fn test_authorized_keys() {
+        use super::*;
+
+        let (pk, sk) = sign::gen_keypair();
+        let pk_b64 = base64::encode(pk.0, base64::Variant::Original);
+        let s = format!(
+            "# admins\ned25519 {pk_b64} admin laptop\n\nview-only,no-clipboard ed25519 {pk_b64}\n\
+             rsa {pk_b64}\nno-shell ed25519 {pk_b64}\ned25519 AAAA"
+        );
+        let keys = parse_authorized_keys(&s);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].pk, pk.0.to_vec());
+        assert_eq!(keys[0].comment, "admin laptop");
+        assert!(!keys[0].view_only && keys[0].file_transfer && keys[0].clipboard);
+        assert!(keys[1].view_only && keys[1].file_transfer && !keys[1].clipboard);
+        assert!(keys[1].comment.is_empty());
+
+        let signature = sign_login_challenge(&sk.0, "challenge", "123456789").unwrap();
+        let verify = |signature: &[u8], challenge, id| {
+            verify_login_challenge(&pk.0, signature, challenge, id)
+        };
+        assert!(verify(&signature, "challenge", "123456789"));
+        assert!(!verify(&signature, "challenge", "987654321"));
+        assert!(!verify(&signature, "other", "123456789"));
+        assert!(!verify(&[], "challenge", "123456789"));
+    }
+
+    #[test]
+    fn test_access_code() {
+        use super::*;
+
+        let code = new_access_code(0, true, false, true);
+        assert_eq!(code.code.len(), ACCESS_CODE_LENGTH);
+        assert!(!code.outdate());
+        assert!(code.view_only && !code.file_transfer && code.clipboard);
+        assert!(code.expire - crate::get_time() <= 60 * 1000);
+        let code = new_access_code(30, false, true, false);
+        assert!(code.expire - crate::get_time() > 29 * 60 * 1000);
+        let outdated = AccessCode {
+            expire: crate::get_time() - 1,
+            ..code
+        };
+        assert!(outdated.outdate());
+    }
+
     #[test]
     fn test() {
         use super::*;
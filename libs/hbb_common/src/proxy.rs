@@ -12,7 +12,7 @@ use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufStream};
 use tokio_native_tls::{native_tls, TlsConnector, TlsStream};
 #[cfg(not(any(target_os = "windows", target_os = "macos")))]
 use tokio_rustls::{client::TlsStream, TlsConnector};
-use tokio_socks::{tcp::Socks5Stream, IntoTargetAddr};
+use tokio_socks::{tcp::Socks5Stream, IntoTargetAddr, TargetAddr};
 use tokio_util::codec::Framed;
 use url::Url;
 
@@ -45,6 +45,8 @@ pub enum ProxyError {
     HttpCode200(u16),
     #[error("The proxy address resolution failed: {0}")]
     AddressResolutionFailed(String),
+    #[error("SOCKS5 error: {0}")]
+    Socks5Error(String),
     #[cfg(any(target_os = "windows", target_os = "macos"))]
     #[error("The native tls error: {0}")]
     NativeTlsError(#[from] tokio_native_tls::native_tls::Error),
@@ -55,6 +57,18 @@ const MAXIMUM_RESPONSE_HEADER_LENGTH: usize = 4096;
 const MAXIMUM_RESPONSE_HEADERS: usize = 16;
 const DEFINE_TIME_OUT: u64 = 600;
 
+const SOCKS5_VERSION: u8 = 0x05;
+const SOCKS5_NO_AUTH: u8 = 0x00;
+const SOCKS5_NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const SOCKS5_CMD_CONNECT: u8 = 0x01;
+const SOCKS5_ATYP_IPV4: u8 = 0x01;
+const SOCKS5_ATYP_DOMAIN: u8 = 0x03;
+const SOCKS5_ATYP_IPV6: u8 = 0x04;
+pub const SOCKS5_REPLY_SUCCEEDED: u8 = 0x00;
+pub const SOCKS5_REPLY_GENERAL_FAILURE: u8 = 0x01;
+const SOCKS5_REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const SOCKS5_REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
 pub trait IntoUrl {
     
     // Besides parsing as a valid `Url`, the `Url` must be a valid
@@ -508,11 +522,93 @@ fn get_domain_and_port<'a, T: IntoTargetAddr<'a>>(target: T) -> Result<(String,
         .into_target_addr()
         .map_err(|e| ProxyError::TargetParseError(e.to_string()))?;
     match target_addr {
-        tokio_socks::TargetAddr::Ip(addr) => Ok((addr.ip().to_string(), addr.port())),
-        tokio_socks::TargetAddr::Domain(name, port) => Ok((name.to_string(), port)),
+        TargetAddr::Ip(addr) => Ok((addr.ip().to_string(), addr.port())),
+        TargetAddr::Domain(name, port) => Ok((name.to_string(), port)),
     }
 }
 
+/// Server side of the SOCKS5 handshake, used by dynamic port forwarding.
+/// Only no authentication and `CONNECT` are supported. The target is returned, and
+/// [`socks5_reply`] must be called once it is known whether the target is reachable.
+pub async fn socks5_accept<IO>(io: &mut IO) -> Result<(String, u16), ProxyError>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+    let mut head = [0u8; 2];
+    io.read_exact(&mut head).await?;
+    if head[0] != SOCKS5_VERSION {
+        return Err(ProxyError::Socks5Error(format!(
+            "Unsupported version {}",
+            head[0]
+        )));
+    }
+    let mut methods = vec![0u8; head[1] as usize];
+    io.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS5_NO_AUTH) {
+        io.write_all(&[SOCKS5_VERSION, SOCKS5_NO_ACCEPTABLE_METHODS])
+            .await?;
+        return Err(ProxyError::Socks5Error(
+            "No acceptable authentication method".to_owned(),
+        ));
+    }
+    io.write_all(&[SOCKS5_VERSION, SOCKS5_NO_AUTH]).await?;
+
+    // VER CMD RSV ATYP
+    let mut request = [0u8; 4];
+    io.read_exact(&mut request).await?;
+    if request[0] != SOCKS5_VERSION {
+        return Err(ProxyError::Socks5Error(format!(
+            "Unsupported version {}",
+            request[0]
+        )));
+    }
+    if request[1] != SOCKS5_CMD_CONNECT {
+        socks5_reply(io, SOCKS5_REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(ProxyError::Socks5Error(format!(
+            "Unsupported command {}",
+            request[1]
+        )));
+    }
+    let target = match request[3] {
+        SOCKS5_ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            io.read_exact(&mut ip).await?;
+            TargetAddr::Ip(SocketAddr::from((ip, io.read_u16().await?)))
+        }
+        SOCKS5_ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            io.read_exact(&mut ip).await?;
+            TargetAddr::Ip(SocketAddr::from((ip, io.read_u16().await?)))
+        }
+        SOCKS5_ATYP_DOMAIN => {
+            let mut domain = vec![0u8; io.read_u8().await? as usize];
+            io.read_exact(&mut domain).await?;
+            let domain = String::from_utf8(domain)
+                .map_err(|e| ProxyError::TargetParseError(e.to_string()))?;
+            TargetAddr::Domain(domain.into(), io.read_u16().await?)
+        }
+        atyp => {
+            socks5_reply(io, SOCKS5_REPLY_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Err(ProxyError::Socks5Error(format!(
+                "Unsupported address type {}",
+                atyp
+            )));
+        }
+    };
+    get_domain_and_port(target)
+}
+
+pub async fn socks5_reply<IO>(io: &mut IO, reply: u8) -> Result<(), ProxyError>
+where
+    IO: AsyncWrite + Unpin,
+{
+    // the address bound on the peer side is unknown here
+    io.write_all(&[SOCKS5_VERSION, reply, 0, SOCKS5_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
 async fn get_response<IO>(stream: &mut BufStream<IO>) -> Result<String, ProxyError>
 where
     IO: AsyncRead + AsyncWrite + Unpin,
@@ -559,3 +655,41 @@ where
         None => Err(ProxyError::NoHttpCode),
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_socks5_accept() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let task = tokio::spawn(async move { socks5_accept(&mut server).await });
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        let mut buf = [0u8; 2];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [5, 0]);
+        let mut request = vec![5, 1, 0, 3, 11];
+        request.extend(b"example.com");
+        request.extend(&443u16.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+        assert_eq!(
+            task.await.unwrap().unwrap(),
+            ("example.com".to_owned(), 443)
+        );
+
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let task = tokio::spawn(async move { socks5_accept(&mut server).await });
+        client.write_all(&[5, 1, 0]).await.unwrap();
+        client.read_exact(&mut buf).await.unwrap();
+        // BIND is not supported
+        client
+            .write_all(&[5, 2, 0, 1, 127, 0, 0, 1, 0, 80])
+            .await
+            .unwrap();
+        assert!(task.await.unwrap().is_err());
+        let mut reply = [0u8; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], SOCKS5_REPLY_COMMAND_NOT_SUPPORTED);
+    }
+}
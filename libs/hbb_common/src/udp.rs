@@ -44,6 +44,13 @@ fn new_socket(addr: SocketAddr, reuse: bool, buf_size: usize) -> Result<Socket,
     Ok(socket)
 }
 
+/// A blocking socket sharing its port with others, e.g. multicast responders on a well-known port.
+pub fn new_std_reuse(addr: SocketAddr) -> ResultType<std::net::UdpSocket> {
+    let socket = new_socket(addr, true, 0)?;
+    socket.set_nonblocking(false)?;
+    Ok(socket.into_udp_socket())
+}
+
 impl FramedSocket {
     pub async fn new<T: ToSocketAddrs>(addr: T) -> ResultType<Self> {
         Self::new_reuse(addr, false, 0).await
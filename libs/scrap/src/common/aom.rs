@@ -7,6 +7,7 @@
 include!(concat!(env!("OUT_DIR"), "/aom_ffi.rs"));
 
 use crate::codec::{base_bitrate, codec_thread_num, Quality};
+use crate::damage::{active_map, DamageRect, PendingDamage};
 use crate::{codec::EncoderApi, EncodeFrame, STRIDE_ALIGN};
 use crate::{common::GoogleImage, generate_call_macro, generate_call_ptr_macro, Error, Result};
 use crate::{EncodeInput, EncodeYuvFormat, Pixfmt};
@@ -55,6 +56,9 @@ pub struct AomEncoder {
     height: usize,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    // None if the active map is not supported, otherwise whether it is set
+    active_map: Option<bool>,
+    damage: PendingDamage,
 }
 
 // https://webrtc.googlesource.com/src/+/refs/heads/main/modules/video_coding/codecs/av1/libaom_av1_encoder.cc
@@ -243,6 +247,8 @@ impl EncoderApi for AomEncoder {
                     height: config.height as _,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    active_map: Some(false),
+                    damage: Default::default(),
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -258,6 +264,7 @@ impl EncoderApi for AomEncoder {
             frames.push(Self::create_frame(frame));
         }
         if frames.len() > 0 {
+            self.damage.emitted();
             Ok(Self::create_video_frame(frames))
         } else {
             Err(anyhow!("no valid frame"))
@@ -310,6 +317,34 @@ impl EncoderApi for AomEncoder {
     }
 
     fn disable(&self) {}
+
+    fn set_damage(&mut self, damage: Option<&[DamageRect]>) {
+        let Some(set) = self.active_map else {
+            return;
+        };
+        let damage = self.damage.add(damage);
+        if damage.is_none() && !set {
+            return;
+        }
+        // in 16x16 blocks, key frames ignore it
+        let mut map = damage.map(|d| active_map(d, self.width, self.height, 16));
+        let known = damage.is_some();
+        let mut aom_map = aom_active_map_t {
+            active_map: map.as_mut().map_or(ptr::null_mut(), |m| m.0.as_mut_ptr()),
+            rows: map.as_ref().map_or(0, |m| m.1 as _),
+            cols: map.as_ref().map_or(0, |m| m.2 as _),
+        };
+        let ret = call_aom_allow_err!(aom_codec_control(
+            &mut self.ctx,
+            aome_enc_control_id::AOME_SET_ACTIVEMAP as i32,
+            &mut aom_map as *mut aom_active_map_t
+        ));
+        self.active_map = if ret == aom_codec_err_t::AOM_CODEC_OK {
+            Some(known)
+        } else {
+            None
+        };
+    }
 }
 
 impl AomEncoder {
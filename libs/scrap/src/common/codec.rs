@@ -9,11 +9,14 @@ use std::{
 use crate::hwcodec::*;
 #[cfg(feature = "mediacodec")]
 use crate::mediacodec::{MediaCodecDecoder, H264_DECODER_SUPPORT, H265_DECODER_SUPPORT};
+#[cfg(feature = "openh264")]
+use crate::openh264::*;
 #[cfg(feature = "vram")]
 use crate::vram::*;
 use crate::{
     aom::{self, AomDecoder, AomEncoder, AomEncoderConfig},
     common::GoogleImage,
+    damage::DamageRect,
     vpxcodec::{self, VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig, VpxVideoCodecId},
     CodecFormat, EncodeInput, EncodeYuvFormat, ImageRgb, ImageTexture,
 };
@@ -48,6 +51,8 @@ pub enum EncoderCfg {
     HWRAM(HwRamEncoderConfig),
     #[cfg(feature = "vram")]
     VRAM(VRamEncoderConfig),
+    #[cfg(feature = "openh264")]
+    OPENH264(OpenH264EncoderConfig),
 }
 
 pub trait EncoderApi {
@@ -75,6 +80,9 @@ pub trait EncoderApi {
     fn is_hardware(&self) -> bool;
 
     fn disable(&self);
+
+    /// Hint the damage of the next frame, `None` if it is unknown.
+    fn set_damage(&mut self, _damage: Option<&[DamageRect]>) {}
 }
 
 pub struct Encoder {
@@ -111,6 +119,8 @@ pub struct Decoder {
     h264_media_codec: MediaCodecDecoder,
     #[cfg(feature = "mediacodec")]
     h265_media_codec: MediaCodecDecoder,
+    #[cfg(feature = "openh264")]
+    h264_sw: Option<OpenH264Decoder>,
     format: CodecFormat,
     valid: bool,
     #[cfg(feature = "hwcodec")]
@@ -160,6 +170,10 @@ impl Encoder {
                     Err(e)
                 }
             },
+            #[cfg(feature = "openh264")]
+            EncoderCfg::OPENH264(_) => Ok(Encoder {
+                codec: Box::new(OpenH264Encoder::new(config, i444)?),
+            }),
         }
     }
 
@@ -225,8 +239,11 @@ impl Encoder {
                     HwRamEncoder::try_get(CodecFormat::H265).map_or(None, |c| Some(c.name));
             }
         }
-        let h264_useable =
+        let h264_hardware_useable =
             _all_support_h264_decoding && (h264vram_encoding || h264hw_encoding.is_some());
+        // software h264 is used only if preferred
+        let h264_useable =
+            h264_hardware_useable || (_all_support_h264_decoding && cfg!(feature = "openh264"));
         let h265_useable =
             _all_support_h265_decoding && (h265vram_encoding || h265hw_encoding.is_some());
         let mut format = ENCODE_CODEC_FORMAT.lock().unwrap();
@@ -270,7 +287,7 @@ impl Encoder {
         } else {
             CodecFormat::VP9
         };
-        if h264_useable {
+        if h264_hardware_useable {
             auto_codec = CodecFormat::H264;
         }
         if h265_useable {
@@ -290,7 +307,7 @@ impl Encoder {
             PreferCodec::VP9 => CodecFormat::VP9,
             PreferCodec::AV1 => CodecFormat::AV1,
             PreferCodec::H264 => {
-                if h264vram_encoding || h264hw_encoding.is_some() {
+                if h264_useable {
                     CodecFormat::H264
                 } else {
                     auto_codec
@@ -334,6 +351,7 @@ impl Encoder {
                 ..Default::default()
             })
             .into(),
+            lossless_tiles: true,
             ..Default::default()
         };
         #[cfg(feature = "hwcodec")]
@@ -346,6 +364,10 @@ impl Encoder {
             encoding.h264 |= VRamEncoder::available(CodecFormat::H264).len() > 0;
             encoding.h265 |= VRamEncoder::available(CodecFormat::H265).len() > 0;
         }
+        #[cfg(feature = "openh264")]
+        {
+            encoding.h264 = true;
+        }
         encoding
     }
 
@@ -387,6 +409,8 @@ impl Encoder {
                     return;
                 }
             },
+            #[cfg(feature = "openh264")]
+            EncoderCfg::OPENH264(_) => CodecFormat::H264,
         };
         let current = ENCODE_CODEC_FORMAT.lock().unwrap().clone();
         if current != format {
@@ -410,9 +434,16 @@ impl Encoder {
             EncoderCfg::HWRAM(_) => false,
             #[cfg(feature = "vram")]
             EncoderCfg::VRAM(_) => false,
+            #[cfg(feature = "openh264")]
+            EncoderCfg::OPENH264(_) => false,
         };
         prefer_i444 && i444_useable && !decodings.is_empty()
     }
+
+    pub fn use_lossless_tiles() -> bool {
+        let decodings = PEER_DECODINGS.lock().unwrap();
+        !decodings.is_empty() && decodings.values().all(|d| d.lossless_tiles)
+    }
 }
 
 impl Decoder {
@@ -422,7 +453,7 @@ impl Decoder {
         _luid: Option<i64>,
         mark_unsupported: &Vec<CodecFormat>,
     ) -> SupportedDecoding {
-        let (prefer, prefer_chroma) = Self::preference(id_for_perfer);
+        let (prefer, prefer_chroma, lossless_tiles) = Self::preference(id_for_perfer);
 
         #[allow(unused_mut)]
         let mut decoding = SupportedDecoding {
@@ -437,6 +468,7 @@ impl Decoder {
             .into(),
             prefer: prefer.into(),
             prefer_chroma: prefer_chroma.into(),
+            lossless_tiles,
             ..Default::default()
         };
         #[cfg(feature = "hwcodec")]
@@ -480,6 +512,10 @@ impl Decoder {
                     0
                 };
         }
+        #[cfg(feature = "openh264")]
+        {
+            decoding.ability_h264 = 1;
+        }
         for unsupported in mark_unsupported {
             match unsupported {
                 CodecFormat::VP8 => decoding.ability_vp8 = 0,
@@ -502,6 +538,8 @@ impl Decoder {
         let (mut h264_vram, mut h265_vram) = (None, None);
         #[cfg(feature = "mediacodec")]
         let (mut h264_media_codec, mut h265_media_codec) = (None, None);
+        #[cfg(feature = "openh264")]
+        let mut h264_sw = None;
         let mut valid = false;
 
         match format {
@@ -555,6 +593,14 @@ impl Decoder {
                     }
                     valid = h264_media_codec.is_some();
                 }
+                #[cfg(feature = "openh264")]
+                if !valid {
+                    match OpenH264Decoder::new() {
+                        Ok(v) => h264_sw = Some(v),
+                        Err(e) => log::error!("create H264 software decoder failed: {}", e),
+                    }
+                    valid = h264_sw.is_some();
+                }
             }
             CodecFormat::H265 => {
                 #[cfg(feature = "vram")]
@@ -607,6 +653,8 @@ impl Decoder {
             h264_media_codec,
             #[cfg(feature = "mediacodec")]
             h265_media_codec,
+            #[cfg(feature = "openh264")]
+            h264_sw,
             format,
             valid,
             #[cfg(feature = "hwcodec")]
@@ -653,7 +701,7 @@ impl Decoder {
                     bail!("av1 decoder not available");
                 }
             }
-            #[cfg(any(feature = "hwcodec", feature = "vram"))]
+            #[cfg(any(feature = "hwcodec", feature = "vram", feature = "openh264"))]
             video_frame::Union::H264s(h264s) => {
                 *chroma = Some(Chroma::I420);
                 #[cfg(feature = "vram")]
@@ -665,6 +713,10 @@ impl Decoder {
                 if let Some(decoder) = &mut self.h264_ram {
                     return Decoder::handle_hwram_video_frame(decoder, h264s, rgb, &mut self.i420);
                 }
+                #[cfg(feature = "openh264")]
+                if let Some(decoder) = &mut self.h264_sw {
+                    return Decoder::handle_openh264_video_frame(decoder, h264s, rgb);
+                }
                 Err(anyhow!("don't support h264!"))
             }
             #[cfg(any(feature = "hwcodec", feature = "vram"))]
@@ -811,10 +863,26 @@ impl Decoder {
         return Ok(false);
     }
 
-    fn preference(id: Option<&str>) -> (PreferCodec, Chroma) {
+    // rgb [in/out] fmt and stride must be set in ImageRgb
+    #[cfg(feature = "openh264")]
+    fn handle_openh264_video_frame(
+        decoder: &mut OpenH264Decoder,
+        frames: &EncodedVideoFrames,
+        rgb: &mut ImageRgb,
+    ) -> ResultType<bool> {
+        let mut ret = false;
+        for h264 in frames.frames.iter() {
+            if decoder.decode(&h264.data, rgb)? {
+                ret = true;
+            }
+        }
+        Ok(ret)
+    }
+
+    fn preference(id: Option<&str>) -> (PreferCodec, Chroma, bool) {
         let id = id.unwrap_or_default();
         if id.is_empty() {
-            return (PreferCodec::Auto, Chroma::I420);
+            return (PreferCodec::Auto, Chroma::I420, false);
         }
         let options = PeerConfig::load(id).options;
         let codec = options
@@ -838,7 +906,8 @@ impl Decoder {
         } else {
             Chroma::I420
         };
-        (codec, chroma)
+        let lossless_tiles = options.get("lossless-text") == Some(&"Y".to_string());
+        (codec, chroma, lossless_tiles)
     }
 }
 
@@ -0,0 +1,218 @@
+//! Damage regions of the captured frames.
+//!
+//! Capturers report the regions changed since their previous frame, from the system where it is
+//! available (DXGI dirty and move rects), otherwise by comparing with the copy of the previous
+//! frame they keep anyway. The video service skips frames without damage, and the encoders get
+//! the damage as an active map, so that unchanged blocks are coded as static.
+
+use std::io;
+
+// Block size of the comparison with the previous frame, in pixels.
+const DIFF_BLOCK: usize = 32;
+// More pending rects are merged into an unknown damage.
+const MAX_PENDING_RECTS: usize = 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DamageRect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+/// Like [`crate::would_block_if_equal`], but returns the damage of `b` compared with `old`.
+///
+/// The damage is `None` if it is unknown, e.g. the size changes, the whole frame is new then.
+pub fn diff_or_would_block(
+    old: &mut Vec<u8>,
+    b: &[u8],
+    width: usize,
+    height: usize,
+    bytes_per_pixel: usize,
+) -> io::Result<Option<Vec<DamageRect>>> {
+    let stride = if height > 0 { b.len() / height } else { 0 };
+    if old.len() != b.len() || width == 0 || width * bytes_per_pixel > stride {
+        old.resize(b.len(), 0);
+        old.copy_from_slice(b);
+        return Ok(None);
+    }
+    let mut damage = Vec::new();
+    for y in (0..height).step_by(DIFF_BLOCK) {
+        let h = DIFF_BLOCK.min(height - y);
+        // start of the current run of changed blocks in the row
+        let mut run = None;
+        for x in (0..width).step_by(DIFF_BLOCK) {
+            let w = DIFF_BLOCK.min(width - x);
+            let changed = (y..y + h).any(|line| {
+                let start = line * stride + x * bytes_per_pixel;
+                let end = start + w * bytes_per_pixel;
+                old[start..end] != b[start..end]
+            });
+            match (changed, run) {
+                (true, None) => run = Some(x),
+                (false, Some(start)) => {
+                    damage.push(DamageRect {
+                        x: start,
+                        y,
+                        w: x - start,
+                        h,
+                    });
+                    run = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(start) = run {
+            damage.push(DamageRect {
+                x: start,
+                y,
+                w: width - start,
+                h,
+            });
+        }
+    }
+    if damage.is_empty() {
+        return Err(io::ErrorKind::WouldBlock.into());
+    }
+    old.copy_from_slice(b);
+    Ok(Some(damage))
+}
+
+/// The active map of a frame in blocks of `block` pixels, row by row, 1 for the blocks
+/// intersecting the damage and 0 for the unchanged ones.
+///
+/// Returns the map, the rows and the columns.
+pub fn active_map(
+    damage: &[DamageRect],
+    width: usize,
+    height: usize,
+    block: usize,
+) -> (Vec<u8>, usize, usize) {
+    let rows = (height + block - 1) / block;
+    let cols = (width + block - 1) / block;
+    let mut map = vec![0u8; rows * cols];
+    for r in damage {
+        if r.w == 0 || r.h == 0 || r.x >= width || r.y >= height {
+            continue;
+        }
+        let right = ((r.x + r.w).min(width) + block - 1) / block;
+        let bottom = ((r.y + r.h).min(height) + block - 1) / block;
+        for row in r.y / block..bottom {
+            map[row * cols + r.x / block..row * cols + right].fill(1);
+        }
+    }
+    (map, rows, cols)
+}
+
+/// The damage since the last frame emitted by an encoder.
+///
+/// The capturers compare with their previous frame, whether it is encoded or not. The blocks
+/// changed in a frame which the rate control drops or which fails to encode are only encoded if
+/// they stay in the active map of the next frames, until one is emitted.
+#[derive(Debug, Default)]
+pub struct PendingDamage {
+    rects: Vec<DamageRect>,
+    unknown: bool,
+}
+
+impl PendingDamage {
+    /// Add the damage of the next frame, return the damage to encode it with.
+    pub fn add(&mut self, damage: Option<&[DamageRect]>) -> Option<&[DamageRect]> {
+        match damage {
+            Some(d) if !self.unknown && self.rects.len() + d.len() <= MAX_PENDING_RECTS => {
+                self.rects.extend_from_slice(d)
+            }
+            _ => {
+                self.unknown = true;
+                self.rects.clear();
+            }
+        }
+        (!self.unknown).then_some(&self.rects[..])
+    }
+
+    /// A frame is emitted, its damage is encoded.
+    pub fn emitted(&mut self) {
+        self.unknown = false;
+        self.rects.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_or_would_block() {
+        let (width, height, bpp) = (100, 70, 4);
+        let frame = vec![0u8; width * height * bpp];
+        let mut old = Vec::new();
+        assert_eq!(
+            diff_or_would_block(&mut old, &frame, width, height, bpp).unwrap(),
+            None
+        );
+        assert_eq!(
+            diff_or_would_block(&mut old, &frame, width, height, bpp)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let mut frame2 = frame.clone();
+        frame2[(40 * width + 70) * bpp] = 1;
+        frame2[(40 * width + 99) * bpp] = 1;
+        let damage = diff_or_would_block(&mut old, &frame2, width, height, bpp).unwrap();
+        assert_eq!(
+            damage,
+            Some(vec![DamageRect {
+                x: 64,
+                y: 32,
+                w: 36,
+                h: 32
+            }])
+        );
+        assert_eq!(old, frame2);
+    }
+
+    #[test]
+    fn test_active_map() {
+        let damage = [
+            DamageRect {
+                x: 20,
+                y: 0,
+                w: 10,
+                h: 10,
+            },
+            DamageRect {
+                x: 0,
+                y: 40,
+                w: 100,
+                h: 100,
+            },
+        ];
+        let (map, rows, cols) = active_map(&damage, 50, 50, 16);
+        assert_eq!((rows, cols), (4, 4));
+        assert_eq!(map, [0, 1, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_pending_damage() {
+        let a = DamageRect {
+            x: 0,
+            y: 0,
+            w: 16,
+            h: 16,
+        };
+        let b = DamageRect { x: 32, ..a };
+        let mut pending = PendingDamage::default();
+        assert_eq!(pending.add(Some(&[a])), Some(&[a][..]));
+        // the frame is dropped
+        assert_eq!(pending.add(Some(&[b])), Some(&[a, b][..]));
+        pending.emitted();
+        assert_eq!(pending.add(Some(&[b])), Some(&[b][..]));
+        // an unknown damage stays unknown until a frame is emitted
+        assert_eq!(pending.add(None), None);
+        assert_eq!(pending.add(Some(&[a])), None);
+        pending.emitted();
+        assert_eq!(pending.add(Some(&[a])), Some(&[a][..]));
+    }
+}
@@ -1,6 +1,6 @@
 #[cfg(feature = "vram")]
 use crate::AdapterDevice;
-use crate::{common::TraitCapturer, dxgi, Frame, Pixfmt};
+use crate::{common::TraitCapturer, damage::DamageRect, dxgi, Frame, Pixfmt};
 use std::{
     io::{
         self,
@@ -73,6 +73,7 @@ pub struct PixelBuffer<'a> {
     width: usize,
     height: usize,
     stride: Vec<usize>,
+    damage: Option<Vec<DamageRect>>,
 }
 
 impl<'a> PixelBuffer<'a> {
@@ -85,8 +86,14 @@ impl<'a> PixelBuffer<'a> {
             width,
             height,
             stride,
+            damage: None,
         }
     }
+
+    pub fn with_damage(mut self, damage: Option<Vec<DamageRect>>) -> Self {
+        self.damage = damage;
+        self
+    }
 }
 
 impl<'a> crate::TraitPixelBuffer for PixelBuffer<'a> {
@@ -106,6 +113,10 @@ impl<'a> crate::TraitPixelBuffer for PixelBuffer<'a> {
         self.stride.clone()
     }
 
+    fn damage(&self) -> Option<&[DamageRect]> {
+        self.damage.as_deref()
+    }
+
     fn pixfmt(&self) -> Pixfmt {
         Pixfmt::BGRA
     }
@@ -0,0 +1,422 @@
+//! Lossless tiles of static regions.
+//!
+//! Thin text is blurred by the lossy codecs even with the best quality. The blocks staying
+//! unchanged for [`STATIC_FRAMES`] frames are sent once more as zstd compressed BGRA tiles in
+//! the `RGB` video frame, which the client draws over the decoded frames until the server
+//! invalidates them, because they changed, or a key frame arrives.
+
+use crate::{damage::DamageRect, ImageFormat, ImageRgb};
+use hbb_common::{
+    compress::{compress, decompress},
+    message_proto::{message, video_frame, EncodedVideoFrames, Message, RGBTile, VideoFrame, RGB},
+};
+
+/// Frames a block must stay unchanged for before it is sent lossless.
+pub const STATIC_FRAMES: u32 = 15;
+const BLOCK: usize = 64;
+// Limit the size of a message when a large region becomes static.
+const MAX_BLOCKS_PER_MESSAGE: usize = 64;
+
+/// Whether an encoded video frame is a key frame, which clears the lossless tiles.
+pub fn is_key_frame(vf: &VideoFrame) -> bool {
+    let frames = |f: &EncodedVideoFrames| f.frames.iter().any(|f| f.key);
+    match &vf.union {
+        Some(video_frame::Union::Vp8s(f))
+        | Some(video_frame::Union::Vp9s(f))
+        | Some(video_frame::Union::Av1s(f))
+        | Some(video_frame::Union::H264s(f))
+        | Some(video_frame::Union::H265s(f)) => frames(f),
+        _ => false,
+    }
+}
+
+/// Whether a message carries lossless tiles rather than an encoded frame.
+pub fn is_tiles(msg: &Message) -> bool {
+    match &msg.union {
+        Some(message::Union::VideoFrame(vf)) => {
+            matches!(vf.union, Some(video_frame::Union::Rgb(_)))
+        }
+        _ => false,
+    }
+}
+
+/// The lossless tiles of a display on the server.
+pub struct LosslessTiles {
+    width: usize,
+    height: usize,
+    rows: usize,
+    cols: usize,
+    // BGRA copy of the latest frame
+    pixels: Vec<u8>,
+    valid: bool,
+    // frames since the last change of each block
+    static_frames: Vec<u32>,
+    // whether the client shows the lossless tile of each block
+    sent: Vec<bool>,
+    invalidated: Vec<DamageRect>,
+}
+
+impl LosslessTiles {
+    pub fn new(width: usize, height: usize) -> Self {
+        let rows = (height + BLOCK - 1) / BLOCK;
+        let cols = (width + BLOCK - 1) / BLOCK;
+        Self {
+            width,
+            height,
+            rows,
+            cols,
+            pixels: vec![0; width * height * 4],
+            valid: false,
+            static_frames: vec![0; rows * cols],
+            sent: vec![false; rows * cols],
+            invalidated: Vec::new(),
+        }
+    }
+
+    /// Update with a captured frame.
+    #[cfg(not(target_os = "ios"))]
+    pub fn update(&mut self, frame: &crate::Frame) {
+        use crate::TraitPixelBuffer;
+
+        let crate::Frame::PixelBuffer(pixelbuffer) = frame else {
+            return;
+        };
+        let swap = match pixelbuffer.pixfmt() {
+            crate::Pixfmt::BGRA => false,
+            crate::Pixfmt::RGBA => true,
+            _ => return,
+        };
+        if pixelbuffer.width() != self.width || pixelbuffer.height() != self.height {
+            return;
+        }
+        let whole = [DamageRect {
+            x: 0,
+            y: 0,
+            w: self.width,
+            h: self.height,
+        }];
+        let damage = match frame.damage() {
+            Some(damage) if self.valid => damage,
+            _ => &whole,
+        };
+        let data = pixelbuffer.data();
+        let stride = pixelbuffer
+            .stride()
+            .first()
+            .cloned()
+            .unwrap_or(self.width * 4);
+        for r in damage {
+            let right = (r.x + r.w).min(self.width);
+            let bottom = (r.y + r.h).min(self.height);
+            if r.x >= right || r.y >= bottom {
+                continue;
+            }
+            for y in r.y..bottom {
+                let src = &data[y * stride + r.x * 4..y * stride + right * 4];
+                let dst =
+                    &mut self.pixels[(y * self.width + r.x) * 4..(y * self.width + right) * 4];
+                dst.copy_from_slice(src);
+                if swap {
+                    dst.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+                }
+            }
+            for row in r.y / BLOCK..(bottom + BLOCK - 1) / BLOCK {
+                for col in r.x / BLOCK..(right + BLOCK - 1) / BLOCK {
+                    let i = row * self.cols + col;
+                    self.static_frames[i] = 0;
+                    if self.sent[i] {
+                        self.sent[i] = false;
+                        self.invalidated.push(self.block_rect(row, col, 1));
+                    }
+                }
+            }
+        }
+        self.valid = true;
+    }
+
+    /// The client clears the lossless tiles on key frames.
+    pub fn reset(&mut self) {
+        self.sent.iter_mut().for_each(|s| *s = false);
+        self.invalidated.clear();
+    }
+
+    /// Count a frame, and take the message of the new tiles and the ones invalidated since.
+    pub fn next_message(&mut self, display: usize) -> Option<Message> {
+        if !self.valid {
+            return None;
+        }
+        self.static_frames
+            .iter_mut()
+            .for_each(|s| *s = s.saturating_add(1));
+        let mut tiles = Vec::new();
+        let mut count = 0;
+        for row in 0..self.rows {
+            // start of the current run of blocks to send in the row
+            let mut run = None;
+            for col in 0..=self.cols {
+                let i = row * self.cols + col;
+                let ready = col < self.cols
+                    && count < MAX_BLOCKS_PER_MESSAGE
+                    && !self.sent[i]
+                    && self.static_frames[i] >= STATIC_FRAMES;
+                if ready {
+                    self.sent[i] = true;
+                    count += 1;
+                    run.get_or_insert(col);
+                } else if let Some(start) = run.take() {
+                    tiles.push(self.tile(self.block_rect(row, start, col - start)));
+                }
+            }
+        }
+        self.message(display, tiles)
+    }
+
+    /// Take the message of the invalidated tiles, sent before the frame which changed them so
+    /// that the client does not draw the stale tiles over that frame.
+    pub fn take_invalidated(&mut self, display: usize) -> Option<Message> {
+        self.message(display, Vec::new())
+    }
+
+    fn message(&mut self, display: usize, tiles: Vec<RGBTile>) -> Option<Message> {
+        if tiles.is_empty() && self.invalidated.is_empty() {
+            return None;
+        }
+        let invalidated = std::mem::take(&mut self.invalidated)
+            .into_iter()
+            .map(|r| RGBTile {
+                x: r.x as _,
+                y: r.y as _,
+                width: r.w as _,
+                height: r.h as _,
+                ..Default::default()
+            })
+            .collect();
+        let mut vf = VideoFrame::new();
+        vf.set_rgb(RGB {
+            compress: true,
+            tiles,
+            invalidated,
+            ..Default::default()
+        });
+        vf.display = display as _;
+        let mut msg = Message::new();
+        msg.set_video_frame(vf);
+        Some(msg)
+    }
+
+    fn block_rect(&self, row: usize, col: usize, cols: usize) -> DamageRect {
+        let x = col * BLOCK;
+        let y = row * BLOCK;
+        DamageRect {
+            x,
+            y,
+            w: ((col + cols) * BLOCK).min(self.width) - x,
+            h: BLOCK.min(self.height - y),
+        }
+    }
+
+    fn tile(&self, r: DamageRect) -> RGBTile {
+        let mut raw = Vec::with_capacity(r.w * r.h * 4);
+        for y in r.y..r.y + r.h {
+            raw.extend_from_slice(
+                &self.pixels[(y * self.width + r.x) * 4..(y * self.width + r.x + r.w) * 4],
+            );
+        }
+        RGBTile {
+            x: r.x as _,
+            y: r.y as _,
+            width: r.w as _,
+            height: r.h as _,
+            data: compress(&raw).into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// The lossless tiles of a display on the client, drawn over the decoded frames.
+#[derive(Default)]
+pub struct LosslessOverlay {
+    width: usize,
+    height: usize,
+    // BGRA
+    pixels: Vec<u8>,
+    rects: Vec<DamageRect>,
+}
+
+impl LosslessOverlay {
+    pub fn clear(&mut self) {
+        self.rects.clear();
+    }
+
+    /// Apply the tiles to the overlay and draw it over the latest decoded image.
+    pub fn handle_rgb(&mut self, rgb_frame: &RGB, rgb: &mut ImageRgb) -> bool {
+        if rgb.w == 0 || rgb.h == 0 {
+            return false;
+        }
+        if self.width != rgb.w || self.height != rgb.h {
+            self.width = rgb.w;
+            self.height = rgb.h;
+            self.pixels = vec![0; rgb.w * rgb.h * 4];
+            self.rects.clear();
+        }
+        for tile in rgb_frame.invalidated.iter() {
+            let r = Self::tile_rect(tile);
+            self.rects = self.rects.iter().flat_map(|a| subtract(a, &r)).collect();
+        }
+        for tile in rgb_frame.tiles.iter() {
+            let r = Self::tile_rect(tile);
+            if r.w == 0 || r.x + r.w > self.width || r.y + r.h > self.height {
+                continue;
+            }
+            let data = if rgb_frame.compress {
+                decompress(&tile.data)
+            } else {
+                tile.data.to_vec()
+            };
+            if data.len() != r.w * r.h * 4 {
+                continue;
+            }
+            for (i, line) in data.chunks_exact(r.w * 4).enumerate() {
+                let start = ((r.y + i) * self.width + r.x) * 4;
+                self.pixels[start..start + r.w * 4].copy_from_slice(line);
+            }
+            self.rects = self.rects.iter().flat_map(|a| subtract(a, &r)).collect();
+            self.rects.push(r);
+        }
+        self.draw(rgb);
+        true
+    }
+
+    /// Draw the overlay over a decoded image.
+    pub fn draw(&self, rgb: &mut ImageRgb) {
+        if self.rects.is_empty() || self.width != rgb.w || self.height != rgb.h {
+            return;
+        }
+        let bytes_per_pixel = match rgb.fmt() {
+            ImageFormat::Raw => 3,
+            ImageFormat::ARGB | ImageFormat::ABGR => 4,
+        };
+        let align = rgb.align().max(1);
+        let stride = (rgb.w * bytes_per_pixel + align - 1) & !(align - 1);
+        if rgb.raw.len() < stride * rgb.h {
+            return;
+        }
+        for r in self.rects.iter() {
+            for y in r.y..r.y + r.h {
+                let src =
+                    &self.pixels[(y * self.width + r.x) * 4..(y * self.width + r.x + r.w) * 4];
+                let start = y * stride + r.x * bytes_per_pixel;
+                let dst = &mut rgb.raw[start..start + r.w * bytes_per_pixel];
+                match rgb.fmt() {
+                    // BGRA in memory
+                    ImageFormat::ARGB => dst.copy_from_slice(src),
+                    // RGBA in memory
+                    ImageFormat::ABGR => {
+                        for (d, s) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+                            d.copy_from_slice(&[s[2], s[1], s[0], s[3]]);
+                        }
+                    }
+                    // RGB in memory
+                    ImageFormat::Raw => {
+                        for (d, s) in dst.chunks_exact_mut(3).zip(src.chunks_exact(4)) {
+                            d.copy_from_slice(&[s[2], s[1], s[0]]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn tile_rect(tile: &RGBTile) -> DamageRect {
+        DamageRect {
+            x: tile.x.max(0) as _,
+            y: tile.y.max(0) as _,
+            w: tile.width.max(0) as _,
+            h: tile.height.max(0) as _,
+        }
+    }
+}
+
+// The parts of `a` outside of `b`.
+fn subtract(a: &DamageRect, b: &DamageRect) -> Vec<DamageRect> {
+    let (ax1, ay1, bx1, by1) = (a.x + a.w, a.y + a.h, b.x + b.w, b.y + b.h);
+    if b.x >= ax1 || bx1 <= a.x || b.y >= ay1 || by1 <= a.y {
+        return vec![*a];
+    }
+    let mut parts = Vec::new();
+    let top = b.y.max(a.y);
+    let bottom = by1.min(ay1);
+    if top > a.y {
+        parts.push(DamageRect { h: top - a.y, ..*a });
+    }
+    if bottom < ay1 {
+        parts.push(DamageRect {
+            y: bottom,
+            h: ay1 - bottom,
+            ..*a
+        });
+    }
+    if b.x > a.x {
+        parts.push(DamageRect {
+            x: a.x,
+            y: top,
+            w: b.x - a.x,
+            h: bottom - top,
+        });
+    }
+    if bx1 < ax1 {
+        parts.push(DamageRect {
+            x: bx1,
+            y: top,
+            w: ax1 - bx1,
+            h: bottom - top,
+        });
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subtract() {
+        let a = DamageRect {
+            x: 0,
+            y: 0,
+            w: 192,
+            h: 64,
+        };
+        let b = DamageRect {
+            x: 64,
+            y: 0,
+            w: 64,
+            h: 64,
+        };
+        assert_eq!(
+            subtract(&a, &b),
+            vec![
+                DamageRect {
+                    x: 0,
+                    y: 0,
+                    w: 64,
+                    h: 64
+                },
+                DamageRect {
+                    x: 128,
+                    y: 0,
+                    w: 64,
+                    h: 64
+                }
+            ]
+        );
+        let c = DamageRect {
+            x: 200,
+            y: 0,
+            w: 10,
+            h: 10,
+        };
+        assert_eq!(subtract(&a, &c), vec![a]);
+        assert!(subtract(&b, &a).is_empty());
+    }
+}
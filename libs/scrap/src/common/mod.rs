@@ -41,6 +41,8 @@ pub mod convert;
 pub mod hwcodec;
 #[cfg(feature = "mediacodec")]
 pub mod mediacodec;
+#[cfg(feature = "openh264")]
+pub mod openh264;
 pub mod vpxcodec;
 #[cfg(feature = "vram")]
 pub mod vram;
@@ -49,6 +51,8 @@ pub const STRIDE_ALIGN: usize = 64; // commonly used in libvpx vpx_img_alloc cal
 pub const HW_STRIDE_ALIGN: usize = 0; // recommended by av_frame_get_buffer
 
 pub mod aom;
+pub mod damage;
+pub mod lossless;
 pub mod record;
 mod vpx;
 
@@ -167,6 +171,11 @@ pub trait TraitPixelBuffer {
     fn stride(&self) -> Vec<usize>;
 
     fn pixfmt(&self) -> Pixfmt;
+
+    /// The regions changed since the previous frame, `None` if unknown.
+    fn damage(&self) -> Option<&[damage::DamageRect]> {
+        None
+    }
 }
 
 #[cfg(not(any(target_os = "ios")))]
@@ -184,6 +193,13 @@ impl Frame<'_> {
         }
     }
 
+    pub fn damage(&self) -> Option<&[damage::DamageRect]> {
+        match self {
+            Frame::PixelBuffer(pixelbuffer) => pixelbuffer.damage(),
+            Frame::Texture(_) => None,
+        }
+    }
+
     pub fn to<'a>(
         &'a self,
         yuvfmt: EncodeYuvFormat,
@@ -0,0 +1,337 @@
+// Software H264 with OpenH264, for the builds without hwcodec and the peers without hardware
+// codecs. It is only used when the peer prefers H264, the auto codec stays with the hardware.
+// https://github.com/ralfbiedert/openh264-rs
+
+use crate::{
+    codec::{base_bitrate, EncoderApi, EncoderCfg, Quality},
+    EncodeInput, EncodeYuvFormat, GoogleImage, ImageRgb, Pixfmt, STRIDE_ALIGN,
+};
+use hbb_common::{
+    anyhow::{anyhow, Context},
+    bail,
+    bytes::Bytes,
+    message_proto::{Chroma, EncodedVideoFrame, EncodedVideoFrames, VideoFrame},
+    ResultType,
+};
+use openh264::{
+    decoder::{DecodedYUV, Decoder},
+    encoder::{Encoder, EncoderConfig, FrameType, RateControlMode},
+    formats::YUVSource,
+    OpenH264API,
+};
+
+const DEFAULT_FPS: f32 = 30.0;
+
+#[derive(Debug, Clone)]
+pub struct OpenH264EncoderConfig {
+    pub width: usize,
+    pub height: usize,
+    pub quality: Quality,
+    pub keyframe_interval: Option<usize>,
+}
+
+pub struct OpenH264Encoder {
+    encoder: Encoder,
+    config: OpenH264EncoderConfig,
+    bitrate: u32,
+    yuvfmt: EncodeYuvFormat,
+    frames: usize,
+}
+
+impl EncoderApi for OpenH264Encoder {
+    fn new(cfg: EncoderCfg, _i444: bool) -> ResultType<Self>
+    where
+        Self: Sized,
+    {
+        match cfg {
+            EncoderCfg::OPENH264(config) => {
+                let bitrate = Self::bitrate_of(&config);
+                let encoder = Self::create(bitrate)?;
+                let yuvfmt = Self::get_yuvfmt(config.width, config.height);
+                Ok(Self {
+                    encoder,
+                    config,
+                    bitrate,
+                    yuvfmt,
+                    frames: 0,
+                })
+            }
+            _ => Err(anyhow!("encoder type mismatch")),
+        }
+    }
+
+    fn encode_to_message(&mut self, input: EncodeInput, ms: i64) -> ResultType<VideoFrame> {
+        let data = input.yuv()?;
+        let source = I420Source {
+            fmt: &self.yuvfmt,
+            data,
+        };
+        if data.len() < source.len() {
+            bail!("invalid yuv length: {} < {}", data.len(), source.len());
+        }
+        if let Some(interval) = self.config.keyframe_interval {
+            if self.frames > 0 && interval > 0 && self.frames % interval == 0 {
+                self.encoder.force_intra_frame();
+            }
+        }
+        self.frames += 1;
+        let stream = self
+            .encoder
+            .encode(&source)
+            .with_context(|| "Failed to encode")?;
+        let key = matches!(stream.frame_type(), FrameType::IDR | FrameType::I);
+        let data = stream.to_vec();
+        if data.is_empty() {
+            bail!("no valid frame");
+        }
+        let mut frames = EncodedVideoFrames::new();
+        frames.frames.push(EncodedVideoFrame {
+            data: Bytes::from(data),
+            key,
+            pts: ms,
+            ..Default::default()
+        });
+        let mut vf = VideoFrame::new();
+        vf.set_h264s(frames);
+        Ok(vf)
+    }
+
+    fn yuvfmt(&self) -> EncodeYuvFormat {
+        self.yuvfmt.clone()
+    }
+
+    #[cfg(feature = "vram")]
+    fn input_texture(&self) -> bool {
+        false
+    }
+
+    fn set_quality(&mut self, quality: Quality) -> ResultType<()> {
+        if quality == self.config.quality {
+            return Ok(());
+        }
+        self.config.quality = quality;
+        // the bitrate is fixed on creation, the new encoder starts with a key frame
+        self.bitrate = Self::bitrate_of(&self.config);
+        self.encoder = Self::create(self.bitrate)?;
+        self.frames = 0;
+        Ok(())
+    }
+
+    fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+
+    // Recreating the encoder for every adjustment sends too many key frames.
+    fn support_abr(&self) -> bool {
+        false
+    }
+
+    fn support_changing_quality(&self) -> bool {
+        true
+    }
+
+    fn latency_free(&self) -> bool {
+        true
+    }
+
+    fn is_hardware(&self) -> bool {
+        false
+    }
+
+    fn disable(&self) {}
+}
+
+impl OpenH264Encoder {
+    fn create(bitrate: u32) -> ResultType<Encoder> {
+        // every frame must produce an output, `encode_to_message` fails on the skipped ones
+        let config = EncoderConfig::new()
+            .set_bitrate_bps(bitrate * 1000)
+            .max_frame_rate(DEFAULT_FPS)
+            .rate_control_mode(RateControlMode::Bitrate)
+            .enable_skip_frame(false);
+        Ok(Encoder::with_api_config(
+            OpenH264API::from_source(),
+            config,
+        )?)
+    }
+
+    fn bitrate_of(config: &OpenH264EncoderConfig) -> u32 {
+        let b = match config.quality {
+            Quality::Best => 150,
+            Quality::Balanced => 100 * 2 / 3,
+            Quality::Low => 50,
+            Quality::Custom(b) => b,
+        };
+        let base = base_bitrate(config.width as _, config.height as _);
+        let bitrate = base * b / 100;
+        if bitrate > 0 {
+            bitrate
+        } else {
+            base
+        }
+    }
+
+    fn get_yuvfmt(width: usize, height: usize) -> EncodeYuvFormat {
+        let align = |x: usize| (x + STRIDE_ALIGN - 1) & !(STRIDE_ALIGN - 1);
+        let stride_y = align(width);
+        let stride_uv = align((width + 1) / 2);
+        let u = stride_y * height;
+        let v = u + stride_uv * ((height + 1) / 2);
+        EncodeYuvFormat {
+            pixfmt: Pixfmt::I420,
+            w: width,
+            h: height,
+            stride: vec![stride_y, stride_uv, stride_uv],
+            u,
+            v,
+        }
+    }
+}
+
+// The I420 frame in the layout of `EncodeYuvFormat`.
+struct I420Source<'a> {
+    fmt: &'a EncodeYuvFormat,
+    data: &'a [u8],
+}
+
+impl I420Source<'_> {
+    fn len(&self) -> usize {
+        self.fmt.v + self.fmt.stride[2] * ((self.fmt.h + 1) / 2)
+    }
+}
+
+impl YUVSource for I420Source<'_> {
+    fn dimensions(&self) -> (usize, usize) {
+        (self.fmt.w, self.fmt.h)
+    }
+
+    fn strides(&self) -> (usize, usize, usize) {
+        (self.fmt.stride[0], self.fmt.stride[1], self.fmt.stride[2])
+    }
+
+    fn y(&self) -> &[u8] {
+        &self.data[..self.fmt.u]
+    }
+
+    fn u(&self) -> &[u8] {
+        &self.data[self.fmt.u..self.fmt.v]
+    }
+
+    fn v(&self) -> &[u8] {
+        &self.data[self.fmt.v..self.len()]
+    }
+}
+
+pub struct OpenH264Decoder {
+    decoder: Decoder,
+}
+
+impl OpenH264Decoder {
+    pub fn new() -> ResultType<Self> {
+        Ok(Self {
+            decoder: Decoder::new()?,
+        })
+    }
+
+    // rgb [in/out] fmt and stride must be set in ImageRgb
+    pub fn decode(&mut self, data: &[u8], rgb: &mut ImageRgb) -> ResultType<bool> {
+        match self.decoder.decode(data)? {
+            Some(yuv) => {
+                Image(&yuv).to(rgb);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+struct Image<'a, 'b>(&'a DecodedYUV<'b>);
+
+impl GoogleImage for Image<'_, '_> {
+    fn width(&self) -> usize {
+        self.0.dimensions().0
+    }
+
+    fn height(&self) -> usize {
+        self.0.dimensions().1
+    }
+
+    fn stride(&self) -> Vec<i32> {
+        let (y, u, v) = self.0.strides();
+        vec![y as _, u as _, v as _]
+    }
+
+    fn planes(&self) -> Vec<*mut u8> {
+        vec![
+            self.0.y().as_ptr() as _,
+            self.0.u().as_ptr() as _,
+            self.0.v().as_ptr() as _,
+        ]
+    }
+
+    fn chroma(&self) -> Chroma {
+        Chroma::I420
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ImageFormat;
+    use hbb_common::message_proto::video_frame;
+
+    #[test]
+    fn test_i420_source() {
+        let fmt = OpenH264Encoder::get_yuvfmt(101, 67);
+        assert_eq!(fmt.stride, vec![128, 64, 64]);
+        let data = vec![0; fmt.v + fmt.stride[2] * 34];
+        let source = I420Source {
+            fmt: &fmt,
+            data: &data,
+        };
+        assert_eq!(source.len(), data.len());
+        assert_eq!(source.dimensions(), (101, 67));
+        assert_eq!(source.y().len(), 128 * 67);
+        assert_eq!(source.u().len(), 64 * 34);
+        assert_eq!(source.v().len(), 64 * 34);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let (width, height) = (128, 64);
+        let mut encoder = OpenH264Encoder::new(
+            EncoderCfg::OPENH264(OpenH264EncoderConfig {
+                width,
+                height,
+                quality: Quality::Balanced,
+                keyframe_interval: Some(2),
+            }),
+            false,
+        )
+        .unwrap();
+        let yuvfmt = encoder.yuvfmt();
+        let mut yuv = vec![128; yuvfmt.v + yuvfmt.stride[2] * height / 2];
+        yuv[..yuvfmt.u]
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, p)| *p = (i % 251) as _);
+        assert!(encoder
+            .encode_to_message(EncodeInput::YUV(&yuv[1..]), 0)
+            .is_err());
+
+        let mut decoder = OpenH264Decoder::new().unwrap();
+        let mut rgb = ImageRgb::new(ImageFormat::ARGB, 1);
+        for (i, key) in [true, false, true].into_iter().enumerate() {
+            let vf = encoder
+                .encode_to_message(EncodeInput::YUV(&yuv), i as _)
+                .unwrap();
+            let Some(video_frame::Union::H264s(frames)) = vf.union else {
+                panic!("not h264");
+            };
+            assert_eq!(frames.frames[0].key, key);
+            assert!(decoder.decode(&frames.frames[0].data, &mut rgb).unwrap());
+            assert_eq!((rgb.w, rgb.h), (width, height));
+            assert_eq!(rgb.raw.len(), width * height * 4);
+        }
+    }
+}
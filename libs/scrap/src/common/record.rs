@@ -2,23 +2,103 @@ use crate::CodecFormat;
 #[cfg(feature = "hwcodec")]
 use hbb_common::anyhow::anyhow;
 use hbb_common::{
-    bail, chrono, log,
+    bail, chrono,
+    config::{keys, Config},
+    log,
     message_proto::{message, video_frame, EncodedVideoFrame, Message},
+    serde_derive::Serialize,
+    serde_json::{self, json},
     ResultType,
 };
 #[cfg(feature = "hwcodec")]
 use hwcodec::mux::{MuxContext, Muxer};
 use std::{
+    collections::{HashMap, HashSet},
     fs::{File, OpenOptions},
     io,
     ops::{Deref, DerefMut},
-    path::PathBuf,
-    sync::mpsc::Sender,
-    time::Instant,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::Sender,
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant, SystemTime},
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
 
 const MIN_SECS: u64 = 1;
+// opus is always decoded at 48k, and a decoder of 2 channels can decode mono packets too
+const AUDIO_SAMPLE_RATE: u32 = 48000;
+const AUDIO_CHANNELS: u8 = 2;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+// a marker not refreshed for this long is left by a process that exited without removing it
+const MARKER_TIMEOUT: Duration = Duration::from_secs(3 * 600);
+const MARKER_SUFFIX: &str = ".recording";
+
+static NEXT_LISTENER: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static::lazy_static! {
+    // the recorders receiving the audio frames of their sessions
+    static ref AUDIO_RECORDERS: Mutex<Vec<(RecordSession, Weak<Mutex<Option<Recorder>>>)>> = Default::default();
+    // the recorders of the displays of one session share a manifest, keyed by (server, id)
+    static ref MANIFESTS: Mutex<HashMap<(bool, String), Weak<Mutex<RecordManifest>>>> = Default::default();
+    // files being written or uploaded, with the number of users, never removed by the retention sweeper
+    static ref RECORDING: Mutex<HashMap<PathBuf, usize>> = Default::default();
+    // directories swept by the retention sweeper
+    static ref SWEEP_DIRS: Mutex<HashSet<PathBuf>> = Default::default();
+    // the uploaders of the recorders alive, told about the files removed by the retention sweeper
+    static ref PURGE_LISTENERS: Mutex<HashMap<usize, Sender<RecordState>>> = Default::default();
+}
+
+/// Source of the opus audio written to recordings, each has its own track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordAudio {
+    /// Audio captured on this side, e.g. the microphone in a voice call.
+    Local,
+    /// Audio received from the peer.
+    Peer,
+}
+
+/// The recordings an audio frame belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordSession {
+    /// The recordings of the incoming sessions of this device.
+    Incoming,
+    /// The recordings of the outgoing sessions to the peer of this id.
+    Outgoing(String),
+}
+
+impl RecordSession {
+    fn of(ctx: &RecorderContext) -> Self {
+        if ctx.server {
+            Self::Incoming
+        } else {
+            Self::Outgoing(ctx.id.clone())
+        }
+    }
+}
+
+/// Write an opus frame to the recordings of `session` in progress.
+pub fn write_audio(session: &RecordSession, source: RecordAudio, data: &[u8]) {
+    let recorders: Vec<_> = {
+        let mut lock = AUDIO_RECORDERS.lock().unwrap();
+        if lock.is_empty() {
+            return;
+        }
+        lock.retain(|(_, r)| r.strong_count() > 0);
+        lock.iter()
+            .filter(|(s, _)| s == session)
+            .filter_map(|(_, r)| r.upgrade())
+            .collect()
+    };
+    let now = Instant::now();
+    for recorder in recorders {
+        if let Some(recorder) = recorder.lock().unwrap().as_mut() {
+            recorder.write_audio_frame(source, now, data);
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct RecorderContext {
@@ -64,6 +144,123 @@ impl RecorderContext2 {
     }
 }
 
+/// Binds the files of all the displays recorded in a session to a common clock, so they can be
+/// played in sync. A file of a display covers the session time from `start` (ms), where its video
+/// timestamp is `first_pts`.
+///
+/// The manifest is written next to the videos, and rewritten whenever a new file is started.
+#[derive(Debug)]
+pub struct RecordManifest {
+    path: PathBuf,
+    id: String,
+    server: bool,
+    clock: Instant,
+    start_time: i64,
+    files: Vec<RecordManifestFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RecordManifestFile {
+    #[serde(skip)]
+    path: PathBuf,
+    display: usize,
+    // the name of the file, in the directory of the manifest
+    filename: String,
+    // the audio of an mp4 file
+    audio: Option<String>,
+    start: u64,
+    first_pts: i64,
+    width: usize,
+    height: usize,
+}
+
+impl RecordManifest {
+    fn get(ctx: &RecorderContext) -> Arc<Mutex<Self>> {
+        let mut manifests = MANIFESTS.lock().unwrap();
+        manifests.retain(|_, m| m.strong_count() > 0);
+        let key = (ctx.server, ctx.id.clone());
+        if let Some(manifest) = manifests.get(&key).and_then(|m| m.upgrade()) {
+            return manifest;
+        }
+        let file = if ctx.server { "incoming" } else { "outgoing" }.to_string()
+            + "_"
+            + &ctx.id
+            + &chrono::Local::now().format("_%Y%m%d%H%M%S%3f_").to_string()
+            + "manifest.json";
+        let path = PathBuf::from(&ctx.dir).join(file);
+        set_recording(&path.to_string_lossy(), true);
+        let manifest = Arc::new(Mutex::new(Self {
+            path,
+            id: ctx.id.clone(),
+            server: ctx.server,
+            clock: Instant::now(),
+            start_time: chrono::Utc::now().timestamp_millis(),
+            files: Vec::new(),
+        }));
+        manifests.insert(key, Arc::downgrade(&manifest));
+        manifest
+    }
+
+    fn add_file(&mut self, display: usize, ctx2: &RecorderContext2, time: Instant, pts: i64) {
+        let path = PathBuf::from(&ctx2.filename);
+        self.files.push(RecordManifestFile {
+            filename: file_name(&path).unwrap_or_default(),
+            path,
+            display,
+            audio: None,
+            start: time.saturating_duration_since(self.clock).as_millis() as u64,
+            first_pts: pts,
+            width: ctx2.width,
+            height: ctx2.height,
+        });
+        self.write();
+    }
+
+    fn write(&mut self) {
+        // the files which are too short are removed by the recorders
+        self.files.retain(|f| f.path.exists());
+        if self.files.is_empty() {
+            std::fs::remove_file(&self.path).ok();
+            return;
+        }
+        for f in self.files.iter_mut() {
+            let audio = PathBuf::from(audio_filename(&f.path.to_string_lossy()));
+            f.audio = if audio.exists() {
+                file_name(&audio)
+            } else {
+                None
+            };
+        }
+        let manifest = json!({
+            "id": self.id,
+            "server": self.server,
+            "start_time": self.start_time,
+            "files": self.files,
+        });
+        match serde_json::to_string_pretty(&manifest) {
+            Ok(s) => {
+                if let Err(e) = std::fs::write(&self.path, s) {
+                    log::error!("Failed to write record manifest: {}", e);
+                }
+            }
+            Err(e) => log::error!("Failed to serialize record manifest: {}", e),
+        }
+    }
+}
+
+fn file_name(path: &std::path::Path) -> Option<String> {
+    path.file_name().map(|x| x.to_string_lossy().to_string())
+}
+
+impl Drop for RecordManifest {
+    fn drop(&mut self) {
+        if !self.files.is_empty() {
+            self.write();
+        }
+        set_recording(&self.path.to_string_lossy(), false);
+    }
+}
+
 unsafe impl Send for Recorder {}
 unsafe impl Sync for Recorder {}
 
@@ -72,6 +269,9 @@ pub trait RecorderApi {
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    fn write_audio(&mut self, _source: RecordAudio, _data: &[u8], _timestamp_ns: u64) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -80,6 +280,224 @@ pub enum RecordState {
     NewFrame,
     WriteTail,
     RemoveFile,
+    // removed by the retention sweeper
+    PurgeFile(String),
+}
+
+fn marker_path(path: &Path) -> PathBuf {
+    let mut marker = path.as_os_str().to_owned();
+    marker.push(MARKER_SUFFIX);
+    PathBuf::from(marker)
+}
+
+fn is_marker(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(MARKER_SUFFIX)
+}
+
+// Create the marker of `path`, or refresh its modified time if it exists.
+fn touch_marker(path: &Path) {
+    let marker = marker_path(path);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&marker)
+        .and_then(|f| f.set_modified(SystemTime::now()));
+    if let Err(e) = file {
+        log::debug!("Failed to touch recording marker {:?}: {}", marker, e);
+    }
+}
+
+/// Mark a file in the video save directory as being written or uploaded, so the retention sweeper
+/// keeps it. Every `set_recording(path, true)` must be paired with a `set_recording(path, false)`.
+///
+/// The mark is also written on disk as `<file>.recording`, which the sweepers of the other
+/// processes sharing the directory respect while it is refreshed.
+pub fn set_recording(path: &str, recording: bool) {
+    let mut lock = RECORDING.lock().unwrap();
+    let path = PathBuf::from(path);
+    if recording {
+        let count = lock.entry(path.clone()).or_default();
+        *count += 1;
+        if *count == 1 {
+            touch_marker(&path);
+        }
+    } else if let Some(count) = lock.get_mut(&path) {
+        *count -= 1;
+        if *count == 0 {
+            lock.remove(&path);
+            std::fs::remove_file(marker_path(&path)).ok();
+        }
+    }
+}
+
+/// Limits of the recordings kept in the video save directory, 0 means unlimited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub max_age: Duration,
+    pub max_size: u64,
+    pub max_count: usize,
+}
+
+impl RetentionPolicy {
+    pub fn load() -> Self {
+        let get = |key: &str| Config::get_option(key).trim().parse::<u64>().unwrap_or(0);
+        Self {
+            max_age: Duration::from_secs(
+                get(keys::OPTION_RECORD_MAX_AGE_DAYS).saturating_mul(24 * 3600),
+            ),
+            max_size: get(keys::OPTION_RECORD_MAX_SIZE_MB).saturating_mul(1024 * 1024),
+            max_count: get(keys::OPTION_RECORD_MAX_COUNT) as _,
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age.is_zero() && self.max_size == 0 && self.max_count == 0
+    }
+
+    // files: (path, modified time, size, protected)
+    fn select(
+        &self,
+        mut files: Vec<(PathBuf, SystemTime, u64, bool)>,
+        now: SystemTime,
+    ) -> Vec<PathBuf> {
+        // oldest first
+        files.sort_by_key(|f| f.1);
+        let mut total_size: u64 = files.iter().map(|f| f.2).sum();
+        let mut count = files.len();
+        let mut removed = Vec::new();
+        for (path, modified, size, protected) in files {
+            if protected {
+                continue;
+            }
+            let expired = !self.max_age.is_zero()
+                && now.duration_since(modified).unwrap_or_default() > self.max_age;
+            let over_size = self.max_size > 0 && total_size > self.max_size;
+            let over_count = self.max_count > 0 && count > self.max_count;
+            if expired || over_size || over_count {
+                total_size -= size;
+                count -= 1;
+                removed.push(path);
+            }
+        }
+        removed
+    }
+}
+
+fn is_record_file(path: &Path) -> bool {
+    !is_marker(path)
+        && path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .map(|x| x.starts_with("incoming_") || x.starts_with("outgoing_"))
+            .unwrap_or_default()
+}
+
+// The files in `dir` marked on disk by a process that is alive, stale markers are removed.
+fn marked_files(dir: &Path) -> HashSet<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Default::default();
+    };
+    let recording = RECORDING.lock().unwrap();
+    let mut marked = HashSet::new();
+    for marker in entries.flatten().map(|e| e.path()).filter(|p| is_marker(p)) {
+        let path = PathBuf::from(
+            marker
+                .to_string_lossy()
+                .trim_end_matches(MARKER_SUFFIX)
+                .to_owned(),
+        );
+        let fresh = std::fs::metadata(&marker)
+            .and_then(|m| m.modified())
+            .map(|t| t.elapsed().unwrap_or_default() < MARKER_TIMEOUT)
+            .unwrap_or_default();
+        if fresh || recording.contains_key(&path) {
+            marked.insert(path);
+        } else {
+            log::info!("Removed stale recording marker {:?}", marker);
+            std::fs::remove_file(&marker).ok();
+        }
+    }
+    marked
+}
+
+/// Remove the recordings in `dir` exceeding the retention policy, return the removed files.
+pub fn sweep_records(dir: &Path, policy: &RetentionPolicy) -> Vec<PathBuf> {
+    if policy.is_unlimited() {
+        return vec![];
+    }
+    let marked = marked_files(dir);
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let files = {
+        let recording = RECORDING.lock().unwrap();
+        entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| is_record_file(p))
+            .filter_map(|p| {
+                let meta = std::fs::metadata(&p).ok()?;
+                if !meta.is_file() {
+                    return None;
+                }
+                let protected = recording.contains_key(&p) || marked.contains(&p);
+                Some((p, meta.modified().ok()?, meta.len(), protected))
+            })
+            .collect()
+    };
+    policy
+        .select(files, SystemTime::now())
+        .into_iter()
+        .filter(|p| match std::fs::remove_file(p) {
+            Ok(_) => {
+                log::info!("Removed expired recording {:?}", p);
+                true
+            }
+            Err(e) => {
+                log::error!("Failed to remove expired recording {:?}: {}", p, e);
+                false
+            }
+        })
+        .collect()
+}
+
+// Tell the uploaders that `path` is removed, dropping the ones whose thread has exited.
+fn notify_purged(path: &Path) {
+    let path = path.to_string_lossy().to_string();
+    PURGE_LISTENERS
+        .lock()
+        .unwrap()
+        .retain(|_, tx| tx.send(RecordState::PurgeFile(path.clone())).is_ok());
+}
+
+/// Sweep `dir` with the retention policy in the background. The sweeper is started by the first
+/// call, at the server start or by the first recorder, and sweeps all the directories watched by
+/// the process. It also keeps the on-disk markers of the files in use by the process fresh.
+pub fn watch_record_dir(dir: &str) {
+    if dir.is_empty() {
+        return;
+    }
+    let mut dirs = SWEEP_DIRS.lock().unwrap();
+    let start = dirs.is_empty();
+    dirs.insert(PathBuf::from(dir));
+    if !start {
+        return;
+    }
+    std::thread::spawn(|| loop {
+        // under the lock, not to recreate the marker of a file released meanwhile
+        for path in RECORDING.lock().unwrap().keys() {
+            touch_marker(path);
+        }
+        let policy = RetentionPolicy::load();
+        let dirs: Vec<PathBuf> = SWEEP_DIRS.lock().unwrap().iter().cloned().collect();
+        for dir in dirs {
+            for path in sweep_records(&dir, &policy) {
+                notify_purged(&path);
+            }
+        }
+        std::thread::sleep(SWEEP_INTERVAL);
+    });
 }
 
 pub struct Recorder {
@@ -88,6 +506,19 @@ pub struct Recorder {
     ctx2: Option<RecorderContext2>,
     pts: Option<i64>,
     check_failed: bool,
+    // (time, pts) of the first video frame of the file, which the audio is aligned to
+    clock: Option<(Instant, i64)>,
+    manifest: Arc<Mutex<RecordManifest>>,
+    // the key of the uploader in PURGE_LISTENERS
+    listener: Option<usize>,
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Some(listener) = self.listener {
+            PURGE_LISTENERS.lock().unwrap().remove(&listener);
+        }
+    }
 }
 
 impl Deref for Recorder {
@@ -106,15 +537,36 @@ impl DerefMut for Recorder {
 
 impl Recorder {
     pub fn new(ctx: RecorderContext) -> ResultType<Self> {
+        watch_record_dir(&ctx.dir);
+        let manifest = RecordManifest::get(&ctx);
+        let listener = ctx.tx.clone().map(|tx| {
+            let listener = NEXT_LISTENER.fetch_add(1, Ordering::Relaxed);
+            PURGE_LISTENERS.lock().unwrap().insert(listener, tx);
+            listener
+        });
         Ok(Self {
             inner: None,
             ctx,
             ctx2: None,
             pts: None,
             check_failed: false,
+            clock: None,
+            manifest,
+            listener,
         })
     }
 
+    /// Share the recorder with the audio of its session, which is written as soon as it arrives.
+    pub fn into_shared(self) -> Arc<Mutex<Option<Self>>> {
+        let session = RecordSession::of(&self.ctx);
+        let recorder = Arc::new(Mutex::new(Some(self)));
+        AUDIO_RECORDERS
+            .lock()
+            .unwrap()
+            .push((session, Arc::downgrade(&recorder)));
+        recorder
+    }
+
     fn check(&mut self, w: usize, h: usize, format: CodecFormat) -> ResultType<()> {
         match self.ctx2 {
             Some(ref ctx2) => {
@@ -160,6 +612,7 @@ impl Recorder {
             };
             // pts is None when new inner is created
             self.pts = None;
+            self.clock = None;
             self.send_state(RecordState::NewFile(ctx2.filename.clone()));
         }
         Ok(())
@@ -257,16 +710,105 @@ impl Recorder {
             }
             self.pts = Some(pts);
         }
+        if self.clock.is_none() {
+            let now = Instant::now();
+            self.clock = Some((now, pts));
+            if let Some(ctx2) = &self.ctx2 {
+                self.manifest
+                    .lock()
+                    .unwrap()
+                    .add_file(self.ctx.display, ctx2, now, pts);
+            }
+        }
         Ok(())
     }
 
+    fn write_audio_frame(&mut self, source: RecordAudio, time: Instant, data: &[u8]) {
+        let Some((clock, pts)) = self.clock else {
+            return;
+        };
+        // captured before the file starts
+        let Some(elapsed) = time.checked_duration_since(clock) else {
+            return;
+        };
+        let timestamp_ns = pts as u64 * 1_000_000 + elapsed.as_nanos() as u64;
+        self.as_mut()
+            .map(|x| x.write_audio(source, data, timestamp_ns));
+    }
+
     fn send_state(&self, state: RecordState) {
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
 
+// https://www.rfc-editor.org/rfc/rfc7845#section-5.1
+fn opus_head() -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(AUDIO_CHANNELS);
+    head.extend(0u16.to_le_bytes()); // pre-skip
+    head.extend(AUDIO_SAMPLE_RATE.to_le_bytes());
+    head.extend(0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family
+    head
+}
+
+fn new_webm(filename: &str) -> ResultType<Segment<Writer<File>>> {
+    let out = match {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(filename)
+    } {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => File::create(filename)?,
+        Err(e) => return Err(e.into()),
+    };
+    match mux::Segment::new(mux::Writer::new(out)) {
+        Some(v) => Ok(v),
+        None => bail!("Failed to create webm mux"),
+    }
+}
+
+// The opus tracks of the local and the peer audio.
+struct AudioTracks {
+    // (source, track, timestamp of the last frame)
+    tracks: Vec<(RecordAudio, AudioTrack, Option<u64>)>,
+}
+
+impl AudioTracks {
+    fn new(webm: &mut Segment<Writer<File>>) -> ResultType<Self> {
+        let mut tracks = Vec::new();
+        for source in [RecordAudio::Local, RecordAudio::Peer] {
+            let at = webm.add_audio_track(
+                AUDIO_SAMPLE_RATE as _,
+                AUDIO_CHANNELS as _,
+                None,
+                mux::AudioCodecId::Opus,
+            );
+            if !webm.set_codec_private(at.track_number(), &opus_head()) {
+                bail!("Failed to set codec private of audio");
+            }
+            tracks.push((source, at, None));
+        }
+        Ok(Self { tracks })
+    }
+
+    fn write(&mut self, source: RecordAudio, data: &[u8], timestamp_ns: u64) -> bool {
+        let Some((_, at, last)) = self.tracks.iter_mut().find(|x| x.0 == source) else {
+            return false;
+        };
+        if last.map_or(false, |last| timestamp_ns <= last) {
+            return false;
+        }
+        *last = Some(timestamp_ns);
+        at.add_frame(data, timestamp_ns, true)
+    }
+}
+
 struct WebmRecorder {
     vt: VideoTrack,
+    audio_tracks: AudioTracks,
     webm: Option<Segment<Writer<File>>>,
     ctx: RecorderContext,
     ctx2: RecorderContext2,
@@ -277,20 +819,7 @@ struct WebmRecorder {
 
 impl RecorderApi for WebmRecorder {
     fn new(ctx: RecorderContext, ctx2: RecorderContext2) -> ResultType<Self> {
-        let out = match {
-            OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(&ctx2.filename)
-        } {
-            Ok(file) => file,
-            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => File::create(&ctx2.filename)?,
-            Err(e) => return Err(e.into()),
-        };
-        let mut webm = match mux::Segment::new(mux::Writer::new(out)) {
-            Some(v) => v,
-            None => bail!("Failed to create webm mux"),
-        };
+        let mut webm = new_webm(&ctx2.filename)?;
         let vt = webm.add_video_track(
             ctx2.width as _,
             ctx2.height as _,
@@ -310,8 +839,11 @@ impl RecorderApi for WebmRecorder {
                 bail!("Failed to set codec private");
             }
         }
+        let audio_tracks = AudioTracks::new(&mut webm)?;
+        set_recording(&ctx2.filename, true);
         Ok(WebmRecorder {
             vt,
+            audio_tracks,
             webm: Some(webm),
             ctx,
             ctx2,
@@ -337,11 +869,19 @@ impl RecorderApi for WebmRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, source: RecordAudio, data: &[u8], timestamp_ns: u64) -> bool {
+        if !self.key {
+            return false;
+        }
+        self.audio_tracks.write(source, data, timestamp_ns)
+    }
 }
 
 impl Drop for WebmRecorder {
     fn drop(&mut self) {
         let _ = std::mem::replace(&mut self.webm, None).map_or(false, |webm| webm.finalize(None));
+        set_recording(&self.ctx2.filename, false);
         let mut state = RecordState::WriteTail;
         if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
             std::fs::remove_file(&self.ctx2.filename).ok();
@@ -351,6 +891,14 @@ impl Drop for WebmRecorder {
     }
 }
 
+/// The file next to an mp4 recording with its audio, the hardware muxer only writes video.
+pub fn audio_filename(filename: &str) -> String {
+    PathBuf::from(filename)
+        .with_extension("audio.webm")
+        .to_string_lossy()
+        .to_string()
+}
+
 #[cfg(feature = "hwcodec")]
 struct HwRecorder {
     muxer: Option<Muxer>,
@@ -359,6 +907,10 @@ struct HwRecorder {
     written: bool,
     key: bool,
     start: Instant,
+    // the audio file, whose timestamps start at the first video frame
+    audio: Option<(Segment<Writer<File>>, AudioTracks)>,
+    audio_written: bool,
+    first_pts: Option<i64>,
 }
 
 #[cfg(feature = "hwcodec")]
@@ -372,6 +924,21 @@ impl RecorderApi for HwRecorder {
             framerate: crate::hwcodec::DEFAULT_FPS as _,
         })
         .map_err(|_| anyhow!("Failed to create hardware muxer"))?;
+        set_recording(&ctx2.filename, true);
+        let audio_filename = audio_filename(&ctx2.filename);
+        let audio = match new_webm(&audio_filename).and_then(|mut webm| {
+            let tracks = AudioTracks::new(&mut webm)?;
+            Ok((webm, tracks))
+        }) {
+            Ok(audio) => {
+                set_recording(&audio_filename, true);
+                Some(audio)
+            }
+            Err(e) => {
+                log::error!("Failed to create the audio file of the recording: {}", e);
+                None
+            }
+        };
         Ok(HwRecorder {
             muxer: Some(muxer),
             ctx,
@@ -379,6 +946,9 @@ impl RecorderApi for HwRecorder {
             written: false,
             key: false,
             start: Instant::now(),
+            audio,
+            audio_written: false,
+            first_pts: None,
         })
     }
 
@@ -394,25 +964,161 @@ impl RecorderApi for HwRecorder {
                 .unwrap_or_default();
             if ok {
                 self.written = true;
+                self.first_pts.get_or_insert(frame.pts);
             }
             ok
         } else {
             false
         }
     }
+
+    fn write_audio(&mut self, source: RecordAudio, data: &[u8], timestamp_ns: u64) -> bool {
+        let (Some(first_pts), Some((_, tracks))) = (self.first_pts, self.audio.as_mut()) else {
+            return false;
+        };
+        let timestamp_ns = timestamp_ns.saturating_sub(first_pts as u64 * 1_000_000);
+        let ok = tracks.write(source, data, timestamp_ns);
+        if ok {
+            self.audio_written = true;
+        }
+        ok
+    }
 }
 
 #[cfg(feature = "hwcodec")]
 impl Drop for HwRecorder {
     fn drop(&mut self) {
         self.muxer.as_mut().map(|m| m.write_tail().ok());
+        set_recording(&self.ctx2.filename, false);
+        if let Some((webm, _)) = self.audio.take() {
+            webm.finalize(None);
+            let audio_filename = audio_filename(&self.ctx2.filename);
+            set_recording(&audio_filename, false);
+            if !self.audio_written {
+                std::fs::remove_file(&audio_filename).ok();
+            }
+        }
         let mut state = RecordState::WriteTail;
         if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
             // The process cannot access the file because it is being used by another process
             self.muxer = None;
             std::fs::remove_file(&self.ctx2.filename).ok();
+            std::fs::remove_file(audio_filename(&self.ctx2.filename)).ok();
             state = RecordState::RemoveFile;
         }
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_filename() {
+        let audio = audio_filename("/records/incoming_123_20240101_display0_h264.mp4");
+        assert_eq!(
+            audio,
+            "/records/incoming_123_20240101_display0_h264.audio.webm"
+        );
+        // swept with its video
+        assert!(is_record_file(std::path::Path::new(&audio)));
+    }
+
+    #[test]
+    fn test_retention_select() {
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 3600);
+        let files = |protected: bool| {
+            (0..5)
+                .map(|i| {
+                    (
+                        PathBuf::from(format!("incoming_{}.webm", i)),
+                        now - day * i,
+                        100,
+                        protected && i == 4,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let names = |v: Vec<PathBuf>| {
+            let mut v: Vec<_> = v.iter().map(|p| p.to_string_lossy().to_string()).collect();
+            v.sort();
+            v
+        };
+        let policy = RetentionPolicy::default();
+        assert!(policy.select(files(false), now).is_empty());
+        let policy = RetentionPolicy {
+            max_age: day * 2 + Duration::from_secs(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(policy.select(files(false), now)),
+            vec!["incoming_3.webm", "incoming_4.webm"]
+        );
+        let policy = RetentionPolicy {
+            max_size: 250,
+            ..Default::default()
+        };
+        assert_eq!(
+            names(policy.select(files(false), now)),
+            vec!["incoming_2.webm", "incoming_3.webm", "incoming_4.webm"]
+        );
+        let policy = RetentionPolicy {
+            max_count: 3,
+            ..Default::default()
+        };
+        // the oldest file is being written
+        assert_eq!(
+            names(policy.select(files(true), now)),
+            vec!["incoming_2.webm", "incoming_3.webm"]
+        );
+    }
+
+    #[test]
+    fn test_set_recording() {
+        // the recorder and the uploader
+        let path = "/records/incoming_test_set_recording.webm";
+        let recording = || RECORDING.lock().unwrap().contains_key(&PathBuf::from(path));
+        set_recording(path, true);
+        set_recording(path, true);
+        set_recording(path, false);
+        assert!(recording());
+        set_recording(path, false);
+        assert!(!recording());
+        set_recording(path, false);
+        assert!(!recording());
+    }
+
+    #[test]
+    fn test_sweep_marked() {
+        let dir = std::env::temp_dir().join(format!("test_sweep_marked_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(3600);
+        let create = |name: &str, age: u32| {
+            let path = dir.join(name);
+            File::create(&path)
+                .unwrap()
+                .set_modified(now - hour * age)
+                .unwrap();
+            path
+        };
+        // written by another process, the oldest one
+        let marked = create("incoming_a.webm", 3);
+        create("incoming_a.webm.recording", 0);
+        let old = create("incoming_b.webm", 2);
+        let new = create("incoming_c.webm", 1);
+        // left by a process which exited
+        let stale = create("incoming_d.webm.recording", 5);
+        let policy = RetentionPolicy {
+            max_count: 2,
+            ..Default::default()
+        };
+        assert_eq!(sweep_records(&dir, &policy), vec![old]);
+        assert!(marked.exists());
+        assert!(new.exists());
+        assert!(!stale.exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -8,6 +8,7 @@ use hbb_common::message_proto::{Chroma, EncodedVideoFrame, EncodedVideoFrames, V
 use hbb_common::ResultType;
 
 use crate::codec::{base_bitrate, codec_thread_num, EncoderApi, Quality};
+use crate::damage::{active_map, DamageRect, PendingDamage};
 use crate::{EncodeInput, EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
 
 use super::vpx::{vp8e_enc_control_id::*, vpx_codec_err_t::*, *};
@@ -17,6 +18,7 @@ use std::os::raw::{c_int, c_uint};
 use std::{ptr, slice};
 
 generate_call_macro!(call_vpx, false);
+generate_call_macro!(call_vpx_allow_err, true);
 generate_call_ptr_macro!(call_vpx_ptr);
 
 const DEFAULT_QP_MAX: u32 = 56; // no more than 63
@@ -41,6 +43,9 @@ pub struct VpxEncoder {
     id: VpxVideoCodecId,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    // None if the active map is not supported, otherwise whether it is set
+    active_map: Option<bool>,
+    damage: PendingDamage,
 }
 
 pub struct VpxDecoder {
@@ -177,6 +182,8 @@ impl EncoderApi for VpxEncoder {
                     id: config.codec,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    active_map: Some(false),
+                    damage: Default::default(),
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -197,6 +204,7 @@ impl EncoderApi for VpxEncoder {
 
         // to-do: flush periodically, e.g. 1 second
         if frames.len() > 0 {
+            self.damage.emitted();
             Ok(VpxEncoder::create_video_frame(self.id, frames))
         } else {
             Err(anyhow!("no valid frame"))
@@ -248,6 +256,34 @@ impl EncoderApi for VpxEncoder {
     }
 
     fn disable(&self) {}
+
+    fn set_damage(&mut self, damage: Option<&[DamageRect]>) {
+        let Some(set) = self.active_map else {
+            return;
+        };
+        let damage = self.damage.add(damage);
+        if damage.is_none() && !set {
+            return;
+        }
+        // in 16x16 macroblocks for both VP8 and VP9, key frames ignore it
+        let mut map = damage.map(|d| active_map(d, self.width, self.height, 16));
+        let known = damage.is_some();
+        let mut vpx_map = vpx_active_map_t {
+            active_map: map.as_mut().map_or(ptr::null_mut(), |m| m.0.as_mut_ptr()),
+            rows: map.as_ref().map_or(0, |m| m.1 as _),
+            cols: map.as_ref().map_or(0, |m| m.2 as _),
+        };
+        let ret = call_vpx_allow_err!(vpx_codec_control_(
+            &mut self.ctx,
+            VP8E_SET_ACTIVEMAP as _,
+            &mut vpx_map as *mut vpx_active_map_t
+        ));
+        self.active_map = if ret == VPX_CODEC_OK {
+            Some(known)
+        } else {
+            None
+        };
+    }
 }
 
 impl VpxEncoder {
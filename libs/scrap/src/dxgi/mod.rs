@@ -23,7 +23,7 @@ use winapi::{
 
 use crate::RotationMode::*;
 
-use crate::{AdapterDevice, Frame, PixelBuffer};
+use crate::{damage::DamageRect, AdapterDevice, Frame, PixelBuffer};
 use std::ffi::c_void;
 
 pub struct ComPtr<T>(*mut T);
@@ -327,7 +327,10 @@ impl Capturer {
         self.output_texture = texture;
     }
 
-    unsafe fn load_frame(&mut self, timeout: UINT) -> io::Result<(*const u8, i32)> {
+    unsafe fn load_frame(
+        &mut self,
+        timeout: UINT,
+    ) -> io::Result<(*const u8, i32, Option<Vec<DamageRect>>)> {
         let mut frame = ptr::null_mut();
         #[allow(invalid_value)]
         let mut info = mem::MaybeUninit::uninit().assume_init();
@@ -338,6 +341,11 @@ impl Capturer {
         if *info.LastPresentTime.QuadPart() == 0 {
             return Err(std::io::ErrorKind::WouldBlock.into());
         }
+        let damage = if info.TotalMetadataBufferSize > 0 {
+            self.frame_damage(info.TotalMetadataBufferSize).ok()
+        } else {
+            None
+        };
 
         #[allow(invalid_value)]
         let mut rect = mem::MaybeUninit::uninit().assume_init();
@@ -347,7 +355,40 @@ impl Capturer {
             self.surface = ComPtr(self.ohgodwhat(frame.0)?);
             wrap_hresult((*self.surface.0).Map(&mut rect, DXGI_MAP_READ))?;
         }
-        Ok((rect.pBits, rect.Pitch))
+        Ok((rect.pBits, rect.Pitch, damage))
+    }
+
+    // The dirty rects and the destinations of the move rects of the acquired frame.
+    unsafe fn frame_damage(&mut self, metadata_size: UINT) -> io::Result<Vec<DamageRect>> {
+        let mut required = 0;
+        let mut moves: Vec<DXGI_OUTDUPL_MOVE_RECT> = Vec::with_capacity(
+            metadata_size as usize / mem::size_of::<DXGI_OUTDUPL_MOVE_RECT>() + 1,
+        );
+        wrap_hresult((*self.duplication.0).GetFrameMoveRects(
+            (moves.capacity() * mem::size_of::<DXGI_OUTDUPL_MOVE_RECT>()) as _,
+            moves.as_mut_ptr(),
+            &mut required,
+        ))?;
+        moves.set_len(required as usize / mem::size_of::<DXGI_OUTDUPL_MOVE_RECT>());
+        let mut dirty: Vec<RECT> =
+            Vec::with_capacity(metadata_size as usize / mem::size_of::<RECT>() + 1);
+        wrap_hresult((*self.duplication.0).GetFrameDirtyRects(
+            (dirty.capacity() * mem::size_of::<RECT>()) as _,
+            dirty.as_mut_ptr(),
+            &mut required,
+        ))?;
+        dirty.set_len(required as usize / mem::size_of::<RECT>());
+        Ok(moves
+            .iter()
+            .map(|m| &m.DestinationRect)
+            .chain(dirty.iter())
+            .map(|r| DamageRect {
+                x: r.left.max(0) as _,
+                y: r.top.max(0) as _,
+                w: (r.right - r.left.max(0)).max(0) as _,
+                h: (r.bottom - r.top.max(0)).max(0) as _,
+            })
+            .collect())
     }
 
     // copy from GPU memory to system memory
@@ -394,15 +435,17 @@ impl Capturer {
         } else {
             let width = self.width;
             let height = self.height;
-            Ok(Frame::PixelBuffer(PixelBuffer::new(
-                self.get_pixelbuffer(timeout)?,
-                width,
-                height,
-            )))
+            let (data, damage) = self.get_pixelbuffer(timeout)?;
+            Ok(Frame::PixelBuffer(
+                PixelBuffer::new(data, width, height).with_damage(damage),
+            ))
         }
     }
 
-    fn get_pixelbuffer<'a>(&'a mut self, timeout: UINT) -> io::Result<&'a [u8]> {
+    fn get_pixelbuffer<'a>(
+        &'a mut self,
+        timeout: UINT,
+    ) -> io::Result<(&'a [u8], Option<Vec<DamageRect>>)> {
         unsafe {
             // Release last frame.
             // No error checking needed because we don't care.
@@ -411,11 +454,14 @@ impl Capturer {
                 if let Some(gdi_capturer) = &self.gdi_capturer {
                     match gdi_capturer.frame(&mut self.gdi_buffer) {
                         Ok(_) => {
-                            crate::would_block_if_equal(
+                            let damage = crate::damage::diff_or_would_block(
                                 &mut self.saved_raw_data,
                                 &self.gdi_buffer,
+                                self.width,
+                                self.height,
+                                4,
                             )?;
-                            &self.gdi_buffer
+                            (&self.gdi_buffer[..], damage)
                         }
                         Err(err) => {
                             return Err(io::Error::new(io::ErrorKind::Other, err.to_string()));
@@ -437,7 +483,7 @@ impl Capturer {
                         }
                     };
                     if rotate == kRotate0 {
-                        slice::from_raw_parts(r.0, r.1 as usize * self.height)
+                        (slice::from_raw_parts(r.0, r.1 as usize * self.height), r.2)
                     } else {
                         self.rotated.resize(self.width * self.height * 4, 0);
                         crate::common::ARGBRotate(
@@ -457,7 +503,8 @@ impl Capturer {
                             } as _,
                             rotate,
                         );
-                        &self.rotated[..]
+                        // the damage is in the coordinates before rotation
+                        (&self.rotated[..], None)
                     }
                 }
             };
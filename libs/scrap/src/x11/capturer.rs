@@ -1,5 +1,6 @@
 use super::ffi::*;
 use super::Display;
+use crate::damage::DamageRect;
 use hbb_common::libc;
 use std::{io, ptr, slice};
 
@@ -93,11 +94,18 @@ impl Capturer {
         }
     }
 
-    pub fn frame<'b>(&'b mut self) -> std::io::Result<&'b [u8]> {
+    pub fn frame<'b>(&'b mut self) -> std::io::Result<(&'b [u8], Option<Vec<DamageRect>>)> {
         self.get_image();
         let result = unsafe { slice::from_raw_parts(self.buffer, self.size) };
-        crate::would_block_if_equal(&mut self.saved_raw_data, result)?;
-        Ok(result)
+        let rect = self.display.rect();
+        let damage = crate::damage::diff_or_would_block(
+            &mut self.saved_raw_data,
+            result,
+            rect.w as _,
+            rect.h as _,
+            self.display.pixfmt().bytes_per_pixel(),
+        )?;
+        Ok((result, damage))
     }
 }
 
@@ -1,13 +1,15 @@
 use hbb_common::{
     anyhow::anyhow,
     bail,
-    config::Config,
-    get_time,
+    config::{self, keys, Config},
+    get_time, log,
+    message_proto::Auth2FA,
     password_security::{decrypt_vec_or_original, encrypt_vec_or_original},
+    sodiumoxide::{base64, crypto::sign},
     ResultType,
 };
 use serde_derive::{Deserialize, Serialize};
-use std::sync::Mutex;
+use std::{path::PathBuf, sync::Mutex};
 use totp_rs::{Algorithm, Secret, TOTP};
 
 lazy_static::lazy_static! {
@@ -111,6 +113,129 @@ pub fn get_2fa(raw: Option<String>) -> Option<TOTP> {
         .unwrap_or_default()
 }
 
+// Security keys, the controlled side sends a nonce in `Auth2FA` and the controller answers with
+// the signature of its software authenticator, whose public key is in "2fa-security-keys".
+
+const AUTHENTICATOR_FILE: &str = "authenticator.toml";
+const NONCE_LENGTH: usize = 32;
+
+pub fn get_security_keys() -> Vec<Vec<u8>> {
+    parse_security_keys(&Config::get_option(keys::OPTION_2FA_SECURITY_KEYS))
+}
+
+// One "ed25519 <base64 public key> [comment]" per line
+fn parse_security_keys(s: &str) -> Vec<Vec<u8>> {
+    s.lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            if tokens.next()? != "ed25519" {
+                return None;
+            }
+            let pk = base64::decode(tokens.next()?, base64::Variant::Original).ok()?;
+            (pk.len() == sign::PUBLICKEYBYTES).then_some(pk)
+        })
+        .collect()
+}
+
+pub fn new_security_key_nonce() -> String {
+    Config::get_auto_password(NONCE_LENGTH)
+}
+
+// The signature covers the id of the peer too, so that it can not be relayed to another peer.
+fn security_key_data(nonce: &str, peer_id: &str) -> Vec<u8> {
+    [b"2fa\0", nonce.as_bytes(), b"\0", peer_id.as_bytes()].concat()
+}
+
+// `None` if the key is not one of ours, it is no answer rather than a wrong one.
+pub fn verify_security_key(nonce: &str, peer_id: &str, tfa: &Auth2FA) -> Option<bool> {
+    verify_listed_security_key(&get_security_keys(), nonce, peer_id, tfa)
+}
+
+fn verify_listed_security_key(
+    keys: &[Vec<u8>],
+    nonce: &str,
+    peer_id: &str,
+    tfa: &Auth2FA,
+) -> Option<bool> {
+    if !keys.iter().any(|pk| pk[..] == tfa.pk[..]) {
+        return None;
+    }
+    Some(!nonce.is_empty() && verify_signature(nonce, peer_id, tfa))
+}
+
+fn verify_signature(nonce: &str, peer_id: &str, tfa: &Auth2FA) -> bool {
+    let Some(pk) = sign::PublicKey::from_slice(&tfa.pk) else {
+        return false;
+    };
+    let Ok(signature) = sign::Signature::try_from(&tfa.signature[..]) else {
+        return false;
+    };
+    sign::verify_detached(&signature, &security_key_data(nonce, peer_id), &pk)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SoftwareAuthenticator {
+    secret: Vec<u8>,
+    public: Vec<u8>,
+    created_at: i64,
+}
+
+impl SoftwareAuthenticator {
+    fn path() -> PathBuf {
+        Config::path(AUTHENTICATOR_FILE)
+    }
+
+    fn load() -> Option<sign::SecretKey> {
+        let auth: SoftwareAuthenticator = config::load_path(Self::path());
+        if auth.secret.is_empty() {
+            return None;
+        }
+        let (secret, success, _) = decrypt_vec_or_original(&auth.secret, "00");
+        if !success {
+            log::error!("decrypt_vec_or_original authenticator secret failed");
+            return None;
+        }
+        sign::SecretKey::from_slice(&secret)
+    }
+
+    fn create() -> ResultType<sign::SecretKey> {
+        let (pk, sk) = sign::gen_keypair();
+        let auth = SoftwareAuthenticator {
+            secret: encrypt_vec_or_original(&sk.0, "00", 1024),
+            public: pk.0.to_vec(),
+            created_at: get_time(),
+        };
+        config::store_path(Self::path(), auth)?;
+        Ok(sk)
+    }
+}
+
+// The line to add to "2fa-security-keys" of the peers, the authenticator is created on first use.
+pub fn get_authenticator_public_key() -> ResultType<String> {
+    let sk = match SoftwareAuthenticator::load() {
+        Some(sk) => sk,
+        None => SoftwareAuthenticator::create()?,
+    };
+    Ok(format!(
+        "ed25519 {} {}",
+        base64::encode(sk.public_key().0, base64::Variant::Original),
+        crate::username()
+    ))
+}
+
+pub fn answer_security_key(nonce: &str, peer_id: &str) -> Option<Auth2FA> {
+    if nonce.is_empty() {
+        return None;
+    }
+    let sk = SoftwareAuthenticator::load()?;
+    let signature = sign::sign_detached(&security_key_data(nonce, peer_id), &sk);
+    Some(Auth2FA {
+        pk: sk.public_key().0.to_vec().into(),
+        signature: signature.to_bytes().to_vec().into(),
+        ..Default::default()
+    })
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TelegramBot {
     #[serde(skip)]
@@ -202,3 +327,39 @@ pub fn get_chatid_telegram(bot_token: &str) -> ResultType<Option<String>> {
 
     Ok(chat_id)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_security_key() {
+        let (pk, sk) = sign::gen_keypair();
+        let line = format!(
+            "ed25519 {} laptop",
+            base64::encode(pk.0, base64::Variant::Original)
+        );
+        let keys = parse_security_keys(&format!("{line}\nrsa AAAA\ned25519 AAAA"));
+        assert_eq!(keys, vec![pk.0.to_vec()]);
+
+        let signature = sign::sign_detached(&security_key_data("nonce", "123456789"), &sk);
+        let verify = |nonce, peer_id| {
+            sign::verify_detached(&signature, &security_key_data(nonce, peer_id), &pk)
+        };
+        assert!(verify("nonce", "123456789"));
+        assert!(!verify("nonce", "987654321"));
+        assert!(!verify("other", "123456789"));
+
+        let tfa = Auth2FA {
+            pk: pk.0.to_vec().into(),
+            signature: signature.to_bytes().to_vec().into(),
+            ..Default::default()
+        };
+        let verify =
+            |keys: &[Vec<u8>], nonce| verify_listed_security_key(keys, nonce, "123456789", &tfa);
+        assert_eq!(verify(&keys, "nonce"), Some(true));
+        assert_eq!(verify(&keys, "other"), Some(false));
+        assert_eq!(verify(&keys, ""), Some(false));
+        assert_eq!(verify(&[], "nonce"), None);
+    }
+}
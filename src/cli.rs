@@ -1,17 +1,33 @@
 use crate::client::*;
 use async_trait::async_trait;
 use hbb_common::{
+    allow_err, bail,
     config::PeerConfig,
     config::READ_TIMEOUT,
+    fs::{self, can_enable_overwrite_detection, get_string, DigestCheckResult},
     futures::{SinkExt, StreamExt},
     log,
     message_proto::*,
-    protobuf::Message as _,
+    protobuf::{Message as _, MessageField},
     rendezvous_proto::ConnType,
-    tokio::{self, sync::mpsc},
-    Stream,
+    timeout,
+    tokio::{self, sync::mpsc, time},
+    ResultType, Stream,
 };
-use std::sync::{Arc, RwLock};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+/// Exit codes of the headless file transfer.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONNECT: i32 = 3;
+pub const EXIT_LOGIN: i32 = 4;
+
+const JOB_ID: i32 = 1;
 
 #[derive(Clone)]
 pub struct Session {
@@ -19,27 +35,44 @@ pub struct Session {
     lc: Arc<RwLock<LoginConfigHandler>>,
     sender: mpsc::UnboundedSender<Data>,
     password: String,
+    // whether the password can be asked again on the terminal
+    interactive: bool,
+    // the 2FA code given on the command line, only tried once
+    tfa_code: Arc<Mutex<Option<String>>>,
 }
 
 impl Session {
-    pub fn new(id: &str, sender: mpsc::UnboundedSender<Data>) -> Self {
-        let mut password = "".to_owned();
-        if PeerConfig::load(id).password.is_empty() {
-            password = rpassword::prompt_password("Enter password: ").unwrap();
-        }
+    pub fn new(
+        id: &str,
+        conn_type: ConnType,
+        password: Option<String>,
+        tfa_code: Option<String>,
+        sender: mpsc::UnboundedSender<Data>,
+    ) -> Self {
+        let interactive = password.is_none();
+        let password = match password {
+            Some(password) => password,
+            None if PeerConfig::load(id).password.is_empty() => {
+                rpassword::prompt_password("Enter password: ").unwrap()
+            }
+            None => "".to_owned(),
+        };
         let session = Self {
             id: id.to_owned(),
             sender,
             password,
+            interactive,
+            tfa_code: Arc::new(Mutex::new(tfa_code)),
             lc: Default::default(),
         };
         session.lc.write().unwrap().initialize(
             id.to_owned(),
-            ConnType::PORT_FORWARD,
+            conn_type,
             None,
             false,
             None,
             None,
+            None,
         );
         session
     }
@@ -47,7 +80,7 @@ impl Session {
 
 #[async_trait]
 impl Interface for Session {
-    fn get_login_config_handler(&self) -> Arc<RwLock<LoginConfigHandler>> {
+    fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
         return self.lc.clone();
     }
 
@@ -55,14 +88,24 @@ impl Interface for Session {
         match msgtype {
             "input-password" => {
                 self.sender
-                    .send(Data::Login((self.password.clone(), true)))
+                    .send(Data::Login((
+                        "".to_owned(),
+                        "".to_owned(),
+                        self.password.clone(),
+                        true,
+                    )))
                     .ok();
             }
+            "re-input-password" if !self.interactive => {
+                eprintln!("{}: {}", title, text);
+                self.sender.send(Data::Close).ok();
+            }
             "re-input-password" => {
-                log::error!("{}: {}", title, text);
+                eprintln!("{}: {}", title, text);
                 match rpassword::prompt_password("Enter password: ") {
                     Ok(password) => {
-                        let login_data = Data::Login((password, true));
+                        let login_data =
+                            Data::Login(("".to_owned(), "".to_owned(), password, true));
                         self.sender.send(login_data).ok();
                     }
                     Err(e) => {
@@ -70,8 +113,29 @@ impl Interface for Session {
                     }
                 }
             }
+            "input-2fa" => {
+                // the code of the command line is tried once, then it is asked on the terminal
+                let code = self.tfa_code.lock().unwrap().take().or_else(|| {
+                    eprintln!("{}", title);
+                    if self.interactive {
+                        rpassword::prompt_password("Enter 2FA code: ").ok()
+                    } else {
+                        None
+                    }
+                });
+                if let Some(code) = code {
+                    let mut msg_out = Message::new();
+                    msg_out.set_auth_2fa(Auth2FA {
+                        code,
+                        ..Default::default()
+                    });
+                    self.sender.send(Data::Message(msg_out)).ok();
+                } else {
+                    self.sender.send(Data::Close).ok();
+                }
+            }
             msg if msg.contains("error") => {
-                log::error!("{}: {}: {}", msgtype, title, text);
+                eprintln!("{}: {}: {}", msgtype, title, text);
             }
             _ => {
                 log::info!("{}: {}: {}", msgtype, title, text);
@@ -87,6 +151,8 @@ impl Interface for Session {
         self.lc.write().unwrap().handle_peer_info(&pi);
     }
 
+    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
     async fn handle_hash(&self, pass: &str, hash: Hash, peer: &mut Stream) {
         log::info!(
             "password={}",
@@ -126,12 +192,12 @@ impl Interface for Session {
 #[tokio::main(flavor = "current_thread")]
 pub async fn connect_test(id: &str, key: String, token: String) {
     let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
+    let handler = Session::new(&id, ConnType::PORT_FORWARD, None, None, sender);
     match crate::client::Client::start(id, &key, &token, ConnType::PORT_FORWARD, handler).await {
         Err(err) => {
             log::error!("Failed to connect {}: {}", &id, err);
         }
-        Ok((mut stream, direct)) => {
+        Ok(((mut stream, direct, _pk), _)) => {
             log::info!("direct: {}", direct);
             // rpassword::prompt_password("Input anything to exit").ok();
             loop {
@@ -166,13 +232,15 @@ pub async fn start_one_port_forward(
     port: i32,
     remote_host: String,
     remote_port: i32,
+    mode: crate::port_forward::ForwardMode,
+    bind_address: String,
     key: String,
     token: String,
 ) {
     crate::common::test_rendezvous_server();
     crate::common::test_nat_type();
     let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
+    let handler = Session::new(&id, ConnType::PORT_FORWARD, None, None, sender);
     if let Err(err) = crate::port_forward::listen(
         handler.id.clone(),
         handler.password.clone(),
@@ -184,6 +252,8 @@ pub async fn start_one_port_forward(
         handler.lc.clone(),
         remote_host,
         remote_port,
+        mode,
+        bind_address,
     )
     .await
     {
@@ -191,3 +261,661 @@ pub async fn start_one_port_forward(
     }
     log::info!("port forward (:{}) exit", port);
 }
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_one_reverse_port_forward(
+    id: String,
+    remote_port: i32,
+    local_host: String,
+    local_port: i32,
+    key: String,
+    token: String,
+) {
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, ConnType::PORT_FORWARD, None, None, sender);
+    if let Err(err) = crate::port_forward::listen_reverse(
+        handler.id.clone(),
+        handler.password.clone(),
+        remote_port,
+        handler.clone(),
+        receiver,
+        &key,
+        &token,
+        handler.lc.clone(),
+        local_host,
+        local_port,
+    )
+    .await
+    {
+        log::error!("Reverse port forward of {} failed: {}", remote_port, err);
+    }
+    log::info!("reverse port forward (:{}) exit", remote_port);
+}
+
+/// Forward all the rules over one connection to the peer, a rule which fails to listen does not
+/// stop the others.
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_port_forward_rules(
+    id: String,
+    rules: Vec<crate::port_forward::PortForwardRule>,
+    key: String,
+    token: String,
+) {
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, ConnType::PORT_FORWARD, None, None, sender);
+    if let Err(err) = crate::port_forward::listen_rules(
+        handler.id.clone(),
+        handler.password.clone(),
+        rules,
+        handler.clone(),
+        receiver,
+        &key,
+        &token,
+        handler.lc.clone(),
+    )
+    .await
+    {
+        log::error!("Port forward rules failed: {}", err);
+    }
+    log::info!("port forward rules exit");
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileTransferCommand {
+    Ls(String),
+    /// remote path, local path
+    Get(String, String),
+    /// local path, remote path
+    Put(String, String),
+    Rm(String),
+}
+
+impl FileTransferCommand {
+    pub fn parse(args: &[&str]) -> Option<Self> {
+        match args {
+            ["ls", path] => Some(Self::Ls(path.to_string())),
+            ["get", remote, local] => Some(Self::Get(remote.to_string(), local.to_string())),
+            ["put", local, remote] => Some(Self::Put(local.to_string(), remote.to_string())),
+            ["rm", path] => Some(Self::Rm(path.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Run one file transfer command against the peer without any UI, returns the exit code.
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_file_transfer(
+    id: String,
+    password: Option<String>,
+    tfa_code: Option<String>,
+    command: FileTransferCommand,
+    show_hidden: bool,
+    key: String,
+    token: String,
+) -> i32 {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, ConnType::FILE_TRANSFER, password, tfa_code, sender);
+    let res = connect_and_login(&handler, &mut receiver, &key, &token).await;
+    if let Err(err) = &res {
+        eprintln!("Failed to connect {}: {}", &id, err);
+    }
+    let (mut stream, pi) = match res {
+        Ok(Some(res)) => res,
+        res => return login_exit_code(&res),
+    };
+    let od = can_enable_overwrite_detection(handler.lc.read().unwrap().version);
+    let is_windows_peer = pi.platform == "Windows";
+    let res = match command {
+        FileTransferCommand::Ls(path) => ls(&mut stream, path, show_hidden).await,
+        FileTransferCommand::Get(remote, local) => {
+            get(&mut stream, remote, local, show_hidden, od, is_windows_peer).await
+        }
+        FileTransferCommand::Put(local, remote) => {
+            put(&mut stream, local, remote, show_hidden, od, is_windows_peer).await
+        }
+        FileTransferCommand::Rm(path) => rm(&mut stream, path, is_windows_peer).await,
+    };
+    if let Err(err) = &res {
+        eprintln!("File transfer failed: {}", err);
+    }
+    exit_code(&res)
+}
+
+// `Ok(None)` if the login is refused or canceled.
+fn login_exit_code<T>(res: &ResultType<Option<T>>) -> i32 {
+    match res {
+        Ok(Some(_)) => EXIT_OK,
+        Ok(None) => EXIT_LOGIN,
+        Err(_) => EXIT_CONNECT,
+    }
+}
+
+fn exit_code(res: &ResultType<()>) -> i32 {
+    match res {
+        Ok(()) => EXIT_OK,
+        Err(_) => EXIT_FAILURE,
+    }
+}
+
+async fn connect_and_login(
+    handler: &Session,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+) -> ResultType<Option<(Stream, PeerInfo)>> {
+    let ((mut stream, direct, _pk), (feedback, rendezvous_server)) = Client::start(
+        &handler.id,
+        key,
+        token,
+        ConnType::FILE_TRANSFER,
+        handler.clone(),
+    )
+    .await?;
+    log::info!("direct: {}", direct);
+    let _keep_it = hc_connection(feedback, rendezvous_server, token).await;
+    loop {
+        tokio::select! {
+            res = timeout(READ_TIMEOUT, stream.next()) => match res {
+                Err(_) => {
+                    bail!("Timeout");
+                }
+                Ok(Some(Ok(bytes))) => {
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::Hash(hash)) => {
+                            handler.handle_hash(&handler.password, hash, &mut stream).await;
+                        }
+                        Some(message::Union::LoginResponse(lr)) => match lr.union {
+                            Some(login_response::Union::Error(err)) => {
+                                if !handler.handle_login_error(&err) {
+                                    return Ok(None);
+                                }
+                            }
+                            Some(login_response::Union::PeerInfo(pi)) => {
+                                handler.handle_peer_info(pi.clone());
+                                return Ok(Some((stream, pi)));
+                            }
+                            _ => {}
+                        }
+                        Some(message::Union::TestDelay(t)) => {
+                            handler.handle_test_delay(t, &mut stream).await;
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(Some(Err(err))) => {
+                    bail!("Connection closed: {}", err);
+                }
+                _ => {
+                    bail!("Reset by the peer");
+                }
+            },
+            d = receiver.recv() => {
+                match d {
+                    Some(Data::Login((os_username, os_password, password, remember))) => {
+                        handler.handle_login_from_ui(os_username, os_password, password, remember, &mut stream).await;
+                    }
+                    Some(Data::Message(msg)) => {
+                        allow_err!(stream.send(&msg).await);
+                    }
+                    Some(Data::Close) | None => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// The next file response of the peer, test delay is answered on the way.
+async fn next_file_response(stream: &mut Stream) -> ResultType<file_response::Union> {
+    loop {
+        let msg_in = match timeout(READ_TIMEOUT, stream.next()).await? {
+            Some(Ok(bytes)) => Message::parse_from_bytes(&bytes)?,
+            Some(Err(err)) => bail!("Connection closed: {}", err),
+            None => bail!("Reset by the peer"),
+        };
+        match msg_in.union {
+            Some(message::Union::FileResponse(fr)) => {
+                if let Some(union) = fr.union {
+                    return Ok(union);
+                }
+            }
+            Some(message::Union::TestDelay(t)) => {
+                handle_test_delay(t, stream).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn send_file_action(stream: &mut Stream, action: FileAction) -> ResultType<()> {
+    let mut msg_out = Message::new();
+    msg_out.set_file_action(action);
+    stream.send(&msg_out).await
+}
+
+async fn wait_done(stream: &mut Stream, id: i32) -> ResultType<()> {
+    loop {
+        match next_file_response(stream).await? {
+            file_response::Union::Done(d) if d.id == id => return Ok(()),
+            file_response::Union::Error(e) if e.id == id => bail!("{}", e.error),
+            _ => {}
+        }
+    }
+}
+
+async fn ls(stream: &mut Stream, path: String, show_hidden: bool) -> ResultType<()> {
+    let mut action = FileAction::new();
+    action.set_read_dir(ReadDir {
+        path,
+        include_hidden: show_hidden,
+        ..Default::default()
+    });
+    send_file_action(stream, action).await?;
+    loop {
+        match next_file_response(stream).await? {
+            file_response::Union::Dir(fd) => {
+                for entry in fd.entries.iter() {
+                    let entry_type = match entry.entry_type.enum_value() {
+                        Ok(FileType::Dir) | Ok(FileType::DirDrive) => "d",
+                        Ok(FileType::DirLink) | Ok(FileType::FileLink) => "l",
+                        _ => "-",
+                    };
+                    let modified_time = chrono::DateTime::<chrono::Local>::from(
+                        UNIX_EPOCH + Duration::from_secs(entry.modified_time),
+                    );
+                    println!(
+                        "{} {:>14} {} {}",
+                        entry_type,
+                        entry.size,
+                        modified_time.format("%Y-%m-%d %H:%M"),
+                        entry.name
+                    );
+                }
+                return Ok(());
+            }
+            file_response::Union::Error(e) => bail!("{}", e.error),
+            _ => {}
+        }
+    }
+}
+
+struct Progress {
+    last: Instant,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Self {
+            last: Instant::now(),
+        }
+    }
+
+    fn update(&mut self, job: &fs::TransferJob, force: bool) {
+        if !force && self.last.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+        self.last = Instant::now();
+        let total_size = job.total_size().max(1);
+        eprintln!(
+            "{}/{} bytes ({}%), file {}/{}",
+            job.finished_size(),
+            job.total_size(),
+            job.finished_size().min(total_size) * 100 / total_size,
+            (job.file_num() + 1).min(job.files().len() as i32),
+            job.files().len()
+        );
+    }
+}
+
+async fn get(
+    stream: &mut Stream,
+    remote: String,
+    local: String,
+    show_hidden: bool,
+    od: bool,
+    is_windows_peer: bool,
+) -> ResultType<()> {
+    let mut job = fs::TransferJob::new_write(
+        JOB_ID,
+        remote.clone(),
+        local.clone(),
+        0,
+        show_hidden,
+        true,
+        Vec::new(),
+        od,
+    );
+    stream
+        .send(&fs::new_send(JOB_ID, remote.clone(), 0, show_hidden))
+        .await?;
+    let mut progress = Progress::new();
+    loop {
+        match next_file_response(stream).await? {
+            file_response::Union::Dir(fd) if fd.id == JOB_ID => {
+                let mut entries = fd.entries.to_vec();
+                if cfg!(not(windows)) && is_windows_peer {
+                    fs::transform_windows_path(&mut entries);
+                }
+                // a single file is listed with an empty name, keep its name under a local directory
+                if entries.len() == 1 && entries[0].name.is_empty() && Path::new(&local).is_dir() {
+                    let name = remote
+                        .rsplit(|c| c == '/' || c == '\\')
+                        .next()
+                        .unwrap_or_default();
+                    job.path = Path::new(&local).join(name);
+                }
+                job.set_files(entries);
+            }
+            file_response::Union::Digest(digest) if digest.id == JOB_ID => {
+                let path = match job.files().get(digest.file_num as usize) {
+                    Some(file) => get_string(&job.join(&file.name)),
+                    None => continue,
+                };
+                let mut req = FileTransferSendConfirmRequest {
+                    id: digest.id,
+                    file_num: digest.file_num,
+                    ..Default::default()
+                };
+                match fs::is_write_need_confirmation(&path, &digest)? {
+                    DigestCheckResult::IsSame => {
+                        eprintln!("{} is identical, skipped", path);
+                        req.set_skip(true);
+                    }
+                    DigestCheckResult::NeedConfirm(_) => {
+                        if digest.can_delta {
+                            req.signatures =
+                                MessageField::from_option(fs::get_block_signatures(path).await);
+                        }
+                        req.set_offset_blk(0);
+                    }
+                    DigestCheckResult::NoSuchFile => {
+                        req.set_offset_blk(job.prepare_resume(&digest));
+                    }
+                }
+                job.confirm(&req);
+                stream.send(&fs::new_send_confirm(req)).await?;
+            }
+            file_response::Union::Block(block) if block.id == JOB_ID => {
+                job.write(block).await?;
+                progress.update(&job, false);
+            }
+            file_response::Union::Done(d) if d.id == JOB_ID => {
+                job.modify_time();
+                progress.update(&job, true);
+                return Ok(());
+            }
+            file_response::Union::Error(e) if e.id == JOB_ID => {
+                bail!("{}", e.error);
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn put(
+    stream: &mut Stream,
+    local: String,
+    remote: String,
+    show_hidden: bool,
+    od: bool,
+    is_windows_peer: bool,
+) -> ResultType<()> {
+    let mut job = fs::TransferJob::new_read(
+        JOB_ID,
+        remote.clone(),
+        local.clone(),
+        0,
+        show_hidden,
+        false,
+        od,
+    )?;
+    let mut files = job.files().clone();
+    // a single file is listed with an empty name, keep its name under a remote directory
+    let remote = if is_single_file(&files) && is_remote_dir(stream, &remote).await? {
+        let sep = if is_windows_peer { "\\" } else { "/" };
+        remote_file_path(&remote, &local, sep)
+    } else {
+        remote
+    };
+    if cfg!(windows) && !is_windows_peer {
+        fs::transform_windows_path(&mut files);
+    }
+    stream
+        .send(&fs::new_receive(JOB_ID, remote, 0, files, job.total_size()))
+        .await?;
+    let mut progress = Progress::new();
+    let mut timer = crate::rustdesk_interval(time::interval(MILLI1));
+    let mut sent_done = false;
+    loop {
+        tokio::select! {
+            res = stream.next() => {
+                let msg_in = match res {
+                    Some(Ok(bytes)) => Message::parse_from_bytes(&bytes)?,
+                    Some(Err(err)) => bail!("Connection closed: {}", err),
+                    None => bail!("Reset by the peer"),
+                };
+                match msg_in.union {
+                    Some(message::Union::FileAction(action)) => {
+                        if let Some(file_action::Union::SendConfirm(c)) = action.union {
+                            job.confirm(&c);
+                        }
+                    }
+                    Some(message::Union::FileResponse(fr)) => match fr.union {
+                        Some(file_response::Union::Digest(digest)) if digest.id == JOB_ID => {
+                            // the file exists on the peer, overwrite it
+                            if digest.signatures.is_some() {
+                                // the answer to `request_signatures`
+                                job.confirm(&FileTransferSendConfirmRequest {
+                                    id: digest.id,
+                                    file_num: digest.file_num,
+                                    union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+                                    signatures: digest.signatures,
+                                    ..Default::default()
+                                });
+                            } else if digest.can_delta {
+                                let msg = fs::new_request_signatures(digest.id, digest.file_num);
+                                allow_err!(stream.send(&msg).await);
+                            } else {
+                                let req = FileTransferSendConfirmRequest {
+                                    id: digest.id,
+                                    file_num: digest.file_num,
+                                    union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+                                    ..Default::default()
+                                };
+                                job.confirm(&req);
+                                allow_err!(stream.send(&fs::new_send_confirm(req)).await);
+                            }
+                        }
+                        Some(file_response::Union::Done(d)) if d.id == JOB_ID => {
+                            progress.update(&job, true);
+                            return Ok(());
+                        }
+                        Some(file_response::Union::Error(e)) if e.id == JOB_ID => {
+                            bail!("{}", e.error);
+                        }
+                        _ => {}
+                    },
+                    Some(message::Union::TestDelay(t)) => {
+                        handle_test_delay(t, stream).await;
+                    }
+                    _ => {}
+                }
+            }
+            _ = timer.tick(), if !sent_done => {
+                match job.read(stream).await {
+                    Err(err) => {
+                        stream.send(&fs::new_error(JOB_ID, &err, job.file_num())).await?;
+                        bail!("{}", err);
+                    }
+                    Ok(Some(block)) => {
+                        stream.send(&fs::new_block(block)).await?;
+                        progress.update(&job, false);
+                    }
+                    Ok(None) => {
+                        if job.job_completed() {
+                            stream.send(&fs::new_done(JOB_ID, job.file_num())).await?;
+                            sent_done = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[inline]
+fn is_single_file(entries: &[FileEntry]) -> bool {
+    entries.len() == 1 && entries[0].name.is_empty()
+}
+
+// Whether `path` is an existing directory on the peer.
+async fn is_remote_dir(stream: &mut Stream, path: &str) -> ResultType<bool> {
+    let mut action = FileAction::new();
+    action.set_all_files(ReadAllFiles {
+        id: JOB_ID,
+        path: path.to_owned(),
+        include_hidden: true,
+        ..Default::default()
+    });
+    send_file_action(stream, action).await?;
+    loop {
+        match next_file_response(stream).await? {
+            file_response::Union::Dir(fd) if fd.id == JOB_ID => {
+                return Ok(!is_single_file(&fd.entries))
+            }
+            // no such file or directory
+            file_response::Union::Error(e) if e.id == JOB_ID => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+// The path of the local file under the remote directory.
+fn remote_file_path(remote_dir: &str, local: &str, sep: &str) -> String {
+    let name = Path::new(local)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    format!("{}{}{}", remote_dir.trim_end_matches(sep), sep, name)
+}
+
+async fn rm(stream: &mut Stream, path: String, is_windows_peer: bool) -> ResultType<()> {
+    let mut action = FileAction::new();
+    action.set_all_files(ReadAllFiles {
+        id: JOB_ID,
+        path: path.clone(),
+        include_hidden: true,
+        ..Default::default()
+    });
+    send_file_action(stream, action).await?;
+    let entries = loop {
+        match next_file_response(stream).await? {
+            file_response::Union::Dir(fd) if fd.id == JOB_ID => break fd.entries,
+            file_response::Union::Error(e) if e.id == JOB_ID => bail!("{}", e.error),
+            _ => {}
+        }
+    };
+    let is_file = is_single_file(&entries);
+    let sep = if is_windows_peer { "\\" } else { "/" };
+    for (i, entry) in entries.iter().enumerate() {
+        let file_path = if is_file {
+            path.clone()
+        } else {
+            format!("{}{}{}", path.trim_end_matches(sep), sep, entry.name)
+        };
+        let mut action = FileAction::new();
+        action.set_remove_file(FileRemoveFile {
+            id: JOB_ID,
+            path: file_path.clone(),
+            file_num: i as _,
+            ..Default::default()
+        });
+        send_file_action(stream, action).await?;
+        wait_done(stream, JOB_ID).await?;
+        eprintln!("removed {}", file_path);
+    }
+    if !is_file {
+        let mut action = FileAction::new();
+        action.set_remove_dir(FileRemoveDir {
+            id: JOB_ID,
+            path: path.clone(),
+            recursive: true,
+            ..Default::default()
+        });
+        send_file_action(stream, action).await?;
+        wait_done(stream, JOB_ID).await?;
+        eprintln!("removed {}", path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_transfer_command() {
+        assert_eq!(
+            FileTransferCommand::parse(&["ls", "/tmp"]),
+            Some(FileTransferCommand::Ls("/tmp".to_owned()))
+        );
+        assert_eq!(
+            FileTransferCommand::parse(&["get", "/remote/a", "a"]),
+            Some(FileTransferCommand::Get(
+                "/remote/a".to_owned(),
+                "a".to_owned()
+            ))
+        );
+        assert_eq!(
+            FileTransferCommand::parse(&["put", "a", "/remote/a"]),
+            Some(FileTransferCommand::Put(
+                "a".to_owned(),
+                "/remote/a".to_owned()
+            ))
+        );
+        assert_eq!(
+            FileTransferCommand::parse(&["rm", "/remote/a"]),
+            Some(FileTransferCommand::Rm("/remote/a".to_owned()))
+        );
+        assert_eq!(FileTransferCommand::parse(&[]), None);
+        assert_eq!(FileTransferCommand::parse(&["ls"]), None);
+        assert_eq!(FileTransferCommand::parse(&["get", "/remote/a"]), None);
+        assert_eq!(FileTransferCommand::parse(&["rm", "a", "b"]), None);
+        assert_eq!(FileTransferCommand::parse(&["cp", "a", "b"]), None);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(login_exit_code(&Ok(Some(()))), EXIT_OK);
+        assert_eq!(login_exit_code::<()>(&Ok(None)), EXIT_LOGIN);
+        assert_eq!(
+            login_exit_code::<()>(&Err(hbb_common::anyhow::anyhow!("timeout"))),
+            EXIT_CONNECT
+        );
+        assert_eq!(exit_code(&Ok(())), EXIT_OK);
+        assert_eq!(
+            exit_code(&Err(hbb_common::anyhow::anyhow!("denied"))),
+            EXIT_FAILURE
+        );
+    }
+
+    #[test]
+    fn test_remote_file_path() {
+        assert_eq!(
+            remote_file_path("/remote", "dir/a.txt", "/"),
+            "/remote/a.txt"
+        );
+        assert_eq!(remote_file_path("/remote/", "a.txt", "/"), "/remote/a.txt");
+        assert_eq!(
+            remote_file_path("C:\\remote\\", "a.txt", "\\"),
+            "C:\\remote\\a.txt"
+        );
+    }
+}
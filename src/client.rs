@@ -61,6 +61,7 @@ use hbb_common::{
 pub use helper::*;
 use scrap::{
     codec::Decoder,
+    lossless::LosslessOverlay,
     record::{Recorder, RecorderContext},
     CodecFormat, ImageFormat, ImageRgb, ImageTexture,
 };
@@ -110,6 +111,7 @@ pub const LOGIN_MSG_2FA_WRONG: &str = "Wrong 2FA Code";
 pub const REQUIRE_2FA: &'static str = "2FA Required";
 pub const LOGIN_MSG_NO_PASSWORD_ACCESS: &str = "No Password Access";
 pub const LOGIN_MSG_OFFLINE: &str = "Offline";
+pub const LOGIN_MSG_OUTSIDE_ACCESS_SCHEDULE: &str = "Access is not allowed at this time";
 pub const LOGIN_SCREEN_WAYLAND: &str = "Wayland login screen is not supported";
 #[cfg(target_os = "linux")]
 pub const SCRAP_UBUNTU_HIGHER_REQUIRED: &str = "Wayland requires Ubuntu 21.04 or higher version.";
@@ -1237,6 +1239,9 @@ pub struct VideoHandler {
     _display: usize, // useful for debug
     fail_counter: usize,
     first_frame: bool,
+    lossless: LosslessOverlay,
+    // whether the latest decoded frame is in `rgb`, the lossless tiles are drawn only there
+    rgb_decoded: bool,
 }
 
 impl VideoHandler {
@@ -1269,6 +1274,8 @@ impl VideoHandler {
             _display,
             fail_counter: 0,
             first_frame: true,
+            lossless: Default::default(),
+            rgb_decoded: false,
         }
     }
 
@@ -1280,6 +1287,13 @@ impl VideoHandler {
         pixelbuffer: &mut bool,
         chroma: &mut Option<Chroma>,
     ) -> ResultType<bool> {
+        if let Some(video_frame::Union::Rgb(rgb)) = &vf.union {
+            if !self.rgb_decoded {
+                return Ok(false);
+            }
+            *pixelbuffer = true;
+            return Ok(self.lossless.handle_rgb(rgb, &mut self.rgb));
+        }
         let format = CodecFormat::from(&vf);
         if format != self.decoder.format() {
             self.reset(Some(format));
@@ -1295,6 +1309,13 @@ impl VideoHandler {
                 );
                 if res.as_ref().is_ok_and(|x| *x) {
                     self.fail_counter = 0;
+                    self.rgb_decoded = *pixelbuffer;
+                    if *pixelbuffer {
+                        if scrap::lossless::is_key_frame(&vf) {
+                            self.lossless.clear();
+                        }
+                        self.lossless.draw(&mut self.rgb);
+                    }
                 } else {
                     if self.fail_counter < usize::MAX {
                         if self.first_frame && self.fail_counter < MAX_DECODE_FAIL_COUNTER {
@@ -1339,6 +1360,8 @@ impl VideoHandler {
         self.decoder = Decoder::new(format, luid);
         self.fail_counter = 0;
         self.first_frame = true;
+        self.lossless.clear();
+        self.rgb_decoded = false;
     }
 
     /// Start or stop screen record.
@@ -1352,7 +1375,7 @@ impl VideoHandler {
                 display,
                 tx: None,
             })
-            .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))));
+            .map_or(Default::default(), Recorder::into_shared);
         } else {
             self.recorder = Default::default();
         }
@@ -1425,6 +1448,10 @@ pub struct LoginConfigHandler {
     pub remember: bool,
     config: PeerConfig,
     pub port_forward: (String, i32),
+    // the port to listen on the peer side, for reverse port forward
+    pub reverse_port_forward: Option<i32>,
+    // the connections of several forward rules are opened as tunnels of one connection
+    pub port_forward_tunnels: bool,
     pub version: i64,
     features: Option<Features>,
     pub session_id: u64, // used for local <-> server communication
@@ -1445,6 +1472,8 @@ pub struct LoginConfigHandler {
     password_source: PasswordSource, // where the sent password comes from
     shared_password: Option<String>, // Store the shared password
     pub enable_trusted_devices: bool,
+    // the second factor is answered by the software authenticator
+    pub security_key_answered: bool,
     pub record_state: bool,
     pub record_permission: bool,
 }
@@ -1686,6 +1715,17 @@ impl LoginConfigHandler {
         self.save_config(config);
     }
 
+    /// Remember the key of the peer verified with the rendezvous server, which the lan discovery
+    /// binds the pongs of the peer to.
+    pub fn set_verified_pk(&mut self, pk: &[u8]) {
+        let pk = crate::common::encode64(pk);
+        let mut config = self.load_config();
+        if config.pk != pk {
+            config.pk = pk;
+            self.save_config(config);
+        }
+    }
+
     /// Get a ui config of flutter for handler's [`PeerConfig`].
     /// Return String if the option is found, otherwise return "".
     ///
@@ -2231,6 +2271,35 @@ impl LoginConfigHandler {
         serde_json::to_string::<HashMap<String, String>>(&x).unwrap_or_default()
     }
 
+    // The peer skips the password if our key is in its authorized keys. Only sent to the peers
+    // it is enabled for, the key identifies us.
+    fn public_key_auth(&self, peer_id: &str) -> Option<PublicKeyAuth> {
+        if self.hash.challenge.is_empty()
+            || self.get_option(config::keys::OPTION_PUBLIC_KEY_AUTH) != "Y"
+        {
+            return None;
+        }
+        let (sk, pk) = LocalConfig::get_controller_key_pair();
+        let signature = hbb_common::password_security::sign_login_challenge(
+            &sk,
+            &self.hash.challenge,
+            peer_id,
+        )?;
+        Some(PublicKeyAuth {
+            pk: pk.into(),
+            signature: signature.into(),
+            ..Default::default()
+        })
+    }
+
+    pub fn answer_security_key(&self, nonce: &str) -> Option<Auth2FA> {
+        let peer_id = match self.other_server.as_ref() {
+            Some((id, _, _)) => id,
+            None => &self.id,
+        };
+        crate::auth_2fa::answer_security_key(nonce, peer_id)
+    }
+
     /// Create a [`Message`] for login.
     fn create_login_msg(
         &self,
@@ -2272,6 +2341,7 @@ impl LoginConfigHandler {
         } else {
             Bytes::new()
         };
+        let public_key_auth = self.public_key_auth(&pure_id);
         let mut lr = LoginRequest {
             username: pure_id,
             password: password.into(),
@@ -2288,6 +2358,7 @@ impl LoginConfigHandler {
             })
             .into(),
             hwid,
+            public_key_auth: public_key_auth.into(),
             ..Default::default()
         };
         match self.conn_type {
@@ -2296,11 +2367,21 @@ impl LoginConfigHandler {
                 show_hidden: !self.get_option("remote_show_hidden").is_empty(),
                 ..Default::default()
             }),
-            ConnType::PORT_FORWARD | ConnType::RDP => lr.set_port_forward(PortForward {
-                host: self.port_forward.0.clone(),
-                port: self.port_forward.1,
-                ..Default::default()
-            }),
+            ConnType::PORT_FORWARD | ConnType::RDP => {
+                if let Some(port) = self.reverse_port_forward {
+                    lr.set_reverse_port_forward(ReversePortForward {
+                        port,
+                        ..Default::default()
+                    })
+                } else {
+                    lr.set_port_forward(PortForward {
+                        host: self.port_forward.0.clone(),
+                        port: self.port_forward.1,
+                        tunnels: self.port_forward_tunnels,
+                        ..Default::default()
+                    })
+                }
+            }
             _ => {}
         }
 
@@ -2388,136 +2469,159 @@ pub fn start_video_thread<F, T>(
         let mut count = 0;
         let mut duration = std::time::Duration::ZERO;
         let mut skip_beginning = 0;
+        let mut jitter_buffer = JitterBuffer::default();
+        let get_jitter_target = || {
+            let lc = session.lc.read().unwrap();
+            JitterBuffer::target(
+                lc.get_toggle_option(config::keys::OPTION_LOW_LATENCY_MODE),
+                &lc.get_option(config::keys::OPTION_JITTER_BUFFER_TARGET),
+            )
+        };
+        // reloaded periodically rather than per frame, the options change rarely
+        let mut jitter_target = get_jitter_target();
+        let mut jitter_target_time = std::time::Instant::now();
         loop {
-            if let Ok(data) = video_receiver.recv() {
-                match data {
-                    MediaData::VideoFrame(_) | MediaData::VideoQueue => {
-                        let vf = match data {
-                            MediaData::VideoFrame(vf) => {
-                                *discard_queue.write().unwrap() = false;
-                                *vf
-                            }
-                            MediaData::VideoQueue => {
-                                if let Some(vf) = video_queue.read().unwrap().pop() {
-                                    if discard_queue.read().unwrap().clone() {
-                                        continue;
-                                    }
-                                    vf
-                                } else {
-                                    continue;
-                                }
-                            }
-                            _ => {
-                                // unreachable!();
-                                continue;
-                            }
-                        };
-                        let display = vf.display as usize;
-                        let start = std::time::Instant::now();
-                        let format = CodecFormat::from(&vf);
-                        if video_handler.is_none() {
-                            let mut handler = VideoHandler::new(format, display);
-                            let record_state = session.lc.read().unwrap().record_state;
-                            let record_permission = session.lc.read().unwrap().record_permission;
-                            let id = session.lc.read().unwrap().id.clone();
-                            if record_state && record_permission {
-                                handler.record_screen(true, id, display);
-                            }
-                            video_handler = Some(handler);
+            let data = match jitter_buffer.next_due() {
+                Some(due) => {
+                    let timeout = due.saturating_duration_since(std::time::Instant::now());
+                    match video_receiver.recv_timeout(timeout) {
+                        Ok(data) => Some(data),
+                        Err(mpsc::RecvTimeoutError::Timeout) => None,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match video_receiver.recv() {
+                    Ok(data) => Some(data),
+                    Err(_) => break,
+                },
+            };
+            if jitter_target_time.elapsed() >= std::time::Duration::from_secs(1) {
+                jitter_target = get_jitter_target();
+                jitter_target_time = std::time::Instant::now();
+            }
+            match data {
+                Some(MediaData::VideoFrame(vf)) => {
+                    *discard_queue.write().unwrap() = false;
+                    jitter_buffer.push(*vf, jitter_target, std::time::Instant::now());
+                }
+                Some(MediaData::VideoQueue) => {
+                    if let Some(vf) = video_queue.read().unwrap().pop() {
+                        if discard_queue.read().unwrap().clone() {
+                            continue;
                         }
-                        if let Some(handler) = video_handler.as_mut() {
-                            let mut pixelbuffer = true;
-                            let mut tmp_chroma = None;
-                            let format_changed = handler.decoder.format() != format;
-                            match handler.handle_frame(vf, &mut pixelbuffer, &mut tmp_chroma) {
-                                Ok(true) => {
-                                    video_callback(
-                                        display,
-                                        &mut handler.rgb,
-                                        handler.texture.texture,
-                                        pixelbuffer,
-                                    );
-
-                                    // chroma
-                                    if tmp_chroma.is_some() && last_chroma != tmp_chroma {
-                                        last_chroma = tmp_chroma;
-                                        *chroma.write().unwrap() = tmp_chroma;
-                                    }
-
-                                    // fps calculation
-                                    fps_calculate(
-                                        &mut skip_beginning,
-                                        &fps,
-                                        format_changed,
-                                        start.elapsed(),
-                                        &mut count,
-                                        &mut duration,
-                                    );
-                                }
-                                Err(e) => {
-                                    // This is a simple workaround.
-                                    //
-                                    // I only see the following error:
-                                    // FailedCall("errcode=1 scrap::common::vpxcodec:libs\\scrap\\src\\common\\vpxcodec.rs:433:9")
-                                    // When switching from all displays to one display, the error occurs.
-                                    // eg:
-                                    // 1. Connect to a device with two displays (A and B).
-                                    // 2. Switch to display A. The error occurs.
-                                    // 3. If the error does not occur. Switch from A to display B. The error occurs.
-                                    //
-                                    // to-do: fix the error
-                                    log::error!("handle video frame error, {}", e);
-                                    session.refresh_video(display as _);
-                                }
-                                _ => {}
+                        jitter_buffer.push(vf, jitter_target, std::time::Instant::now());
+                    }
+                }
+                Some(MediaData::Reset) => {
+                    jitter_buffer.clear();
+                    if let Some(handler) = video_handler.as_mut() {
+                        handler.reset(None);
+                    }
+                }
+                Some(MediaData::RecordScreen(start)) => {
+                    let id = session.lc.read().unwrap().id.clone();
+                    if let Some(handler) = video_handler.as_mut() {
+                        handler.record_screen(start, id, display);
+                    }
+                }
+                _ => {}
+            }
+            while let Some(vf) = jitter_buffer.pop(std::time::Instant::now()) {
+                let display = vf.display as usize;
+                let start = std::time::Instant::now();
+                // lossless tiles are drawn over the decoded frames, they are not frames
+                let tiles = matches!(vf.union, Some(video_frame::Union::Rgb(_)));
+                if tiles && video_handler.is_none() {
+                    continue;
+                }
+                let format = CodecFormat::from(&vf);
+                if video_handler.is_none() {
+                    let mut handler = VideoHandler::new(format, display);
+                    let record_state = session.lc.read().unwrap().record_state;
+                    let record_permission = session.lc.read().unwrap().record_permission;
+                    let id = session.lc.read().unwrap().id.clone();
+                    if record_state && record_permission {
+                        handler.record_screen(true, id, display);
+                    }
+                    video_handler = Some(handler);
+                }
+                if let Some(handler) = video_handler.as_mut() {
+                    let mut pixelbuffer = true;
+                    let mut tmp_chroma = None;
+                    let format_changed = !tiles && handler.decoder.format() != format;
+                    match handler.handle_frame(vf, &mut pixelbuffer, &mut tmp_chroma) {
+                        Ok(true) => {
+                            video_callback(
+                                display,
+                                &mut handler.rgb,
+                                handler.texture.texture,
+                                pixelbuffer,
+                            );
+
+                            // chroma
+                            if tmp_chroma.is_some() && last_chroma != tmp_chroma {
+                                last_chroma = tmp_chroma;
+                                *chroma.write().unwrap() = tmp_chroma;
                             }
-                        }
 
-                        // check invalid decoders
-                        let mut should_update_supported = false;
-                        if let Some(handler) = video_handler.as_mut() {
-                            if !handler.decoder.valid()
-                                || handler.fail_counter >= MAX_DECODE_FAIL_COUNTER
-                            {
-                                let mut lc = session.lc.write().unwrap();
-                                let format = handler.decoder.format();
-                                if !lc.mark_unsupported.contains(&format) {
-                                    lc.mark_unsupported.push(format);
-                                    should_update_supported = true;
-                                    log::info!("mark {format:?} decoder as unsupported, valid:{}, fail_counter:{}, all unsupported:{:?}", handler.decoder.valid(), handler.fail_counter, lc.mark_unsupported);
-                                }
+                            // fps calculation
+                            if !tiles {
+                                fps_calculate(
+                                    &mut skip_beginning,
+                                    &fps,
+                                    format_changed,
+                                    start.elapsed(),
+                                    &mut count,
+                                    &mut duration,
+                                );
                             }
                         }
-                        if should_update_supported {
-                            session.send(Data::Message(
-                                session.lc.read().unwrap().update_supported_decodings(),
-                            ));
-                        }
-                    }
-                    MediaData::Reset => {
-                        if let Some(handler) = video_handler.as_mut() {
-                            handler.reset(None);
+                        Err(e) => {
+                            // This is a simple workaround.
+                            //
+                            // I only see the following error:
+                            // FailedCall("errcode=1 scrap::common::vpxcodec:libs\\scrap\\src\\common\\vpxcodec.rs:433:9")
+                            // When switching from all displays to one display, the error occurs.
+                            // eg:
+                            // 1. Connect to a device with two displays (A and B).
+                            // 2. Switch to display A. The error occurs.
+                            // 3. If the error does not occur. Switch from A to display B. The error occurs.
+                            //
+                            // to-do: fix the error
+                            log::error!("handle video frame error, {}", e);
+                            session.refresh_video(display as _);
                         }
+                        _ => {}
                     }
-                    MediaData::RecordScreen(start) => {
-                        let id = session.lc.read().unwrap().id.clone();
-                        if let Some(handler) = video_handler.as_mut() {
-                            handler.record_screen(start, id, display);
+                }
+
+                // check invalid decoders
+                let mut should_update_supported = false;
+                if let Some(handler) = video_handler.as_mut() {
+                    if !handler.decoder.valid() || handler.fail_counter >= MAX_DECODE_FAIL_COUNTER {
+                        let mut lc = session.lc.write().unwrap();
+                        let format = handler.decoder.format();
+                        if !lc.mark_unsupported.contains(&format) {
+                            lc.mark_unsupported.push(format);
+                            should_update_supported = true;
+                            log::info!("mark {format:?} decoder as unsupported, valid:{}, fail_counter:{}, all unsupported:{:?}", handler.decoder.valid(), handler.fail_counter, lc.mark_unsupported);
                         }
                     }
-                    _ => {}
                 }
-            } else {
-                break;
+                if should_update_supported {
+                    session.send(Data::Message(
+                        session.lc.read().unwrap().update_supported_decodings(),
+                    ));
+                }
             }
         }
         log::info!("Video decoder loop exits");
     });
 }
 
-/// Start an audio thread
+/// Start an audio thread, the audio is recorded to the recordings of `record_session`
 /// Return a audio [`MediaSender`]
-pub fn start_audio_thread() -> MediaSender {
+pub fn start_audio_thread(record_session: scrap::record::RecordSession) -> MediaSender {
     let (audio_sender, audio_receiver) = mpsc::channel::<MediaData>();
     std::thread::spawn(move || {
         let mut audio_handler = AudioHandler::default();
@@ -2525,6 +2629,11 @@ pub fn start_audio_thread() -> MediaSender {
             if let Ok(data) = audio_receiver.recv() {
                 match data {
                     MediaData::AudioFrame(af) => {
+                        scrap::record::write_audio(
+                            &record_session,
+                            scrap::record::RecordAudio::Peer,
+                            &af.data,
+                        );
                         audio_handler.handle_frame(*af);
                     }
                     MediaData::AudioFormat(f) => {
@@ -2894,6 +3003,12 @@ lazy_static::lazy_static! {
             text: "Please wait for the remote side to accept your session request...",
             link: "",
             try_again: true,
+        }), (LOGIN_MSG_OUTSIDE_ACCESS_SCHEDULE, LoginErrorMsgBox{
+            msgtype: "error",
+            title: "Login Error",
+            text: LOGIN_MSG_OUTSIDE_ACCESS_SCHEDULE,
+            link: "",
+            try_again: false,
         })]);
         Arc::new(map)
     };
@@ -2914,6 +3029,8 @@ pub fn handle_login_error(
         lc.write().unwrap().password = Default::default();
         interface.msgbox("re-input-password", err, "Do you want to enter again?", "");
         true
+    } else if err == REQUIRE_2FA && lc.read().unwrap().security_key_answered {
+        true
     } else if err == LOGIN_MSG_2FA_WRONG || err == REQUIRE_2FA {
         let enabled = lc.read().unwrap().get_option("trust-this-device") == "Y";
         if enabled {
@@ -2963,6 +3080,7 @@ pub async fn handle_hash(
     peer: &mut Stream,
 ) {
     lc.write().unwrap().hash = hash.clone();
+    lc.write().unwrap().security_key_answered = false;
     // Take care of password application order
 
     // switch_uuid
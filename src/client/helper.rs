@@ -1,9 +1,22 @@
 use hbb_common::{
-    get_time,
-    message_proto::{Message, VoiceCallRequest, VoiceCallResponse},
+    config::{keys, Config, LocalConfig},
+    get_time, log,
+    message_proto::{video_frame, Message, VideoFrame, VoiceCallRequest, VoiceCallResponse},
 };
 use scrap::CodecFormat;
-use std::collections::HashMap;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+// Frames later than this start a new reference, e.g. after the encoder restarts.
+const JITTER_REBASE_MS: i64 = 1000;
+const JITTER_MAX_FRAMES: usize = 60;
+// The reference moves forward by this per frame, to follow the clock drift of the peer.
+const JITTER_DRIFT: Duration = Duration::from_micros(500);
 
 #[derive(Debug, Default)]
 pub struct QualityStatus {
@@ -15,6 +28,202 @@ pub struct QualityStatus {
     pub chroma: Option<String>,
 }
 
+/// The statistics of a display during the last second.
+#[derive(Debug, Default)]
+pub struct DisplayStats {
+    pub received: usize,
+    pub dropped: usize,
+}
+
+/// Per-second network statistics of a session, appended as JSON lines to
+/// `<log dir>/quality/<id>_<time>.jsonl` if `OPTION_ALLOW_QUALITY_STATS_EXPORT` is set.
+#[derive(Default)]
+pub struct QualityStats {
+    file: Option<File>,
+    pub delay: Option<i32>,
+    pub target_bitrate: Option<i32>,
+    pub displays: HashMap<usize, DisplayStats>,
+}
+
+impl QualityStats {
+    fn open(id: &str) -> Option<File> {
+        let dir = Config::log_path().join("quality");
+        std::fs::create_dir_all(&dir).ok()?;
+        let name = format!(
+            "{}_{}.jsonl",
+            id,
+            chrono::Local::now().format("%Y%m%d%H%M%S")
+        );
+        match std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(name))
+        {
+            Ok(file) => Some(file),
+            Err(e) => {
+                log::error!("Failed to create quality stats file: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Append a line of the last `elapsed` ms, `stats` is completed with the delay and the
+    /// display statistics, which are reset then.
+    pub fn export(
+        &mut self,
+        id: &str,
+        elapsed: usize,
+        mut stats: Value,
+        decoded: &HashMap<usize, Value>,
+    ) {
+        let displays = std::mem::take(&mut self.displays);
+        if !LocalConfig::get_bool_option(keys::OPTION_ALLOW_QUALITY_STATS_EXPORT) {
+            self.file = None;
+            return;
+        }
+        if self.file.is_none() {
+            self.file = Self::open(id);
+        }
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let mut indexes: Vec<_> = decoded.keys().chain(displays.keys()).collect();
+        indexes.sort();
+        indexes.dedup();
+        let displays: Vec<_> = indexes
+            .into_iter()
+            .map(|k| {
+                let mut v = decoded.get(k).cloned().unwrap_or(json!({}));
+                let s = displays.get(k);
+                v["display"] = json!(k);
+                let received = s.map(|s| s.received).unwrap_or_default();
+                v["received_fps"] = json!(received * 1000 / elapsed.max(1));
+                v["dropped"] = json!(s.map(|s| s.dropped).unwrap_or_default());
+                v
+            })
+            .collect();
+        stats["time"] = json!(get_time());
+        stats["id"] = json!(id);
+        stats["rtt"] = json!(self.delay);
+        stats["target_bitrate"] = json!(self.target_bitrate);
+        stats["displays"] = json!(displays);
+        if let Err(e) = writeln!(file, "{}", stats) {
+            log::error!("Failed to write quality stats: {}", e);
+            self.file = None;
+        }
+    }
+}
+
+/// Paces the presentation of the video frames by their `pts`, so that the network jitter does
+/// not show up as stutter.
+///
+/// The reference is the frame which arrived the earliest relative to its `pts`. Frames are
+/// delayed by the recently observed transit time over the reference, up to the latency target.
+#[derive(Default)]
+pub struct JitterBuffer {
+    frames: VecDeque<(Instant, VideoFrame)>,
+    // arrival time and pts of the reference frame
+    base: Option<(Instant, i64)>,
+    jitter_ms: f64,
+}
+
+impl JitterBuffer {
+    /// The latency target of the session options, `None` to present the frames on arrival.
+    pub fn target(low_latency: bool, target: &str) -> Option<Duration> {
+        if low_latency {
+            return None;
+        }
+        let ms = target.parse::<u64>().ok().filter(|ms| *ms > 0)?;
+        Some(Duration::from_millis(ms.min(JITTER_REBASE_MS as _)))
+    }
+
+    /// Queue a frame received at `now`.
+    pub fn push(&mut self, vf: VideoFrame, target: Option<Duration>, now: Instant) {
+        let mut due = now;
+        match (target, Self::pts(&vf)) {
+            (Some(target), Some(pts)) => {
+                let transit = match self.base {
+                    Some((t, p)) if pts >= p => {
+                        let expected = t + Duration::from_millis((pts - p) as _);
+                        if now >= expected {
+                            (now - expected).as_millis() as i64
+                        } else {
+                            -((expected - now).as_millis() as i64)
+                        }
+                    }
+                    _ => i64::MAX,
+                };
+                let transit = if transit < 0 || transit > JITTER_REBASE_MS {
+                    if transit > JITTER_REBASE_MS {
+                        self.jitter_ms = 0.0;
+                    }
+                    self.base = Some((now, pts));
+                    0
+                } else {
+                    if let Some((t, _)) = self.base.as_mut() {
+                        if transit > 0 {
+                            *t += JITTER_DRIFT;
+                        }
+                    }
+                    transit
+                };
+                // fast attack, slow release
+                let sample = transit as f64;
+                self.jitter_ms = if sample > self.jitter_ms {
+                    (self.jitter_ms + sample) / 2.0
+                } else {
+                    self.jitter_ms * 0.99 + sample * 0.01
+                };
+                let delay = self.jitter_ms.min(target.as_millis() as f64);
+                let expected = now
+                    .checked_sub(Duration::from_millis(transit as _))
+                    .unwrap_or(now);
+                due = expected + Duration::from_secs_f64(delay / 1000.0);
+            }
+            (None, _) => {
+                self.base = None;
+                self.jitter_ms = 0.0;
+            }
+            // the lossless tiles follow the previous frame
+            _ => {}
+        }
+        if let Some((last, _)) = self.frames.back() {
+            due = due.max(*last);
+        }
+        self.frames.push_back((due, vf));
+    }
+
+    /// When the first frame is due.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.frames.front().map(|f| f.0)
+    }
+
+    /// Pop the first frame if it is due at `now`, or the buffer is full.
+    pub fn pop(&mut self, now: Instant) -> Option<VideoFrame> {
+        match self.frames.front() {
+            Some((due, _)) if *due <= now || self.frames.len() > JITTER_MAX_FRAMES => {
+                self.frames.pop_front().map(|f| f.1)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Default::default();
+    }
+
+    fn pts(vf: &VideoFrame) -> Option<i64> {
+        match &vf.union {
+            Some(video_frame::Union::Vp8s(f))
+            | Some(video_frame::Union::Vp9s(f))
+            | Some(video_frame::Union::Av1s(f))
+            | Some(video_frame::Union::H264s(f))
+            | Some(video_frame::Union::H265s(f)) => f.frames.first().map(|f| f.pts),
+            _ => None,
+        }
+    }
+}
+
 #[inline]
 pub fn new_voice_call_request(is_connect: bool) -> Message {
     let mut req = VoiceCallRequest::new();
@@ -35,3 +244,53 @@ pub fn new_voice_call_response(request_timestamp: i64, accepted: bool) -> Messag
     msg.set_voice_call_response(resp);
     msg
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::message_proto::{EncodedVideoFrame, EncodedVideoFrames};
+
+    fn frame(pts: i64) -> VideoFrame {
+        let mut vf = VideoFrame::new();
+        vf.set_vp9s(EncodedVideoFrames {
+            frames: vec![EncodedVideoFrame {
+                pts,
+                ..Default::default()
+            }]
+            .into(),
+            ..Default::default()
+        });
+        vf
+    }
+
+    #[test]
+    fn test_jitter_buffer() {
+        let target = JitterBuffer::target(false, "100");
+        assert_eq!(target, Some(Duration::from_millis(100)));
+        assert_eq!(JitterBuffer::target(true, "100"), None);
+        assert_eq!(JitterBuffer::target(false, ""), None);
+
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut jb = JitterBuffer::default();
+        // steady frames are presented on arrival
+        for i in 0..3 {
+            jb.push(frame(i * 30), target, at(i as u64 * 30));
+            assert!(jb.pop(at(i as u64 * 30)).is_some());
+        }
+        // a stall raises the delay of the frames catching up
+        jb.push(frame(90), target, at(170));
+        assert!(jb.pop(at(170)).is_some());
+        jb.push(frame(120), target, at(171));
+        assert!(jb.pop(at(171)).is_some());
+        jb.push(frame(150), target, at(172));
+        assert!(jb.pop(at(172)).is_none());
+        let due = jb.next_due().unwrap();
+        assert!(due > at(172) && due <= at(150 + 100));
+        assert!(jb.pop(due).is_some());
+
+        // low latency mode presents on arrival
+        jb.push(frame(150), None, at(200));
+        assert!(jb.pop(at(200)).is_some());
+    }
+}
@@ -15,7 +15,7 @@ use crate::{audio_service, clipboard::CLIPBOARD_INTERVAL, ConnInner, CLIENT_SERV
 use crate::{
     client::{
         self, new_voice_call_request, Client, Data, Interface, MediaData, MediaSender,
-        QualityStatus, MILLI1, SEC30,
+        QualityStats, QualityStatus, MILLI1, SEC30,
     },
     common::get_default_sound_input,
     ui_session_interface::{InvokeUiSession, Session},
@@ -29,12 +29,12 @@ use hbb_common::{
     allow_err,
     config::{self, LocalConfig, PeerConfig, TransferSerde},
     fs::{
-        self, can_enable_overwrite_detection, get_job, get_string, new_send_confirm,
-        DigestCheckResult, RemoveJobMeta,
+        self, can_enable_overwrite_detection, get_job, get_string, new_request_signatures,
+        new_send_confirm, DigestCheckResult, RemoveJobMeta,
     },
     get_time, log,
     message_proto::{permission_info::Permission, *},
-    protobuf::Message as _,
+    protobuf::{Message as _, MessageField},
     rendezvous_proto::ConnType,
     timeout,
     tokio::{
@@ -46,7 +46,10 @@ use hbb_common::{
 };
 #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
 use hbb_common::{tokio::sync::Mutex as TokioMutex, ResultType};
-use scrap::CodecFormat;
+use scrap::{
+    record::{RecordAudio, RecordSession},
+    CodecFormat,
+};
 
 pub struct Remote<T: InvokeUiSession> {
     handler: Session<T>,
@@ -72,6 +75,7 @@ pub struct Remote<T: InvokeUiSession> {
     video_threads: HashMap<usize, VideoThread>,
     chroma: Arc<RwLock<Option<Chroma>>>,
     last_record_state: bool,
+    quality_stats: QualityStats,
 }
 
 #[derive(Default)]
@@ -95,9 +99,10 @@ impl<T: InvokeUiSession> Remote<T> {
         receiver: mpsc::UnboundedReceiver<Data>,
         sender: mpsc::UnboundedSender<Data>,
     ) -> Self {
+        let record_session = RecordSession::Outgoing(handler.get_id());
         Self {
             handler,
-            audio_sender: crate::client::start_audio_thread(),
+            audio_sender: crate::client::start_audio_thread(record_session),
             receiver,
             sender,
             read_jobs: Vec::new(),
@@ -118,6 +123,7 @@ impl<T: InvokeUiSession> Remote<T> {
             video_threads: Default::default(),
             chroma: Default::default(),
             last_record_state: false,
+            quality_stats: Default::default(),
         }
     }
 
@@ -169,6 +175,9 @@ impl<T: InvokeUiSession> Remote<T> {
                     .set_connected();
                 self.handler.set_connection_type(peer.is_secured(), direct); // flutter -> connection_ready
                 self.handler.update_direct(Some(direct));
+                if let Some(pk) = pk.as_ref() {
+                    self.handler.lc.write().unwrap().set_verified_pk(pk);
+                }
                 if conn_type == ConnType::DEFAULT_CONN {
                     self.handler
                         .set_fingerprint(crate::common::pk_to_fingerprint(pk.unwrap_or_default()));
@@ -268,6 +277,7 @@ impl<T: InvokeUiSession> Remote<T> {
                             fps_instant = Instant::now();
                             let mut speed = self.data_count.swap(0, Ordering::Relaxed);
                             speed = speed * 1000 / elapsed as usize;
+                            self.export_quality_stats(direct, elapsed as _, speed);
                             let speed = format!("{:.2}kB/s", speed as f32 / 1024 as f32);
 
                             let fps = self.video_threads.iter().map(|(k, v)| {
@@ -445,6 +455,7 @@ impl<T: InvokeUiSession> Remote<T> {
                 true,
             );
             let tx_audio = self.sender.clone();
+            let record_session = RecordSession::Outgoing(self.handler.get_id());
             std::thread::spawn(move || {
                 loop {
                     // check if client is closed
@@ -465,6 +476,11 @@ impl<T: InvokeUiSession> Remote<T> {
                     match rx_audio_data.try_recv() {
                         Ok((_instant, msg)) => match &msg.union {
                             Some(message::Union::AudioFrame(frame)) => {
+                                scrap::record::write_audio(
+                                    &record_session,
+                                    RecordAudio::Local,
+                                    &frame.data,
+                                );
                                 let mut msg = Message::new();
                                 msg.set_audio_frame(frame.clone());
                                 tx_audio.send(Data::Message(msg)).ok();
@@ -708,24 +724,34 @@ impl<T: InvokeUiSession> Remote<T> {
                         if remember {
                             job.set_overwrite_strategy(Some(need_override));
                         }
-                        job.confirm(&FileTransferSendConfirmRequest {
-                            id,
-                            file_num,
-                            union: if need_override {
-                                Some(file_transfer_send_confirm_request::Union::OffsetBlk(0))
-                            } else {
-                                Some(file_transfer_send_confirm_request::Union::Skip(true))
-                            },
-                            ..Default::default()
-                        });
+                        if job.take_delta_file(file_num) && need_override {
+                            // the read starts once the signatures of the existing file arrive
+                            allow_err!(peer.send(&new_request_signatures(id, file_num)).await);
+                        } else {
+                            job.confirm(&FileTransferSendConfirmRequest {
+                                id,
+                                file_num,
+                                union: if need_override {
+                                    Some(file_transfer_send_confirm_request::Union::OffsetBlk(0))
+                                } else {
+                                    Some(file_transfer_send_confirm_request::Union::Skip(true))
+                                },
+                                ..Default::default()
+                            });
+                        }
                     }
                 } else {
                     if let Some(job) = fs::get_job(id, &mut self.write_jobs) {
                         if remember {
                             job.set_overwrite_strategy(Some(need_override));
                         }
-                        let mut msg = Message::new();
-                        let mut file_action = FileAction::new();
+                        let delta_path = if job.take_delta_file(file_num) && need_override {
+                            job.files()
+                                .get(file_num as usize)
+                                .map(|file| get_string(&job.join(&file.name)))
+                        } else {
+                            None
+                        };
                         let req = FileTransferSendConfirmRequest {
                             id,
                             file_num,
@@ -737,9 +763,11 @@ impl<T: InvokeUiSession> Remote<T> {
                             ..Default::default()
                         };
                         job.confirm(&req);
-                        file_action.set_send_confirm(req);
-                        msg.set_file_action(file_action);
-                        allow_err!(peer.send(&msg).await);
+                        if let Some(path) = delta_path {
+                            self.send_confirm_with_signatures(req, path);
+                        } else {
+                            allow_err!(peer.send(&new_send_confirm(req)).await);
+                        }
                     }
                 }
             }
@@ -931,6 +959,16 @@ impl<T: InvokeUiSession> Remote<T> {
         true
     }
 
+    // Write side, the signatures of the existing file are hashed off the session loop, the
+    // confirmation is sent once they are ready.
+    fn send_confirm_with_signatures(&self, mut req: FileTransferSendConfirmRequest, path: String) {
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            req.signatures = MessageField::from_option(fs::get_block_signatures(path).await);
+            sender.send(Data::Message(new_send_confirm(req))).ok();
+        });
+    }
+
     #[inline]
     fn update_job_status(
         job: &fs::TransferJob,
@@ -1177,7 +1215,11 @@ impl<T: InvokeUiSession> Remote<T> {
                         self.send_toggle_virtual_display_msg(peer).await;
                         self.send_toggle_privacy_mode_msg(peer).await;
                     }
-                    self.video_format = CodecFormat::from(&vf);
+                    // lossless tiles, which are drawn over the frames of the codec
+                    let tiles = matches!(vf.union, Some(video_frame::Union::Rgb(_)));
+                    if !tiles {
+                        self.video_format = CodecFormat::from(&vf);
+                    }
 
                     let display = vf.display as usize;
                     if !self.video_threads.contains_key(&display) {
@@ -1186,7 +1228,10 @@ impl<T: InvokeUiSession> Remote<T> {
                     let Some(thread) = self.video_threads.get_mut(&display) else {
                         return true;
                     };
-                    if Self::contains_key_frame(&vf) {
+                    let stats = self.quality_stats.displays.entry(display).or_default();
+                    stats.received += 1;
+                    // tiles must not be dropped with the queue, or invalidated tiles remain
+                    if tiles || Self::contains_key_frame(&vf) {
                         thread
                             .video_sender
                             .send(MediaData::VideoFrame(Box::new(vf)))
@@ -1195,6 +1240,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         let video_queue = thread.video_queue.read().unwrap();
                         if video_queue.force_push(vf).is_some() {
                             drop(video_queue);
+                            stats.dropped += 1;
                             self.handler.refresh_video(display as _);
                         } else {
                             thread.video_sender.send(MediaData::VideoQueue).ok();
@@ -1206,6 +1252,20 @@ impl<T: InvokeUiSession> Remote<T> {
                         .handle_hash(&self.handler.password.clone(), hash, peer)
                         .await;
                 }
+                Some(message::Union::Auth2fa(tfa)) => {
+                    let answer = self
+                        .handler
+                        .lc
+                        .read()
+                        .unwrap()
+                        .answer_security_key(&tfa.nonce);
+                    if let Some(answer) = answer {
+                        self.handler.lc.write().unwrap().security_key_answered = true;
+                        let mut msg_out = Message::new();
+                        msg_out.set_auth_2fa(answer);
+                        allow_err!(peer.send(&msg_out).await);
+                    }
+                }
                 Some(message::Union::LoginResponse(lr)) => match lr.union {
                     Some(login_response::Union::Error(err)) => {
                         if err == client::REQUIRE_2FA {
@@ -1349,10 +1409,27 @@ impl<T: InvokeUiSession> Remote<T> {
                         Some(file_response::Union::Digest(digest)) => {
                             if digest.is_upload {
                                 if let Some(job) = fs::get_job(digest.id, &mut self.read_jobs) {
-                                    if let Some(file) = job.files().get(digest.file_num as usize) {
+                                    if digest.signatures.is_some() {
+                                        // the answer to `request_signatures`, the overwrite is confirmed
+                                        job.confirm(&FileTransferSendConfirmRequest {
+                                            id: digest.id,
+                                            file_num: digest.file_num,
+                                            union: Some(
+                                                file_transfer_send_confirm_request::Union::OffsetBlk(0),
+                                            ),
+                                            signatures: digest.signatures,
+                                            ..Default::default()
+                                        });
+                                    } else if let Some(file) =
+                                        job.files().get(digest.file_num as usize)
+                                    {
                                         let read_path = get_string(&job.join(&file.name));
                                         let overwrite_strategy = job.default_overwrite_strategy();
-                                        if let Some(overwrite) = overwrite_strategy {
+                                        if overwrite_strategy == Some(true) && digest.can_delta {
+                                            let msg =
+                                                new_request_signatures(digest.id, digest.file_num);
+                                            allow_err!(peer.send(&msg).await);
+                                        } else if let Some(overwrite) = overwrite_strategy {
                                             let req = FileTransferSendConfirmRequest {
                                                 id: digest.id,
                                                 file_num: digest.file_num,
@@ -1369,6 +1446,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                             let msg = new_send_confirm(req);
                                             allow_err!(peer.send(&msg).await);
                                         } else {
+                                            job.set_delta_file(digest.file_num, digest.can_delta);
                                             self.handler.override_file_confirm(
                                                 digest.id,
                                                 digest.file_num,
@@ -1384,6 +1462,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                     if let Some(file) = job.files().get(digest.file_num as usize) {
                                         let write_path = get_string(&job.join(&file.name));
                                         let overwrite_strategy = job.default_overwrite_strategy();
+                                        let can_delta = digest.can_delta;
                                         match fs::is_write_need_confirmation(&write_path, &digest) {
                                             Ok(res) => match res {
                                                 DigestCheckResult::IsSame => {
@@ -1410,9 +1489,19 @@ impl<T: InvokeUiSession> Remote<T> {
                                                             ..Default::default()
                                                         };
                                                         job.confirm(&req);
-                                                        let msg = new_send_confirm(req);
-                                                        allow_err!(peer.send(&msg).await);
+                                                        if overwrite && can_delta {
+                                                            self.send_confirm_with_signatures(
+                                                                req, write_path,
+                                                            );
+                                                        } else {
+                                                            let msg = new_send_confirm(req);
+                                                            allow_err!(peer.send(&msg).await);
+                                                        }
                                                     } else {
+                                                        job.set_delta_file(
+                                                            digest.file_num,
+                                                            can_delta,
+                                                        );
                                                         self.handler.override_file_confirm(
                                                             digest.id,
                                                             digest.file_num,
@@ -1423,10 +1512,11 @@ impl<T: InvokeUiSession> Remote<T> {
                                                     }
                                                 }
                                                 DigestCheckResult::NoSuchFile => {
+                                                    let offset_blk = job.prepare_resume(&digest);
                                                     let req = FileTransferSendConfirmRequest {
                                                         id: digest.id,
                                                         file_num: digest.file_num,
-                                                        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+                                                        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(offset_blk)),
                                                         ..Default::default()
                                                     };
                                                     job.confirm(&req);
@@ -1663,6 +1753,10 @@ impl<T: InvokeUiSession> Remote<T> {
                     _ => {}
                 },
                 Some(message::Union::TestDelay(t)) => {
+                    if !t.from_client {
+                        self.quality_stats.delay = Some(t.last_delay as _);
+                        self.quality_stats.target_bitrate = Some(t.target_bitrate as _);
+                    }
                     self.handler.handle_test_delay(t, peer).await;
                 }
                 Some(message::Union::AudioFrame(frame)) => {
@@ -1973,6 +2067,35 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    fn export_quality_stats(&mut self, direct: bool, elapsed: usize, speed: usize) {
+        // Frames rendered and the average decoding time, which decode_fps is calculated from.
+        let decoded = self
+            .video_threads
+            .iter()
+            .map(|(k, v)| {
+                let decode_fps = *v.decode_fps.read().unwrap();
+                let value = serde_json::json!({
+                    "decoded_fps": *v.frame_count.read().unwrap() * 1000 / elapsed.max(1),
+                    "decode_ms": decode_fps.filter(|f| *f > 0).map(|f| 1000. / f as f64),
+                });
+                (*k, value)
+            })
+            .collect();
+        let codec = if self.video_format == CodecFormat::Unknown {
+            None
+        } else {
+            Some(self.video_format.to_string())
+        };
+        let conn = if direct { "direct" } else { "relay" };
+        let stats = serde_json::json!({
+            "conn": conn,
+            "bitrate": speed * 8 / 1000,
+            "codec": codec,
+        });
+        self.quality_stats
+            .export(&self.handler.get_id(), elapsed, stats, &decoded);
+    }
+
     fn new_video_thread(&mut self, display: usize) {
         let video_queue = Arc::new(RwLock::new(ArrayQueue::new(client::VIDEO_QUEUE_SIZE)));
         let (video_sender, video_receiver) = std::sync::mpsc::channel::<MediaData>();
@@ -1271,6 +1271,49 @@ pub async fn secure_tcp(conn: &mut FramedStream, key: &str) -> ResultType<()> {
     Ok(())
 }
 
+/// Pipe one connection of a reverse port forward, what is read from `sock` is sent to `tx`
+/// as `TunnelData` of `id`, and the data received from `rx` is written to `sock`.
+pub async fn run_tunnel(
+    id: u32,
+    sock: tokio::net::TcpStream,
+    tx: tokio::sync::mpsc::UnboundedSender<TunnelData>,
+    mut rx: tokio::sync::mpsc::UnboundedReceiver<Bytes>,
+) {
+    use hbb_common::{
+        futures::{SinkExt, StreamExt},
+        tokio_util::codec::{BytesCodec, Framed},
+    };
+    let mut sock = Framed::new(sock, BytesCodec::new());
+    loop {
+        tokio::select! {
+            res = sock.next() => match res {
+                Some(Ok(bytes)) => {
+                    let mut td = TunnelData::new();
+                    td.id = id;
+                    td.set_data(bytes.freeze());
+                    if tx.send(td).is_err() {
+                        return;
+                    }
+                }
+                _ => break,
+            },
+            res = rx.recv() => match res {
+                Some(data) => {
+                    if sock.send(data).await.is_err() {
+                        break;
+                    }
+                }
+                // closed by the other side
+                None => return,
+            },
+        }
+    }
+    let mut td = TunnelData::new();
+    td.id = id;
+    td.set_close(true);
+    tx.send(td).ok();
+}
+
 #[inline]
 fn get_pk(pk: &[u8]) -> Option<[u8; 32]> {
     if pk.len() == 32 {
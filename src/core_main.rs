@@ -333,6 +333,16 @@ pub fn core_main() -> Option<Vec<String>> {
                 }
             }
             return None;
+        } else if args[0] == "--play-record" {
+            if args.len() == 3 {
+                match crate::server::event_recorder::make_player(&args[1], &args[2]) {
+                    Ok(path) => println!("{path}"),
+                    Err(err) => println!("{err}"),
+                }
+            } else {
+                println!("Usage: --play-record <video-file> <events-file>");
+            }
+            return None;
         } else if args[0] == "--get-id" {
             println!("{}", crate::ipc::get_id());
             return None;
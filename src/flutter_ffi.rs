@@ -1738,6 +1738,12 @@ pub fn main_wol(id: String) {
     crate::lan::send_wol(id)
 }
 
+pub fn session_send_wol(session_id: SessionID, id: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send_wol(&id);
+    }
+}
+
 pub fn main_create_shortcut(_id: String) {
     #[cfg(windows)]
     create_shortcut(_id);
@@ -2307,6 +2313,10 @@ pub fn main_verify2fa(code: String) -> bool {
     verify2fa(code)
 }
 
+pub fn main_get_authenticator_public_key() -> String {
+    get_authenticator_public_key()
+}
+
 pub fn main_has_valid_2fa_sync() -> SyncReturn<bool> {
     SyncReturn(has_valid_2fa())
 }
@@ -2343,6 +2353,35 @@ pub fn main_clear_trusted_devices() {
     clear_trusted_devices()
 }
 
+pub fn main_get_access_codes() -> String {
+    get_access_codes()
+}
+
+pub fn main_generate_access_code(
+    minutes: u32,
+    view_only: bool,
+    file_transfer: bool,
+    clipboard: bool,
+) -> String {
+    generate_access_code(minutes, view_only, file_transfer, clipboard)
+}
+
+pub fn main_clear_access_codes() {
+    clear_access_codes()
+}
+
+pub fn main_get_login_lockouts() -> String {
+    get_login_lockouts()
+}
+
+pub fn main_unlock_login_source(source: String) {
+    unlock_login_source(source)
+}
+
+pub fn main_get_controller_public_key() -> SyncReturn<String> {
+    SyncReturn(get_controller_public_key())
+}
+
 pub fn main_max_encrypt_len() -> SyncReturn<usize> {
     SyncReturn(max_encrypt_len())
 }
@@ -35,6 +35,7 @@ pub fn run(rx: Receiver<RecordState>) {
         filename: Default::default(),
         upload_size: Default::default(),
         running: Default::default(),
+        protected: Default::default(),
         last_send: Instant::now(),
     };
     std::thread::spawn(move || loop {
@@ -62,13 +63,19 @@ pub fn run(rx: Receiver<RecordState>) {
                         Ok(())
                     }
                 }
+                RecordState::PurgeFile(filepath) => {
+                    uploader.handle_purge(filepath);
+                    Ok(())
+                }
             },
             Err(e) => {
                 log::trace!("upload thread stop: {}", e);
+                uploader.release();
                 break;
             }
         } {
             uploader.running = false;
+            uploader.release();
             log::error!("upload stop: {}", e);
         }
     });
@@ -81,6 +88,8 @@ struct RecordUploader {
     filename: String,
     upload_size: u64,
     running: bool,
+    // the file is kept by the retention sweeper until it is uploaded
+    protected: bool,
     last_send: Instant,
 }
 impl RecordUploader {
@@ -109,6 +118,18 @@ impl RecordUploader {
     }
 
     fn handle_new_file(&mut self, filepath: String) -> ResultType<()> {
+        self.release();
+        // protected before the check, not to be removed by the retention sweeper in between
+        scrap::record::set_recording(&filepath, true);
+        self.filepath = filepath.clone();
+        self.protected = true;
+        if !std::path::Path::new(&filepath).exists() {
+            // removed by the retention sweeper before the upload started
+            self.running = false;
+            self.release();
+            log::info!("skip upload of removed file: {}", filepath);
+            return Ok(());
+        }
         match std::path::PathBuf::from(&filepath).file_name() {
             Some(filename) => match filename.to_owned().into_string() {
                 Ok(filename) => {
@@ -186,6 +207,7 @@ impl RecordUploader {
                             buf,
                         )?;
                         log::info!("upload success, file: {}", self.filename);
+                        self.release();
                         Ok(())
                     }
                     Err(e) => bail!(e.to_string()),
@@ -195,7 +217,27 @@ impl RecordUploader {
         }
     }
 
+    fn release(&mut self) {
+        if self.protected {
+            scrap::record::set_recording(&self.filepath, false);
+            self.protected = false;
+        }
+    }
+
+    // The file is removed by the retention sweeper, skip the rest of its upload if it is the one
+    // uploading.
+    fn handle_purge(&mut self, filepath: String) {
+        if self.running && self.filepath == filepath {
+            log::info!("stop upload of removed file: {}", filepath);
+            self.running = false;
+            self.release();
+        } else {
+            log::debug!("removed recording: {}", filepath);
+        }
+    }
+
     fn handle_remove(&mut self) -> ResultType<()> {
+        self.release();
         self.send(
             &[("type", "remove"), ("file", &self.filename)],
             Bytes::new(),
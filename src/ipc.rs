@@ -22,7 +22,7 @@ pub use clipboard::ClipboardFile;
 use hbb_common::{
     allow_err, bail, bytes,
     bytes_codec::BytesCodec,
-    config::{self, Config, Config2},
+    config::{self, AccessCode, Config, Config2},
     futures::StreamExt as _,
     futures_util::sink::SinkExt,
     log, password_security as password,
@@ -84,6 +84,8 @@ pub enum FS {
         file_num: i32,
         data: Bytes,
         compressed: bool,
+        blk_id: u32,
+        copy_blks: u32,
     },
     WriteDone {
         id: i32,
@@ -105,6 +107,12 @@ pub enum FS {
         file_size: u64,
         last_modified: u64,
         is_upload: bool,
+        can_resume: bool,
+        can_delta: bool,
+    },
+    GetSignatures {
+        id: i32,
+        file_num: i32,
     },
     Rename {
         id: i32,
@@ -273,6 +281,9 @@ pub enum Data {
     HwCodecConfig(Option<String>),
     RemoveTrustedDevices(Vec<Bytes>),
     ClearTrustedDevices,
+    AddAccessCode(AccessCode),
+    ClearAccessCodes,
+    UnlockLoginSource(String),
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -503,6 +514,10 @@ async fn handle(data: Data, stream: &mut Connection) {
                     value = Some(Config::get_unlock_pin());
                 } else if name == "trusted-devices" {
                     value = Some(Config::get_trusted_devices_json());
+                } else if name == "access-codes" {
+                    value = Some(Config::get_access_codes_json());
+                } else if name == "login-lockouts" {
+                    value = Some(crate::server::login_failure::get_lockouts_json());
                 } else {
                     value = None;
                 }
@@ -661,6 +676,15 @@ async fn handle(data: Data, stream: &mut Connection) {
         Data::ClearTrustedDevices => {
             Config::clear_trusted_devices();
         }
+        Data::AddAccessCode(code) => {
+            Config::add_access_code(code);
+        }
+        Data::ClearAccessCodes => {
+            Config::clear_access_codes();
+        }
+        Data::UnlockLoginSource(source) => {
+            crate::server::login_failure::unlock(&source);
+        }
         _ => {}
     }
 }
@@ -991,6 +1015,47 @@ pub fn clear_trusted_devices() {
     allow_err!(set_data(&Data::ClearTrustedDevices));
 }
 
+#[cfg(feature = "flutter")]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn get_access_codes() -> String {
+    if let Ok(Some(v)) = get_config("access-codes") {
+        v
+    } else {
+        Config::get_access_codes_json()
+    }
+}
+
+#[cfg(feature = "flutter")]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn add_access_code(code: AccessCode) {
+    Config::add_access_code(code.clone());
+    allow_err!(set_data(&Data::AddAccessCode(code)));
+}
+
+#[cfg(feature = "flutter")]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn clear_access_codes() {
+    Config::clear_access_codes();
+    allow_err!(set_data(&Data::ClearAccessCodes));
+}
+
+// The lockouts are kept by the server process only.
+#[cfg(feature = "flutter")]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn get_login_lockouts() -> String {
+    if let Ok(Some(v)) = get_config("login-lockouts") {
+        v
+    } else {
+        "[]".to_owned()
+    }
+}
+
+#[cfg(feature = "flutter")]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn unlock_login_source(source: String) {
+    allow_err!(set_data(&Data::UnlockLoginSource(source)));
+}
+
 pub fn get_id() -> String {
     if let Ok(Some(v)) = get_config("id") {
         // update salt also, so that next time reinstallation not causing first-time auto-login failure
@@ -2,10 +2,12 @@ use hbb_common::config::Config;
 use hbb_common::{
     allow_err,
     anyhow::bail,
+    bytes::Bytes,
     config::{self, RENDEZVOUS_PORT},
     log,
     protobuf::Message as _,
     rendezvous_proto::*,
+    sodiumoxide::crypto::sign,
     tokio::{
         self,
         sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
@@ -19,10 +21,14 @@ use std::{
     time::Instant,
 };
 
+#[cfg(not(target_os = "ios"))]
+mod mdns;
+
 type Message = RendezvousMessage;
 
 #[cfg(not(target_os = "ios"))]
 pub(super) fn start_listening() -> ResultType<()> {
+    mdns::start_responder();
     let addr = SocketAddr::from(([0, 0, 0, 0], get_broadcast_port()));
     let socket = std::net::UdpSocket::bind(addr)?;
     socket.set_read_timeout(Some(std::time::Duration::from_millis(1000)))?;
@@ -45,21 +51,7 @@ pub(super) fn start_listening() -> ResultType<()> {
                             }
                             if let Some(self_addr) = get_ipaddr_by_peer(&addr) {
                                 let mut msg_out = Message::new();
-                                let mut hostname = whoami::hostname();
-                                // The default hostname is "localhost" which is a bit confusing
-                                if hostname == "localhost" {
-                                    hostname = "unknown".to_owned();
-                                }
-                                let peer = PeerDiscovery {
-                                    cmd: "pong".to_owned(),
-                                    mac: get_mac(&self_addr),
-                                    id,
-                                    hostname,
-                                    username: crate::platform::get_active_username(),
-                                    platform: whoami::platform().to_string(),
-                                    ..Default::default()
-                                };
-                                msg_out.set_peer_discovery(peer);
+                                msg_out.set_peer_discovery(make_pong(id, &self_addr, p.nonce));
                                 socket.send_to(&msg_out.write_to_bytes()?, addr).ok();
                             }
                         }
@@ -73,8 +65,12 @@ pub(super) fn start_listening() -> ResultType<()> {
 
 #[tokio::main(flavor = "current_thread")]
 pub async fn discover() -> ResultType<()> {
-    let sockets = send_query()?;
-    let rx = spawn_wait_responses(sockets);
+    let nonce: [u8; 16] = hbb_common::rand::random();
+    let sockets = send_query(&nonce)?;
+    let (tx, rx) = unbounded_channel::<_>();
+    #[cfg(not(target_os = "ios"))]
+    mdns::browse(nonce, tx.clone());
+    spawn_wait_responses(sockets, nonce, tx);
     handle_received_peers(rx).await?;
 
     log::info!("discover ping done");
@@ -82,24 +78,153 @@ pub async fn discover() -> ResultType<()> {
 }
 
 pub fn send_wol(id: String) {
+    let macs: Vec<String> = get_wol_targets(&id)
+        .into_iter()
+        .map(|(_, mac)| mac)
+        .collect();
+    send_wol_macs(&macs);
+}
+
+/// The (ip, MAC address) pairs remembered for the discovered peer `id`.
+pub fn get_wol_targets(id: &str) -> Vec<(String, String)> {
+    config::LanPeers::load()
+        .peers
+        .into_iter()
+        .find(|peer| peer.id == id)
+        .map(|peer| {
+            peer.ip_mac
+                .into_iter()
+                .filter(|(_, mac)| !mac.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Send the magic packets to all the ipv4 networks of this device, return the number of valid
+/// MAC addresses.
+pub fn send_wol_macs(macs: &[String]) -> usize {
     let interfaces = default_net::get_interfaces();
-    for peer in &config::LanPeers::load().peers {
-        if peer.id == id {
-            for (_, mac) in peer.ip_mac.iter() {
-                if let Ok(mac_addr) = mac.parse() {
-                    for interface in &interfaces {
-                        for ipv4 in &interface.ipv4 {
-                            // remove below mask check to avoid unexpected bug
-                            // if (u32::from(ipv4.addr) & u32::from(ipv4.netmask)) == (u32::from(peer_ip) & u32::from(ipv4.netmask))
-                            log::info!("Send wol to {mac_addr} of {}", ipv4.addr);
-                            allow_err!(wol::send_wol(mac_addr, None, Some(IpAddr::V4(ipv4.addr))));
-                        }
-                    }
+    let mut n = 0;
+    for mac in macs {
+        if let Ok(mac_addr) = mac.parse() {
+            n += 1;
+            for interface in &interfaces {
+                for ipv4 in &interface.ipv4 {
+                    // remove below mask check to avoid unexpected bug
+                    // if (u32::from(ipv4.addr) & u32::from(ipv4.netmask)) == (u32::from(peer_ip) & u32::from(ipv4.netmask))
+                    log::info!("Send wol to {mac_addr} of {}", ipv4.addr);
+                    allow_err!(wol::send_wol(mac_addr, None, Some(IpAddr::V4(ipv4.addr))));
                 }
             }
-            break;
         }
     }
+    n
+}
+
+/// Send the magic packets of the (ip, MAC address) pairs only to the ipv4 networks of this device
+/// containing the ip, return the number of pairs sent.
+pub fn send_wol_subnet(targets: &[(String, String)]) -> usize {
+    let interfaces = default_net::get_interfaces();
+    let mut n = 0;
+    for (ip, mac) in targets {
+        let (Ok(peer_ip), Ok(mac_addr)) = (ip.parse::<Ipv4Addr>(), mac.parse()) else {
+            continue;
+        };
+        let mut sent = false;
+        for interface in &interfaces {
+            for ipv4 in &interface.ipv4 {
+                if is_same_subnet(ipv4.addr, ipv4.netmask, peer_ip) {
+                    log::info!("Send wol to {mac_addr} of {} for {peer_ip}", ipv4.addr);
+                    allow_err!(wol::send_wol(mac_addr, None, Some(IpAddr::V4(ipv4.addr))));
+                    sent = true;
+                }
+            }
+        }
+        if sent {
+            n += 1;
+        }
+    }
+    n
+}
+
+fn is_same_subnet(addr: Ipv4Addr, netmask: Ipv4Addr, ip: Ipv4Addr) -> bool {
+    let mask = u32::from(netmask);
+    !addr.is_loopback() && mask != 0 && (u32::from(addr) & mask) == (u32::from(ip) & mask)
+}
+
+fn make_pong(id: String, self_addr: &IpAddr, nonce: Bytes) -> PeerDiscovery {
+    let mut pong = PeerDiscovery {
+        cmd: "pong".to_owned(),
+        mac: get_mac(self_addr),
+        id,
+        hostname: get_hostname(),
+        username: crate::platform::get_active_username(),
+        platform: whoami::platform().to_string(),
+        nonce,
+        ..Default::default()
+    };
+    sign_pong(&mut pong);
+    pong
+}
+
+// The pong is signed over all its fields, including the nonce of the ping, with the key pair
+// registered to the rendezvous server. The key is self-asserted, the pong is only trusted if the
+// key is the one of the id verified with the rendezvous server, see `handle_received_peers`.
+fn sign_pong(pong: &mut PeerDiscovery) {
+    let (sk, pk) = Config::get_key_pair();
+    if sk.len() != sign::SECRETKEYBYTES {
+        return;
+    }
+    let mut sk_ = [0u8; sign::SECRETKEYBYTES];
+    sk_[..].copy_from_slice(&sk);
+    pong.pk = pk.into();
+    pong.signature = Default::default();
+    if let Ok(data) = pong.write_to_bytes() {
+        let signature = sign::sign_detached(&data, &sign::SecretKey(sk_));
+        pong.signature = signature.to_bytes().to_vec().into();
+    }
+}
+
+// Returns the key of the pong if it is signed over the nonce of our ping.
+fn verify_pong(pong: &PeerDiscovery, nonce: &[u8]) -> Option<sign::PublicKey> {
+    if pong.nonce != nonce {
+        return None;
+    }
+    let pk = sign::PublicKey::from_slice(&pong.pk)?;
+    let signature = sign::Signature::try_from(&pong.signature[..]).ok()?;
+    let mut unsigned = pong.clone();
+    unsigned.signature = Default::default();
+    let data = unsigned.write_to_bytes().ok()?;
+    sign::verify_detached(&signature, &data, &pk).then_some(pk)
+}
+
+fn pong_to_peer(pong: &PeerDiscovery, addr: &SocketAddr, nonce: &[u8]) -> config::DiscoveryPeer {
+    let pk = match verify_pong(pong, nonce) {
+        Some(pk) => crate::common::encode64(pk.0),
+        None => {
+            log::debug!("Unsigned pong of {} from {}", pong.id, addr);
+            "".to_owned()
+        }
+    };
+    config::DiscoveryPeer {
+        id: pong.id.clone(),
+        ip_mac: HashMap::from([(addr.ip().to_string(), pong.mac.clone())]),
+        username: pong.username.clone(),
+        hostname: pong.hostname.clone(),
+        platform: pong.platform.clone(),
+        online: true,
+        pk,
+    }
+}
+
+fn get_hostname() -> String {
+    let hostname = whoami::hostname();
+    // The default hostname is "localhost" which is a bit confusing
+    if hostname == "localhost" {
+        "unknown".to_owned()
+    } else {
+        hostname
+    }
 }
 
 #[inline]
@@ -143,7 +268,13 @@ fn get_mac_by_ip(ip: &IpAddr) -> ResultType<String> {
 
 // Mainly from https://github.com/shellrow/default-net/blob/cf7ca24e7e6e8e566ed32346c9cfddab3f47e2d6/src/interface/shared.rs#L4
 fn get_ipaddr_by_peer<A: ToSocketAddrs>(peer: A) -> Option<IpAddr> {
-    let socket = match UdpSocket::bind("0.0.0.0:0") {
+    let peer = peer.to_socket_addrs().ok()?.next()?;
+    let any = if peer.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = match UdpSocket::bind(any) {
         Ok(s) => s,
         Err(_) => return None,
     };
@@ -183,7 +314,7 @@ fn create_broadcast_sockets() -> Vec<UdpSocket> {
     sockets
 }
 
-fn send_query() -> ResultType<Vec<UdpSocket>> {
+fn send_query(nonce: &[u8]) -> ResultType<Vec<UdpSocket>> {
     let sockets = create_broadcast_sockets();
     if sockets.is_empty() {
         bail!("Found no bindable ipv4 addresses");
@@ -204,6 +335,7 @@ fn send_query() -> ResultType<Vec<UdpSocket>> {
     let peer = PeerDiscovery {
         cmd: "ping".to_owned(),
         id,
+        nonce: nonce.to_vec().into(),
         ..Default::default()
     };
     msg_out.set_peer_discovery(peer);
@@ -219,6 +351,7 @@ fn send_query() -> ResultType<Vec<UdpSocket>> {
 fn wait_response(
     socket: UdpSocket,
     timeout: Option<std::time::Duration>,
+    nonce: [u8; 16],
     tx: UnboundedSender<config::DiscoveryPeer>,
 ) -> ResultType<()> {
     let mut last_recv_time = Instant::now();
@@ -261,16 +394,7 @@ fn wait_response(
                             };
 
                             if local_mac.is_empty() && p.mac.is_empty() || local_mac != p.mac {
-                                allow_err!(tx.send(config::DiscoveryPeer {
-                                    id: p.id.clone(),
-                                    ip_mac: HashMap::from([
-                                        (addr.ip().to_string(), p.mac.clone(),)
-                                    ]),
-                                    username: p.username.clone(),
-                                    hostname: p.hostname.clone(),
-                                    platform: p.platform.clone(),
-                                    online: true,
-                                }));
+                                allow_err!(tx.send(pong_to_peer(&p, &addr, &nonce)));
                             }
                         }
                     }
@@ -285,19 +409,22 @@ fn wait_response(
     Ok(())
 }
 
-fn spawn_wait_responses(sockets: Vec<UdpSocket>) -> UnboundedReceiver<config::DiscoveryPeer> {
-    let (tx, rx) = unbounded_channel::<_>();
+fn spawn_wait_responses(
+    sockets: Vec<UdpSocket>,
+    nonce: [u8; 16],
+    tx: UnboundedSender<config::DiscoveryPeer>,
+) {
     for socket in sockets {
         let tx_clone = tx.clone();
         std::thread::spawn(move || {
             allow_err!(wait_response(
                 socket,
                 Some(std::time::Duration::from_millis(10)),
+                nonce,
                 tx_clone
             ));
         });
     }
-    rx
 }
 
 async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>) -> ResultType<()> {
@@ -307,15 +434,30 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
     });
 
     let mut response_set = HashSet::new();
+    let mut verified_set = HashSet::new();
     let mut last_write_time: Option<Instant> = None;
     loop {
         tokio::select! {
             data = rx.recv() => match data {
                 Some(mut peer) => {
+                    // Anyone can sign a pong with its own key, so a pong is only verified if it is
+                    // signed with the key of the id verified on a connection to it. The others are
+                    // still listed, marked as unverified, unless a verified one is received.
+                    peer.verified = !peer.pk.is_empty()
+                        && config::PeerConfig::exists(&peer.id)
+                        && config::PeerConfig::load(&peer.id).pk == peer.pk;
+                    if peer.verified {
+                        if verified_set.insert(peer.id.clone()) {
+                            peers.retain(|x| x.id != peer.id || x.verified);
+                        }
+                    } else if verified_set.contains(&peer.id) {
+                        log::warn!("Ignore the unverified pong of {}", peer.id);
+                        continue;
+                    }
                     let in_response_set = !response_set.insert(peer.id.clone());
                     if let Some(pos) = peers.iter().position(|x| x.is_same_peer(&peer) ) {
                         let peer1 = peers.remove(pos);
-                        if in_response_set {
+                        if in_response_set && peer1.verified == peer.verified {
                             peer.ip_mac.extend(peer1.ip_mac);
                             peer.online = true;
                         }
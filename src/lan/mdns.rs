@@ -0,0 +1,453 @@
+// DNS-SD over mDNS (RFC 6762, RFC 6763) which advertises and browses `_rustdesk._tcp.local`,
+// so peers are found on IPv6 only networks and where the IPv4 broadcast is filtered.
+//
+// Only the records needed by the discovery are handled. The browser sends one-shot queries
+// from an ephemeral port, which the responders answer with unicast. The query asks for the TXT
+// record of `<hex nonce>.<NONCE>` besides the service, so the pong in the TXT record of the
+// response is signed over a random nonce, other responders ignore the question.
+
+use super::*;
+use std::{
+    net::{Ipv6Addr, SocketAddrV6},
+    time::Duration,
+};
+
+const MDNS_PORT: u16 = 5353;
+const MDNS_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+const SERVICE: &str = "_rustdesk._tcp.local";
+const NONCE: &str = "_nonce._rustdesk._tcp.local";
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CACHE_FLUSH: u16 = 0x8000;
+const FLAG_RESPONSE: u16 = 0x8400; // response, authoritative
+const TTL: u32 = 120;
+// RFC 6762 6.7, the ttl of the answers to legacy unicast queries
+const LEGACY_TTL: u32 = 10;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Default)]
+struct DnsMessage {
+    id: u16,
+    response: bool,
+    // (name, type)
+    questions: Vec<(String, u16)>,
+    answers: Vec<DnsRecord>,
+}
+
+#[derive(Debug, PartialEq)]
+enum DnsRecord {
+    Ptr(String, String),
+    Txt(String, Vec<String>),
+    Other,
+}
+
+impl DnsMessage {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let read_u16 = |pos: usize| -> Option<u16> {
+            Some(u16::from_be_bytes([*buf.get(pos)?, *buf.get(pos + 1)?]))
+        };
+        let mut msg = DnsMessage {
+            id: read_u16(0)?,
+            response: read_u16(2)? & 0x8000 != 0,
+            ..Default::default()
+        };
+        let qdcount = read_u16(4)?;
+        // the answers, authority and additional records are handled the same
+        let rrcount = read_u16(6)? as usize + read_u16(8)? as usize + read_u16(10)? as usize;
+        let mut pos = 12;
+        for _ in 0..qdcount {
+            let (name, next) = read_name(buf, pos)?;
+            msg.questions.push((name, read_u16(next)?));
+            pos = next + 4;
+        }
+        for _ in 0..rrcount {
+            let (name, next) = read_name(buf, pos)?;
+            let rtype = read_u16(next)?;
+            let len = read_u16(next + 8)? as usize;
+            let start = next + 10;
+            let rdata = buf.get(start..start + len)?;
+            let record = match rtype {
+                TYPE_PTR => DnsRecord::Ptr(name, read_name(buf, start)?.0),
+                TYPE_TXT => {
+                    let mut txt = Vec::new();
+                    let mut i = 0;
+                    while i < rdata.len() {
+                        let n = rdata[i] as usize;
+                        let s = rdata.get(i + 1..i + 1 + n)?;
+                        txt.push(String::from_utf8_lossy(s).to_string());
+                        i += n + 1;
+                    }
+                    DnsRecord::Txt(name, txt)
+                }
+                _ => DnsRecord::Other,
+            };
+            msg.answers.push(record);
+            pos = start + len;
+        }
+        Some(msg)
+    }
+}
+
+// Returns the name and the position after it, compressed names are followed.
+fn read_name(buf: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // a pointer must point backward, which also bounds the loop
+    let mut limit = pos;
+    loop {
+        let len = *buf.get(pos)? as usize;
+        if len & 0xC0 == 0xC0 {
+            let ptr = (len & 0x3F) << 8 | *buf.get(pos + 1)? as usize;
+            if ptr >= limit {
+                return None;
+            }
+            end.get_or_insert(pos + 2);
+            limit = ptr;
+            pos = ptr;
+        } else if len == 0 {
+            end.get_or_insert(pos + 1);
+            break;
+        } else {
+            let label = buf.get(pos + 1..pos + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).to_string());
+            pos += len + 1;
+        }
+    }
+    Some((labels.join("."), end?))
+}
+
+fn put_name(buf: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|x| !x.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        buf.push(label.len() as _);
+        buf.extend(label);
+    }
+    buf.push(0);
+}
+
+fn put_record(buf: &mut Vec<u8>, name: &str, rtype: u16, class: u16, ttl: u32, rdata: &[u8]) {
+    put_name(buf, name);
+    buf.extend(rtype.to_be_bytes());
+    buf.extend(class.to_be_bytes());
+    buf.extend(ttl.to_be_bytes());
+    buf.extend((rdata.len() as u16).to_be_bytes());
+    buf.extend(rdata);
+}
+
+fn build_query(id: u16, nonce: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for x in [id, 0, 2, 0, 0, 0] {
+        buf.extend(x.to_be_bytes());
+    }
+    put_name(&mut buf, SERVICE);
+    buf.extend(TYPE_PTR.to_be_bytes());
+    buf.extend(CLASS_IN.to_be_bytes());
+    put_name(&mut buf, &format!("{}.{}", hex::encode(nonce), NONCE));
+    buf.extend(TYPE_TXT.to_be_bytes());
+    buf.extend(CLASS_IN.to_be_bytes());
+    buf
+}
+
+// The nonce of the query, empty if the query is not sent by us.
+fn query_nonce(msg: &DnsMessage) -> Vec<u8> {
+    msg.questions
+        .iter()
+        .find_map(|(name, _)| {
+            let (nonce, suffix) = name.split_once('.')?;
+            if !suffix.eq_ignore_ascii_case(NONCE) {
+                return None;
+            }
+            hex::decode(nonce).ok()
+        })
+        .unwrap_or_default()
+}
+
+// `legacy` is a one-shot query from a port other than 5353, which gets the id and the question back.
+fn build_response(id: u16, legacy: bool, self_addr: IpAddr, txt: &[String]) -> Vec<u8> {
+    let hostname = get_hostname();
+    let instance = format!("{}.{}", txt_value(txt, "id"), SERVICE);
+    let target = format!("{}.local", hostname.replace('.', "-"));
+    let (id, ttl) = if legacy { (id, LEGACY_TTL) } else { (0, TTL) };
+    let mut buf = Vec::new();
+    for x in [id, FLAG_RESPONSE, legacy as u16, 1, 0, 3] {
+        buf.extend(x.to_be_bytes());
+    }
+    if legacy {
+        put_name(&mut buf, SERVICE);
+        buf.extend(TYPE_PTR.to_be_bytes());
+        buf.extend(CLASS_IN.to_be_bytes());
+    }
+    let mut rdata = Vec::new();
+    put_name(&mut rdata, &instance);
+    put_record(&mut buf, SERVICE, TYPE_PTR, CLASS_IN, ttl, &rdata);
+    let mut rdata = Vec::new();
+    for s in txt {
+        let s = &s.as_bytes()[..s.len().min(255)];
+        rdata.push(s.len() as _);
+        rdata.extend(s);
+    }
+    let class = CLASS_IN | CACHE_FLUSH;
+    put_record(&mut buf, &instance, TYPE_TXT, class, ttl, &rdata);
+    let mut rdata = Vec::new();
+    rdata.extend(0u16.to_be_bytes()); // priority
+    rdata.extend(0u16.to_be_bytes()); // weight
+    rdata.extend((crate::rendezvous_mediator::get_direct_port() as u16).to_be_bytes());
+    put_name(&mut rdata, &target);
+    put_record(&mut buf, &instance, TYPE_SRV, class, ttl, &rdata);
+    match self_addr {
+        IpAddr::V4(ip) => put_record(&mut buf, &target, TYPE_A, class, ttl, &ip.octets()),
+        IpAddr::V6(ip) => put_record(&mut buf, &target, TYPE_AAAA, class, ttl, &ip.octets()),
+    }
+    buf
+}
+
+fn txt_value(txt: &[String], key: &str) -> String {
+    txt.iter()
+        .find_map(|x| x.strip_prefix(&format!("{}=", key)))
+        .unwrap_or_default()
+        .to_owned()
+}
+
+fn bind(v6: bool, port: u16) -> ResultType<UdpSocket> {
+    let interfaces = default_net::get_interfaces();
+    if v6 {
+        let socket =
+            hbb_common::udp::new_std_reuse(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)))?;
+        if port == MDNS_PORT {
+            for interface in &interfaces {
+                socket.join_multicast_v6(&MDNS_V6, interface.index).ok();
+            }
+        }
+        Ok(socket)
+    } else {
+        let socket =
+            hbb_common::udp::new_std_reuse(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))?;
+        if port == MDNS_PORT {
+            socket
+                .join_multicast_v4(&MDNS_V4, &Ipv4Addr::UNSPECIFIED)
+                .ok();
+            for interface in &interfaces {
+                for ipv4 in &interface.ipv4 {
+                    socket.join_multicast_v4(&MDNS_V4, &ipv4.addr).ok();
+                }
+            }
+        }
+        Ok(socket)
+    }
+}
+
+pub(super) fn start_responder() {
+    for v6 in [false, true] {
+        match bind(v6, MDNS_PORT) {
+            Ok(socket) => {
+                std::thread::spawn(move || respond(socket, v6));
+            }
+            Err(e) => log::warn!("Failed to start mdns responder, v6: {}, {}", v6, e),
+        }
+    }
+}
+
+fn respond(socket: UdpSocket, v6: bool) {
+    log::info!("mdns responder started, v6: {}", v6);
+    loop {
+        let mut buf = [0; 4096];
+        let Ok((len, addr)) = socket.recv_from(&mut buf) else {
+            std::thread::sleep(Duration::from_millis(100));
+            continue;
+        };
+        // the dual-stack socket may receive the ipv4 packets which are answered by the ipv4 one
+        if let IpAddr::V6(ip) = addr.ip() {
+            if ip.to_ipv4_mapped().is_some() {
+                continue;
+            }
+        }
+        if !config::option2bool(
+            "enable-lan-discovery",
+            &Config::get_option("enable-lan-discovery"),
+        ) {
+            continue;
+        }
+        let Some(msg) = DnsMessage::parse(&buf[..len]) else {
+            continue;
+        };
+        if msg.response
+            || !msg.questions.iter().any(|(name, rtype)| {
+                name.eq_ignore_ascii_case(SERVICE) && (*rtype == TYPE_PTR || *rtype == TYPE_ANY)
+            })
+        {
+            continue;
+        }
+        let Some(self_addr) = get_ipaddr_by_peer(&addr) else {
+            continue;
+        };
+        let legacy = addr.port() != MDNS_PORT;
+        let pong = make_pong(Config::get_id(), &self_addr, query_nonce(&msg).into());
+        let txt = vec![
+            format!("id={}", pong.id),
+            format!("hostname={}", pong.hostname),
+            format!("username={}", pong.username),
+            format!("platform={}", pong.platform),
+            format!("mac={}", pong.mac),
+            format!("pk={}", crate::common::encode64(&pong.pk)),
+            format!("sig={}", crate::common::encode64(&pong.signature)),
+        ];
+        let out = build_response(msg.id, legacy, self_addr, &txt);
+        let to = match (legacy, addr) {
+            (true, _) => addr,
+            (false, SocketAddr::V4(_)) => SocketAddr::from((MDNS_V4, MDNS_PORT)),
+            (false, SocketAddr::V6(a)) => {
+                SocketAddrV6::new(MDNS_V6, MDNS_PORT, 0, a.scope_id()).into()
+            }
+        };
+        socket.send_to(&out, to).ok();
+    }
+}
+
+/// Query the peers with mdns, the responses are sent to `tx` in the same way as the broadcast ones.
+pub(super) fn browse(nonce: [u8; 16], tx: UnboundedSender<config::DiscoveryPeer>) {
+    for v6 in [false, true] {
+        let socket = match bind(v6, 0) {
+            Ok(socket) => socket,
+            Err(e) => {
+                log::warn!("Failed to bind mdns query socket, v6: {}, {}", v6, e);
+                continue;
+            }
+        };
+        let id = hbb_common::rand::random::<u16>();
+        let query = build_query(id, &nonce);
+        if v6 {
+            for interface in default_net::get_interfaces() {
+                if interface.ipv6.is_empty() {
+                    continue;
+                }
+                let to = SocketAddrV6::new(MDNS_V6, MDNS_PORT, 0, interface.index);
+                socket.send_to(&query, to).ok();
+            }
+        } else {
+            socket.send_to(&query, (MDNS_V4, MDNS_PORT)).ok();
+        }
+        let tx = tx.clone();
+        std::thread::spawn(move || allow_err!(wait_mdns_response(socket, id, nonce, tx)));
+    }
+}
+
+fn wait_mdns_response(
+    socket: UdpSocket,
+    id: u16,
+    nonce: [u8; 16],
+    tx: UnboundedSender<config::DiscoveryPeer>,
+) -> ResultType<()> {
+    let start = Instant::now();
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+    while start.elapsed() < QUERY_TIMEOUT {
+        let mut buf = [0; 4096];
+        let Ok((len, addr)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        let Some(msg) = DnsMessage::parse(&buf[..len]) else {
+            continue;
+        };
+        if !msg.response || msg.id != id {
+            continue;
+        }
+        let instances: Vec<&String> = msg
+            .answers
+            .iter()
+            .filter_map(|x| match x {
+                DnsRecord::Ptr(name, instance) if name.eq_ignore_ascii_case(SERVICE) => {
+                    Some(instance)
+                }
+                _ => None,
+            })
+            .collect();
+        for record in &msg.answers {
+            let DnsRecord::Txt(name, txt) = record else {
+                continue;
+            };
+            if !instances.contains(&name) {
+                continue;
+            }
+            let decode =
+                |key: &str| crate::common::decode64(txt_value(txt, key)).unwrap_or_default();
+            let pong = PeerDiscovery {
+                cmd: "pong".to_owned(),
+                mac: txt_value(txt, "mac"),
+                id: txt_value(txt, "id"),
+                username: txt_value(txt, "username"),
+                hostname: txt_value(txt, "hostname"),
+                platform: txt_value(txt, "platform"),
+                nonce: nonce.to_vec().into(),
+                pk: decode("pk").into(),
+                signature: decode("sig").into(),
+                ..Default::default()
+            };
+            let local_mac = get_ipaddr_by_peer(&addr)
+                .map(|x| get_mac(&x))
+                .unwrap_or_default();
+            if !local_mac.is_empty() && local_mac == pong.mac {
+                continue;
+            }
+            allow_err!(tx.send(pong_to_peer(&pong, &addr, &nonce)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dns_message() {
+        let nonce = [0xab; 16];
+        let msg = DnsMessage::parse(&build_query(7, &nonce)).unwrap();
+        assert_eq!(msg.id, 7);
+        assert!(!msg.response);
+        assert_eq!(msg.questions[0], (SERVICE.to_owned(), TYPE_PTR));
+        assert_eq!(msg.questions[1].1, TYPE_TXT);
+        assert_eq!(query_nonce(&msg), nonce);
+        // a query of another browser
+        let msg = DnsMessage {
+            questions: vec![(SERVICE.to_owned(), TYPE_PTR)],
+            ..Default::default()
+        };
+        assert!(query_nonce(&msg).is_empty());
+
+        let txt = vec!["id=123456789".to_owned(), "platform=Linux".to_owned()];
+        let ip = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        let msg = DnsMessage::parse(&build_response(7, true, ip, &txt)).unwrap();
+        assert_eq!(msg.id, 7);
+        assert!(msg.response);
+        assert_eq!(msg.questions.len(), 1);
+        let instance = format!("123456789.{}", SERVICE);
+        assert_eq!(msg.answers.len(), 4);
+        assert_eq!(
+            msg.answers[0],
+            DnsRecord::Ptr(SERVICE.to_owned(), instance.clone())
+        );
+        assert_eq!(msg.answers[1], DnsRecord::Txt(instance, txt));
+        // the multicast responses have no id and question
+        let msg = DnsMessage::parse(&build_response(7, false, ip, &[])).unwrap();
+        assert_eq!(msg.id, 0);
+        assert!(msg.questions.is_empty());
+    }
+
+    #[test]
+    fn test_compressed_name() {
+        // "a.local" at 12, "b" + pointer to "local" at 20
+        let mut buf = vec![0; 12];
+        buf.extend([1, b'a', 5, b'l', b'o', b'c', b'a', b'l', 0]);
+        buf.extend([1, b'b', 0xC0, 14]);
+        assert_eq!(read_name(&buf, 12), Some(("a.local".to_owned(), 21)));
+        assert_eq!(read_name(&buf, 21), Some(("b.local".to_owned(), 25)));
+        // pointer loops are rejected
+        let buf = [0xC0, 0];
+        assert_eq!(read_name(&buf, 0), None);
+    }
+}
@@ -237,5 +237,14 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("one-way-file-transfer-tip", "One-way file transfer is enabled on the controlled side."),
         ("web_id_input_tip", "You can input an ID in the same server, direct IP access is not supported in web client.\nIf you want to access a device on another server, please append the server address (<id>@<server_address>?key=<key_value>), for example,\n9123456234@192.168.16.1:21117?key=5Qbwsde3unUcJBtrx9ZkvUmwFNoExHzpryHuPUdqlWM=.\nIf you want to access a device on a public server, please input \"<id>@public\", the key is not needed for public server."),
         ("new-version-of-{}-tip", "There is a new version of {} available"),
+        ("allow-wol-relay-tip", "Allow the controlling side to wake up the devices on my LAN"),
+        ("allow-record-input-text-tip", "Record the typed text and the clipboard content of incoming sessions"),
+        ("wol-relay-empty-tip", "No LAN device has been discovered. The remote device can wake up the devices discovered before on its LAN."),
+        ("lan-unverified-tip", "This device is not verified, it may be another device using the same ID. It is verified once you have connected to it."),
+        ("access-code-tip", "An access code can be used once instead of the password, until it expires."),
+        ("access-code-once-tip", "Hand this code over now, it will not be shown again."),
+        ("authorized-keys-tip", "The devices signing in with these keys are accepted without a password, one key per line: [view-only,no-file-transfer,no-clipboard] ed25519 <key> [comment]"),
+        ("login-lockouts-empty-tip", "No address is locked out or peer ID is reported after too many wrong attempts."),
+        ("my-public-key-tip", "The key of this device, enable \"Sign in with my key\" on the peer to use it"),
     ].iter().cloned().collect();
 }
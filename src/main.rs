@@ -44,9 +44,17 @@ fn main() {
     use hbb_common::log;
     let args = format!(
         "-p, --port-forward=[PORT-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host]'
+        -R, --reverse-port-forward=[REVERSE-PORT-FORWARD-OPTIONS] 'Format: remote-id:remote-port:local-port[:local-host], remote-port is listened on 127.0.0.1 of the peer'
+        -D, --dynamic-port-forward=[DYNAMIC-PORT-FORWARD-OPTIONS] 'Format: remote-id:[bind-address:]local-port, local-port is a SOCKS5 server, listened on 127.0.0.1 if bind-address is not set'
+        -r, --port-forward-rules=[PORT-FORWARD-RULES] 'Format: remote-id:rules-file, the rules file is in TOML'
         -c, --connect=[REMOTE_ID] 'test only'
+        -f, --file-transfer=[REMOTE_ID] 'Headless file transfer, ARGS: ls <remote-path> | get <remote-path> <local-path> | put <local-path> <remote-path> | rm <remote-path>'
+        --password=[PASSWORD] 'Password of the remote peer, the saved one is used if not set'
+        --2fa=[CODE] '2FA code of the remote peer, asked on the terminal if not set and no password is given'
+        --show-hidden 'Include hidden files in file transfer'
         -k, --key=[KEY] ''
-       -s, --server=[] 'Start server'",
+       -s, --server=[] 'Start server'
+        [ARGS]... 'Arguments of file transfer'",
     );
     let matches = App::new("rustdesk")
         .version(crate::VERSION)
@@ -89,15 +97,113 @@ fn main() {
             port,
             remote_host,
             remote_port,
+            port_forward::ForwardMode::Tcp,
+            "".to_owned(),
             key,
             token,
         );
+    } else if let Some(p) = matches.value_of("dynamic-port-forward") {
+        let Some((id, port)) = p.split_once(':') else {
+            log::error!("Wrong dynamic-port-forward options");
+            return;
+        };
+        let (bind_address, port) = port.rsplit_once(':').unwrap_or(("", port));
+        let Ok(port) = port.parse::<i32>() else {
+            log::error!("Wrong local-port");
+            return;
+        };
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        cli::start_one_port_forward(
+            id.to_owned(),
+            port,
+            "".to_owned(),
+            0,
+            port_forward::ForwardMode::Socks5,
+            bind_address.to_owned(),
+            key,
+            token,
+        );
+    } else if let Some(p) = matches.value_of("reverse-port-forward") {
+        let options: Vec<String> = p.split(":").map(|x| x.to_owned()).collect();
+        if options.len() < 3 {
+            log::error!("Wrong reverse-port-forward options");
+            return;
+        }
+        let Ok(remote_port) = options[1].parse::<i32>() else {
+            log::error!("Wrong remote-port");
+            return;
+        };
+        let Ok(local_port) = options[2].parse::<i32>() else {
+            log::error!("Wrong local-port");
+            return;
+        };
+        let local_host = options
+            .get(3)
+            .cloned()
+            .unwrap_or_else(|| "localhost".to_owned());
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        cli::start_one_reverse_port_forward(
+            options[0].clone(),
+            remote_port,
+            local_host,
+            local_port,
+            key,
+            token,
+        );
+    } else if let Some(p) = matches.value_of("port-forward-rules") {
+        let Some((id, path)) = p.split_once(':') else {
+            log::error!("Wrong port-forward-rules options");
+            return;
+        };
+        let rules = match port_forward::load_rules(path) {
+            Ok(rules) => rules,
+            Err(err) => {
+                log::error!("Failed to load port forward rules {}: {}", path, err);
+                return;
+            }
+        };
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        cli::start_port_forward_rules(id.to_owned(), rules, key, token);
     } else if let Some(p) = matches.value_of("connect") {
         common::test_rendezvous_server();
         common::test_nat_type();
         let key = matches.value_of("key").unwrap_or("").to_owned();
         let token = LocalConfig::get_option("access_token");
         cli::connect_test(p, key, token);
+    } else if let Some(p) = matches.value_of("file-transfer") {
+        let args: Vec<&str> = matches
+            .values_of("ARGS")
+            .map(|x| x.collect())
+            .unwrap_or_default();
+        let Some(command) = cli::FileTransferCommand::parse(&args) else {
+            log::error!("Wrong file-transfer arguments: {:?}", args);
+            common::global_clean();
+            std::process::exit(cli::EXIT_USAGE);
+        };
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        let code = cli::start_file_transfer(
+            p.to_owned(),
+            matches.value_of("password").map(|x| x.to_owned()),
+            matches.value_of("2fa").map(|x| x.to_owned()),
+            command,
+            matches.is_present("show-hidden"),
+            key,
+            token,
+        );
+        common::global_clean();
+        std::process::exit(code);
     } else if let Some(p) = matches.value_of("server") {
         log::info!("id={}", hbb_common::config::Config::get_id());
         crate::start_server(true, false);
@@ -1,19 +1,123 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock},
+};
 
 use crate::client::*;
 use hbb_common::{
     allow_err, bail,
+    bytes::Bytes,
     config::READ_TIMEOUT,
     futures::{SinkExt, StreamExt},
     log,
     message_proto::*,
     protobuf::Message as _,
+    proxy::{self, SOCKS5_REPLY_GENERAL_FAILURE, SOCKS5_REPLY_SUCCEEDED},
     rendezvous_proto::ConnType,
     tcp, timeout,
-    tokio::{self, net::TcpStream, sync::mpsc},
+    tokio::{
+        self,
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+    },
     tokio_util::codec::{BytesCodec, Framed},
-    ResultType, Stream,
+    toml, ResultType, Stream,
 };
+use serde_derive::Deserialize;
+
+/// One forward rule of a rules file, e.g.
+///
+/// ```toml
+/// [[rule]]
+/// name = "db"
+/// local_port = 15432
+/// remote_host = "localhost"
+/// remote_port = 5432
+///
+/// [[rule]]
+/// name = "lan"
+/// local_port = 1080
+/// mode = "socks5"
+/// ```
+///
+/// A SOCKS5 rule only listens on 127.0.0.1 unless `bind_address` is set, the others listen on
+/// all the addresses.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct PortForwardRule {
+    #[serde(default)]
+    pub name: String,
+    pub local_port: i32,
+    #[serde(default = "default_remote_host")]
+    pub remote_host: String,
+    #[serde(default)]
+    pub remote_port: i32,
+    #[serde(default)]
+    pub mode: ForwardMode,
+    #[serde(default)]
+    pub bind_address: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardMode {
+    /// Every connection is forwarded to `remote_host:remote_port`.
+    #[default]
+    Tcp,
+    /// Dynamic forwarding, like `ssh -D`, the local port is a SOCKS5 server and every
+    /// `CONNECT` is tunneled to its own target.
+    Socks5,
+}
+
+fn default_remote_host() -> String {
+    "localhost".to_owned()
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PortForwardRules {
+    #[serde(default)]
+    rule: Vec<PortForwardRule>,
+}
+
+pub fn parse_rules(content: &str) -> ResultType<Vec<PortForwardRule>> {
+    let rules = toml::from_str::<PortForwardRules>(content)?.rule;
+    for (i, rule) in rules.iter().enumerate() {
+        if rule.local_port <= 0 || rule.local_port > 65535 {
+            bail!("Wrong local_port of rule {}", i + 1);
+        }
+        if rule.mode == ForwardMode::Tcp && (rule.remote_port <= 0 || rule.remote_port > 65535) {
+            bail!("Wrong remote_port of rule {}", i + 1);
+        }
+        if !rule.bind_address.is_empty() && rule.bind_address.parse::<IpAddr>().is_err() {
+            bail!("Wrong bind_address of rule {}", i + 1);
+        }
+        if rules[..i].iter().any(|r| r.local_port == rule.local_port) {
+            bail!("Duplicated local_port {}", rule.local_port);
+        }
+    }
+    Ok(rules)
+}
+
+pub fn load_rules(path: &str) -> ResultType<Vec<PortForwardRule>> {
+    parse_rules(&std::fs::read_to_string(path)?)
+}
+
+// Anyone who can reach a SOCKS5 server can use it as a proxy into the LAN of the peer, so it is
+// only bound to the loopback like `ssh -D`, unless `bind_address` is given.
+fn listen_address(mode: ForwardMode, bind_address: &str, port: i32) -> String {
+    let ip = if !bind_address.is_empty() {
+        bind_address
+    } else if mode == ForwardMode::Socks5 {
+        "127.0.0.1"
+    } else {
+        "0.0.0.0"
+    };
+    if ip.contains(':') {
+        format!("[{}]:{}", ip, port)
+    } else {
+        format!("{}:{}", ip, port)
+    }
+}
 
 fn run_rdp(port: u16) {
     std::process::Command::new("cmdkey")
@@ -42,6 +146,8 @@ fn run_rdp(port: u16) {
         .ok();
 }
 
+/// `remote_host` and `remote_port` are unused in [`ForwardMode::Socks5`], `bind_address` is the
+/// default of the mode if empty.
 pub async fn listen(
     id: String,
     password: String,
@@ -53,31 +159,59 @@ pub async fn listen(
     lc: Arc<RwLock<LoginConfigHandler>>,
     remote_host: String,
     remote_port: i32,
+    mode: ForwardMode,
+    bind_address: String,
 ) -> ResultType<()> {
-    let listener = tcp::new_listener(format!("0.0.0.0:{}", port), true).await?;
+    let listener = tcp::new_listener(listen_address(mode, &bind_address, port), true).await?;
     let addr = listener.local_addr()?;
     log::info!("listening on port {:?}", addr);
     let is_rdp = port == 0;
     if is_rdp {
         run_rdp(addr.port());
     }
+    let is_socks5 = !is_rdp && mode == ForwardMode::Socks5;
     let mut ui_receiver = ui_receiver;
+    // the accepted connections with their targets, the SOCKS5 handshakes are done aside
+    let (tx_accepted, mut rx_accepted) =
+        mpsc::unbounded_channel::<(TcpStream, SocketAddr, String, i32)>();
     loop {
         tokio::select! {
-            Ok((forward, addr)) = listener.accept() => {
+            Ok((mut forward, addr)) = listener.accept() => {
                 log::info!("new connection from {:?}", addr);
+                if is_socks5 {
+                    let tx_accepted = tx_accepted.clone();
+                    tokio::spawn(async move {
+                        if let Some((host, port)) = socks5_target(&mut forward, addr).await {
+                            tx_accepted.send((forward, addr, host, port)).ok();
+                        }
+                    });
+                } else {
+                    tx_accepted.send((forward, addr, remote_host.clone(), remote_port)).ok();
+                }
+            }
+            Some((forward, addr, remote_host, remote_port)) = rx_accepted.recv() => {
                 lc.write().unwrap().port_forward = (remote_host.clone(), remote_port);
                 let id = id.clone();
                 let password = password.clone();
                 let mut forward = Framed::new(forward, BytesCodec::new());
-                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), &mut forward, key, token, is_rdp).await {
+                let res = connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), Some(&mut forward), key, token, is_rdp).await;
+                if is_socks5 {
+                    let reply = if let Ok(Some(_)) = res {
+                        SOCKS5_REPLY_SUCCEEDED
+                    } else {
+                        SOCKS5_REPLY_GENERAL_FAILURE
+                    };
+                    allow_err!(proxy::socks5_reply(forward.get_mut(), reply).await);
+                }
+                match res {
                     Ok(Some(stream)) => {
                         let interface = interface.clone();
+                        let target = format!("{}:{}", remote_host, remote_port);
                         tokio::spawn(async move {
                             if let Err(err) = run_forward(forward, stream).await {
                                 interface.msgbox("error", "Error", &err.to_string(), "");
                             }
-                            log::info!("connection from {:?} closed", addr);
+                            log::info!("connection from {:?} to {} closed", addr, target);
                        });
                     }
                     Err(err) => {
@@ -103,12 +237,335 @@ pub async fn listen(
     Ok(())
 }
 
+/// Reverse port forward, `remote_port` is listened on the peer side and the connections
+/// are tunneled to `local_host:local_port`.
+pub async fn listen_reverse(
+    id: String,
+    password: String,
+    remote_port: i32,
+    interface: impl Interface,
+    mut ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    local_host: String,
+    local_port: i32,
+) -> ResultType<()> {
+    lc.write().unwrap().reverse_port_forward = Some(remote_port);
+    let res = connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        key,
+        token,
+        false,
+    )
+    .await;
+    let mut stream = match res {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            interface.on_establish_connection_error(err.to_string());
+            return Ok(());
+        }
+    };
+    let target = format!("{}:{}", local_host, local_port);
+    log::info!("remote port {} is forwarded to {}", remote_port, target);
+    let (tx, mut rx) = mpsc::unbounded_channel::<TunnelData>();
+    let mut tunnels: HashMap<u32, mpsc::UnboundedSender<Bytes>> = HashMap::new();
+    loop {
+        tokio::select! {
+            res = stream.next() => match res {
+                Some(Ok(bytes)) => {
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::TunnelData(td)) => match td.union {
+                            Some(tunnel_data::Union::Open(_)) => {
+                                let (tx_data, rx_data) = mpsc::unbounded_channel();
+                                tunnels.insert(td.id, tx_data);
+                                let id = td.id;
+                                let tx = tx.clone();
+                                let target = target.clone();
+                                tokio::spawn(async move {
+                                    match timeout(3000, TcpStream::connect(&target)).await {
+                                        Ok(Ok(sock)) => {
+                                            crate::common::run_tunnel(id, sock, tx, rx_data).await;
+                                        }
+                                        _ => {
+                                            log::error!("Failed to connect to {}", target);
+                                            let mut td = TunnelData::new();
+                                            td.id = id;
+                                            td.set_close(true);
+                                            tx.send(td).ok();
+                                        }
+                                    }
+                                });
+                            }
+                            Some(tunnel_data::Union::Data(data)) => {
+                                if let Some(tx_data) = tunnels.get(&td.id) {
+                                    tx_data.send(data).ok();
+                                }
+                            }
+                            Some(tunnel_data::Union::Close(_)) => {
+                                tunnels.remove(&td.id);
+                            }
+                            _ => {}
+                        },
+                        Some(message::Union::TestDelay(t)) => {
+                            interface.handle_test_delay(t, &mut stream).await;
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Err(err)) => {
+                    bail!("Connection closed: {}", err);
+                }
+                None => {
+                    bail!("Reset by the peer");
+                }
+            },
+            Some(td) = rx.recv() => {
+                if td.has_close() {
+                    tunnels.remove(&td.id);
+                }
+                let mut msg_out = Message::new();
+                msg_out.set_tunnel_data(td);
+                allow_err!(stream.send(&msg_out).await);
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) | None => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Forward all the `rules` over one connection to the peer, every accepted connection is
+/// opened as a tunnel of it. A rule which fails to listen does not stop the others.
+pub async fn listen_rules(
+    id: String,
+    password: String,
+    rules: Vec<PortForwardRule>,
+    interface: impl Interface,
+    mut ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+) -> ResultType<()> {
+    // the accepted connections of all the rules, with their targets
+    let (tx_accepted, rx_accepted) = mpsc::unbounded_channel::<Accepted>();
+    let mut accept_tasks = Vec::new();
+    for rule in rules {
+        let addr = listen_address(rule.mode, &rule.bind_address, rule.local_port);
+        match tcp::new_listener(addr, true).await {
+            Ok(listener) => {
+                log::info!(
+                    "listening on port {} for rule {}",
+                    rule.local_port,
+                    rule.name
+                );
+                accept_tasks.push(tokio::spawn(accept_rule(
+                    listener,
+                    rule,
+                    tx_accepted.clone(),
+                )));
+            }
+            Err(err) => {
+                log::error!(
+                    "Failed to listen on {} for rule {}: {}",
+                    rule.local_port,
+                    rule.name,
+                    err
+                );
+            }
+        }
+    }
+    if accept_tasks.is_empty() {
+        bail!("No rule is listening");
+    }
+    lc.write().unwrap().port_forward_tunnels = true;
+    let res = connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        key,
+        token,
+        false,
+    )
+    .await;
+    let res = match res {
+        Ok(Some(stream)) => run_rules(stream, rx_accepted, ui_receiver, interface).await,
+        Ok(None) => Ok(()),
+        Err(err) => {
+            interface.on_establish_connection_error(err.to_string());
+            Ok(())
+        }
+    };
+    for task in accept_tasks {
+        task.abort();
+    }
+    res
+}
+
+// An accepted connection of a rule, its target and whether it is a SOCKS5 one.
+type Accepted = (TcpStream, SocketAddr, String, bool);
+
+async fn accept_rule(
+    listener: TcpListener,
+    rule: PortForwardRule,
+    tx_accepted: mpsc::UnboundedSender<Accepted>,
+) {
+    let is_socks5 = rule.mode == ForwardMode::Socks5;
+    let target = format!("{}:{}", rule.remote_host, rule.remote_port);
+    while let Ok((mut forward, addr)) = listener.accept().await {
+        log::info!("new connection from {:?} for rule {}", addr, rule.name);
+        if is_socks5 {
+            let tx_accepted = tx_accepted.clone();
+            tokio::spawn(async move {
+                if let Some((host, port)) = socks5_target(&mut forward, addr).await {
+                    let target = format!("{}:{}", host, port);
+                    tx_accepted.send((forward, addr, target, true)).ok();
+                }
+            });
+        } else if tx_accepted
+            .send((forward, addr, target.clone(), false))
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+// The connections accepted by the rules are tunneled over `stream`, each one is only piped
+// once the peer has connected to its target.
+async fn run_rules(
+    mut stream: Stream,
+    mut rx_accepted: mpsc::UnboundedReceiver<Accepted>,
+    mut ui_receiver: mpsc::UnboundedReceiver<Data>,
+    interface: impl Interface,
+) -> ResultType<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<TunnelData>();
+    let mut tunnels: HashMap<u32, mpsc::UnboundedSender<Bytes>> = HashMap::new();
+    let mut opening: HashMap<u32, (TcpStream, bool)> = HashMap::new();
+    let mut next_id: u32 = 0;
+    loop {
+        tokio::select! {
+            Some((forward, addr, target, is_socks5)) = rx_accepted.recv() => {
+                next_id = next_id.wrapping_add(1);
+                log::info!("new tunnel {} from {:?} to {}", next_id, addr, target);
+                opening.insert(next_id, (forward, is_socks5));
+                let mut td = TunnelData::new();
+                td.id = next_id;
+                td.target = target;
+                td.set_open(true);
+                let mut msg_out = Message::new();
+                msg_out.set_tunnel_data(td);
+                allow_err!(stream.send(&msg_out).await);
+            }
+            res = stream.next() => match res {
+                Some(Ok(bytes)) => {
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::TunnelData(td)) => match td.union {
+                            Some(tunnel_data::Union::Open(_)) => {
+                                if let Some((mut forward, is_socks5)) = opening.remove(&td.id) {
+                                    if is_socks5 {
+                                        allow_err!(proxy::socks5_reply(&mut forward, SOCKS5_REPLY_SUCCEEDED).await);
+                                    }
+                                    let (tx_data, rx_data) = mpsc::unbounded_channel();
+                                    tunnels.insert(td.id, tx_data);
+                                    tokio::spawn(crate::common::run_tunnel(td.id, forward, tx.clone(), rx_data));
+                                }
+                            }
+                            Some(tunnel_data::Union::Data(data)) => {
+                                if let Some(tx_data) = tunnels.get(&td.id) {
+                                    tx_data.send(data).ok();
+                                }
+                            }
+                            Some(tunnel_data::Union::Close(_)) => {
+                                tunnels.remove(&td.id);
+                                if let Some((mut forward, is_socks5)) = opening.remove(&td.id) {
+                                    log::error!("The peer failed to open tunnel {}", td.id);
+                                    if is_socks5 {
+                                        allow_err!(proxy::socks5_reply(&mut forward, SOCKS5_REPLY_GENERAL_FAILURE).await);
+                                    }
+                                }
+                            }
+                            _ => {}
+                        },
+                        Some(message::Union::TestDelay(t)) => {
+                            interface.handle_test_delay(t, &mut stream).await;
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Err(err)) => {
+                    bail!("Connection closed: {}", err);
+                }
+                None => {
+                    bail!("Reset by the peer");
+                }
+            },
+            Some(td) = rx.recv() => {
+                if td.has_close() {
+                    tunnels.remove(&td.id);
+                }
+                let mut msg_out = Message::new();
+                msg_out.set_tunnel_data(td);
+                allow_err!(stream.send(&msg_out).await);
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) | None => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// The target of the SOCKS5 `CONNECT` of `forward`, none if the handshake fails.
+async fn socks5_target(forward: &mut TcpStream, addr: SocketAddr) -> Option<(String, i32)> {
+    match timeout(READ_TIMEOUT, proxy::socks5_accept(forward)).await {
+        Ok(Ok((host, port))) => {
+            // ipv6
+            let host = if host.contains(':') {
+                format!("[{}]", host)
+            } else {
+                host
+            };
+            Some((host, port as i32))
+        }
+        Ok(Err(err)) => {
+            log::error!("SOCKS5 handshake with {:?} failed: {}", addr, err);
+            None
+        }
+        Err(_) => {
+            log::error!("SOCKS5 handshake with {:?} timeout", addr);
+            None
+        }
+    }
+}
+
 async fn connect_and_login(
     id: &str,
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    // none for reverse port forward, whose stream is not raw
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
     key: &str,
     token: &str,
     is_rdp: bool,
@@ -178,7 +635,12 @@ async fn connect_and_login(
                     _ => {}
                 }
             },
-            res = forward.next() => {
+            res = async {
+                match forward.as_mut() {
+                    Some(forward) => forward.next().await,
+                    None => std::future::pending().await,
+                }
+            } => {
                 if let Some(Ok(bytes)) = res {
                     buffer.extend(bytes);
                 } else {
@@ -187,9 +649,11 @@ async fn connect_and_login(
             },
         }
     }
-    stream.set_raw();
-    if !buffer.is_empty() {
-        allow_err!(stream.send_bytes(buffer.into()).await);
+    if forward.is_some() {
+        stream.set_raw();
+        if !buffer.is_empty() {
+            allow_err!(stream.send_bytes(buffer.into()).await);
+        }
     }
     Ok(Some(stream))
 }
@@ -218,3 +682,62 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = parse_rules(
+            r#"
+[[rule]]
+name = "db"
+local_port = 15432
+remote_port = 5432
+
+[[rule]]
+local_port = 13389
+remote_host = "192.168.1.10"
+remote_port = 3389
+
+[[rule]]
+name = "socks"
+local_port = 1080
+mode = "socks5"
+"#,
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].remote_host, "localhost");
+        assert_eq!(rules[0].mode, ForwardMode::Tcp);
+        assert_eq!(rules[1].remote_host, "192.168.1.10");
+        assert_eq!(rules[2].mode, ForwardMode::Socks5);
+        assert!(parse_rules("[[rule]]\nlocal_port = 0\nremote_port = 22").is_err());
+        // no remote_port without socks5
+        assert!(parse_rules("[[rule]]\nlocal_port = 1080").is_err());
+        assert!(parse_rules("[[rule]]\nlocal_port = 1080\nmode = \"socks4\"").is_err());
+        assert!(parse_rules(
+            "[[rule]]\nlocal_port = 1080\nmode = \"socks5\"\nbind_address = \"lan\""
+        )
+        .is_err());
+        assert!(parse_rules(
+            "[[rule]]\nlocal_port = 2222\nremote_port = 22\n[[rule]]\nlocal_port = 2222\nremote_port = 23"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_listen_address() {
+        assert_eq!(listen_address(ForwardMode::Tcp, "", 80), "0.0.0.0:80");
+        assert_eq!(
+            listen_address(ForwardMode::Socks5, "", 1080),
+            "127.0.0.1:1080"
+        );
+        assert_eq!(
+            listen_address(ForwardMode::Socks5, "0.0.0.0", 1080),
+            "0.0.0.0:1080"
+        );
+        assert_eq!(listen_address(ForwardMode::Socks5, "::", 1080), "[::]:1080");
+    }
+}
@@ -627,7 +627,7 @@ impl RendezvousMediator {
     }
 }
 
-fn get_direct_port() -> i32 {
+pub(crate) fn get_direct_port() -> i32 {
     let mut port = Config::get_option("direct-access-port")
         .parse::<i32>()
         .unwrap_or(0);
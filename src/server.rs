@@ -62,6 +62,8 @@ pub mod input_service {
 
 mod connection;
 pub mod display_service;
+pub mod event_recorder;
+pub mod login_failure;
 #[cfg(windows)]
 pub mod portable_service;
 mod service;
@@ -498,6 +500,14 @@ pub async fn start_server(is_server: bool, no_server: bool) {
             }
         });
         input_service::fix_key_down_timeout_loop();
+        {
+            // the retention policy also applies when nothing is recorded
+            #[cfg(windows)]
+            let root = crate::platform::is_root();
+            #[cfg(not(windows))]
+            let root = false;
+            scrap::record::watch_record_dir(&crate::ui_interface::video_save_directory(root));
+        }
         #[cfg(target_os = "linux")]
         if input_service::wayland_use_uinput() {
             allow_err!(input_service::setup_uinput(0, 1920, 0, 1080).await);
@@ -496,6 +496,11 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
                     .encode_vec_float(&data[i * BATCH_SIZE..(i + 1) * BATCH_SIZE], BATCH_SIZE)
                 {
                     Ok(data) => {
+                        scrap::record::write_audio(
+                            &scrap::record::RecordSession::Incoming,
+                            scrap::record::RecordAudio::Local,
+                            &data,
+                        );
                         let mut msg_out = Message::new();
                         msg_out.set_audio_frame(AudioFrame {
                             data: data.into(),
@@ -515,6 +520,11 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
     #[cfg(not(target_os = "android"))]
     match encoder.encode_vec_float(data, data.len() * 6) {
         Ok(data) => {
+            scrap::record::write_audio(
+                &scrap::record::RecordSession::Incoming,
+                scrap::record::RecordAudio::Local,
+                &data,
+            );
             let mut msg_out = Message::new();
             msg_out.set_audio_frame(AudioFrame {
                 data: data.into(),
@@ -32,10 +32,10 @@ use hbb_common::{
     futures::{SinkExt, StreamExt},
     get_time, get_version_number,
     message_proto::{option_message::BoolOption, permission_info::Permission},
-    password_security::{self as password, ApproveMode},
+    password_security::{self as password, ApproveMode, ScheduleFallback},
     sleep, timeout,
     tokio::{
-        net::TcpStream,
+        net::{TcpListener, TcpStream},
         sync::mpsc,
         time::{self, Duration, Instant},
     },
@@ -58,12 +58,10 @@ use system_shutdown;
 
 #[cfg(windows)]
 use crate::virtual_display_manager;
-#[cfg(not(any(target_os = "ios")))]
 use std::collections::HashSet;
 pub type Sender = mpsc::UnboundedSender<(Instant, Arc<Message>)>;
 
 lazy_static::lazy_static! {
-    static ref LOGIN_FAILURES: [Arc::<Mutex<HashMap<String, (i32, i32, i32)>>>; 2] = Default::default();
     static ref SESSIONS: Arc::<Mutex<HashMap<SessionKey, Session>>> = Default::default();
     static ref ALIVE_CONNS: Arc::<Mutex<Vec<i32>>> = Default::default();
     pub static ref AUTHED_CONNS: Arc::<Mutex<Vec<(i32, AuthConnType, SessionKey)>>> = Default::default();
@@ -180,10 +178,20 @@ pub struct Connection {
     file_timer: crate::RustDeskInterval,
     file_transfer: Option<(String, bool)>,
     port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
+    // the listener is only bound once the connection is authorized
+    reverse_port_forward: bool,
+    reverse_port_forward_listener: Option<TcpListener>,
+    // the connections of the forward rules are opened by the controller as tunnels
+    port_forward_tunnels: bool,
     port_forward_address: String,
+    event_recorder: Option<event_recorder::EventRecorder>,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
     require_2fa: Option<totp_rs::TOTP>,
+    require_security_key: bool,
+    security_key_nonce: String,
+    // the access code validated by the password, consumed once the login succeeds
+    access_code: Option<String>,
     keyboard: bool,
     clipboard: bool,
     audio: bool,
@@ -192,6 +200,8 @@ pub struct Connection {
     recording: bool,
     block_input: bool,
     last_test_delay: Option<Instant>,
+    #[cfg(not(target_os = "ios"))]
+    last_wake_on_lan: Option<Instant>,
     network_delay: u32,
     lock_after_session_end: bool,
     show_remote_cursor: bool,
@@ -212,6 +222,8 @@ pub struct Connection {
     tx_input: std_mpsc::Sender<MessageInput>,
     // handle input messages
     video_ack_required: bool,
+    // displays whose frames are skipped to the next key frame
+    lagging_displays: HashSet<usize>,
     server_audit_conn: String,
     server_audit_file: String,
     lr: LoginRequest,
@@ -324,6 +336,9 @@ impl Connection {
                 tx_video: Some(tx_video),
             },
             require_2fa: crate::auth_2fa::get_2fa(None),
+            require_security_key: !crate::auth_2fa::get_security_keys().is_empty(),
+            security_key_nonce: "".to_owned(),
+            access_code: None,
             display_idx: *display_service::PRIMARY_DISPLAY_IDX,
             stream,
             server,
@@ -333,7 +348,11 @@ impl Connection {
             file_timer: crate::rustdesk_interval(time::interval(SEC30)),
             file_transfer: None,
             port_forward_socket: None,
+            reverse_port_forward: false,
+            reverse_port_forward_listener: None,
+            port_forward_tunnels: false,
             port_forward_address: "".to_owned(),
+            event_recorder: None,
             tx_to_cm,
             authorized: false,
             keyboard: Connection::permission("enable-keyboard"),
@@ -345,6 +364,8 @@ impl Connection {
             recording: Connection::permission("enable-record-session"),
             block_input: Connection::permission("enable-block-input"),
             last_test_delay: None,
+            #[cfg(not(target_os = "ios"))]
+            last_wake_on_lan: None,
             network_delay: 0,
             lock_after_session_end: false,
             show_remote_cursor: false,
@@ -359,6 +380,7 @@ impl Connection {
             disable_keyboard: false,
             tx_input,
             video_ack_required: false,
+            lagging_displays: Default::default(),
             server_audit_conn: "".to_owned(),
             server_audit_file: "".to_owned(),
             lr: Default::default(),
@@ -431,13 +453,12 @@ impl Connection {
             crate::rustdesk_interval(time::interval_at(Instant::now(), TEST_DELAY_TIMEOUT));
         let mut last_recv_time = Instant::now();
 
-        conn.stream.set_send_timeout(
-            if conn.file_transfer.is_some() || conn.port_forward_socket.is_some() {
+        conn.stream
+            .set_send_timeout(if conn.file_transfer.is_some() || conn.is_port_forward() {
                 SEND_TIMEOUT_OTHER
             } else {
                 SEND_TIMEOUT_VIDEO
-            },
-        );
+            });
 
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         std::thread::spawn(move || Self::handle_input(_rx_input, tx_cloned));
@@ -451,8 +472,9 @@ impl Connection {
                     match data {
                         ipc::Data::Authorize => {
                             conn.require_2fa.take();
+                            conn.require_security_key = false;
                             conn.send_logon_response().await;
-                            if conn.port_forward_socket.is_some() {
+                            if conn.is_port_forward() {
                                 break;
                             }
                         }
@@ -596,7 +618,7 @@ impl Connection {
                                     if !conn.on_message(msg_in).await {
                                         break;
                                     }
-                                    if conn.port_forward_socket.is_some() && conn.authorized {
+                                    if conn.is_port_forward() && conn.authorized {
                                         log::info!("Port forward, last_test_delay is none: {}", conn.last_test_delay.is_none());
                                         // Avoid TestDelay reply injection into rdp data stream
                                         if conn.last_test_delay.is_none() {
@@ -613,8 +635,16 @@ impl Connection {
                 },
                 _ = conn.file_timer.tick() => {
                     if !conn.read_jobs.is_empty() {
+                        let id = conn.inner.id();
+                        if !video_service::VIDEO_QOS.lock().unwrap().user_can_send(id) {
+                            continue;
+                        }
                         conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), fs::serialize_transfer_jobs(&conn.read_jobs))));
-                        match fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await {
+                        let transferred = conn.read_jobs_transferred();
+                        let res = fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await;
+                        let sent = conn.read_jobs_transferred().saturating_sub(transferred);
+                        video_service::VIDEO_QOS.lock().unwrap().user_sent(id, sent as _);
+                        match res {
                             Ok(log) => {
                                 if !log.is_empty() {
                                     conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), log)));
@@ -637,13 +667,21 @@ impl Connection {
                     }
                 }
                 Some((instant, value)) = rx_video.recv() => {
-                    if !conn.video_ack_required {
+                    // the lossless tiles are not frames the video service waits for
+                    if !conn.video_ack_required && !scrap::lossless::is_tiles(&value) {
                         video_service::notify_video_frame_fetched(id, Some(instant.into()));
                     }
+                    if conn.skip_video_frame(instant, &value) {
+                        continue;
+                    }
                     if let Err(err) = conn.stream.send(&value as &Message).await {
                         conn.on_close(&err.to_string(), false).await;
                         break;
                     }
+                    video_service::VIDEO_QOS
+                        .lock()
+                        .unwrap()
+                        .user_sent(conn.inner.id(), value.compute_size() as _);
                 },
                 Some((instant, value)) = rx.recv() => {
                     let latency = instant.elapsed().as_millis() as i64;
@@ -705,6 +743,10 @@ impl Connection {
                         conn.on_close(&err.to_string(), false).await;
                         break;
                     }
+                    video_service::VIDEO_QOS
+                        .lock()
+                        .unwrap()
+                        .user_sent(conn.inner.id(), msg.compute_size() as _);
                 },
                 _ = second_timer.tick() => {
                     #[cfg(windows)]
@@ -726,7 +768,7 @@ impl Connection {
                         break;
                     }
                     // The control end will jump out of the loop after receiving LoginResponse and will not reply to the TestDelay
-                    if conn.last_test_delay.is_none() && !(conn.port_forward_socket.is_some() && conn.authorized) {
+                    if conn.last_test_delay.is_none() && !(conn.is_port_forward() && conn.authorized) {
                         conn.last_test_delay = Some(Instant::now());
                         let mut msg_out = Message::new();
                         msg_out.set_test_delay(TestDelay{
@@ -924,10 +966,138 @@ impl Connection {
                     }
                 }
             }
+        } else if let Some(listener) = self.reverse_port_forward_listener.take() {
+            if self.authorized {
+                self.tunnel_loop(Some(listener), rx_from_cm).await?;
+            }
+        } else if self.port_forward_tunnels {
+            if self.authorized {
+                self.tunnel_loop(None, rx_from_cm).await?;
+            }
         }
         Ok(())
     }
 
+    // The connections accepted by `listener` of a reverse port forward are opened to the
+    // controller, without `listener` the controller opens the connections of its forward rules.
+    async fn tunnel_loop(
+        &mut self,
+        listener: Option<TcpListener>,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        log::info!("Running tunnel loop, reverse: {}", listener.is_some());
+        let mut last_recv_time = Instant::now();
+        let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        let (tx, mut rx) = mpsc::unbounded_channel::<TunnelData>();
+        let (tx_connected, mut rx_connected) =
+            mpsc::unbounded_channel::<(u32, String, Option<TcpStream>)>();
+        let mut tunnels: HashMap<u32, mpsc::UnboundedSender<Bytes>> = HashMap::new();
+        let mut next_id: u32 = 0;
+        loop {
+            tokio::select! {
+                Some(data) = rx_from_cm.recv() => {
+                    match data {
+                        ipc::Data::Close => {
+                            bail!("Close requested from connection manager");
+                        }
+                        ipc::Data::CmErr(e) => {
+                            log::error!("Connection manager error: {e}");
+                            bail!("{e}");
+                        }
+                        _ => {}
+                    }
+                }
+                res = async {
+                    match listener.as_ref() {
+                        Some(listener) => listener.accept().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    let (sock, addr) = res?;
+                    next_id = next_id.wrapping_add(1);
+                    log::info!("new reverse port forwarding connection {} from {:?}", next_id, addr);
+                    let (tx_data, rx_data) = mpsc::unbounded_channel();
+                    tunnels.insert(next_id, tx_data);
+                    let mut td = TunnelData::new();
+                    td.id = next_id;
+                    td.set_open(true);
+                    let mut msg_out = Message::new();
+                    msg_out.set_tunnel_data(td);
+                    self.stream.send(&msg_out).await?;
+                    tokio::spawn(crate::common::run_tunnel(next_id, sock, tx.clone(), rx_data));
+                }
+                Some((id, target, sock)) = rx_connected.recv() => {
+                    let mut td = TunnelData::new();
+                    td.id = id;
+                    if let Some(sock) = sock {
+                        log::info!("new port forwarding connection {} to {}", id, target);
+                        let (tx_data, rx_data) = mpsc::unbounded_channel();
+                        tunnels.insert(id, tx_data);
+                        tokio::spawn(crate::common::run_tunnel(id, sock, tx.clone(), rx_data));
+                        td.set_open(true);
+                    } else {
+                        log::error!("Failed to connect to {}", target);
+                        td.set_close(true);
+                    }
+                    let mut msg_out = Message::new();
+                    msg_out.set_tunnel_data(td);
+                    self.stream.send(&msg_out).await?;
+                }
+                Some(td) = rx.recv() => {
+                    last_recv_time = Instant::now();
+                    if td.has_close() {
+                        tunnels.remove(&td.id);
+                    }
+                    let mut msg_out = Message::new();
+                    msg_out.set_tunnel_data(td);
+                    self.stream.send(&msg_out).await?;
+                }
+                res = self.stream.next() => {
+                    let Some(res) = res else {
+                        bail!("Stream reset by the peer");
+                    };
+                    last_recv_time = Instant::now();
+                    let msg_in = Message::parse_from_bytes(&res?)?;
+                    if let Some(message::Union::TunnelData(td)) = msg_in.union {
+                        match td.union {
+                            Some(tunnel_data::Union::Open(_)) if listener.is_none() => {
+                                let id = td.id;
+                                let target = td.target;
+                                let tx_connected = tx_connected.clone();
+                                tokio::spawn(async move {
+                                    let sock = timeout(3000, TcpStream::connect(&target))
+                                        .await
+                                        .ok()
+                                        .and_then(|res| res.ok());
+                                    tx_connected.send((id, target, sock)).ok();
+                                });
+                            }
+                            Some(tunnel_data::Union::Data(data)) => {
+                                if let Some(tx_data) = tunnels.get(&td.id) {
+                                    tx_data.send(data).ok();
+                                }
+                            }
+                            Some(tunnel_data::Union::Close(_)) => {
+                                tunnels.remove(&td.id);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ = self.timer.tick() => {
+                    if last_recv_time.elapsed() >= H1 {
+                        bail!("Timeout");
+                    }
+                }
+                Ok(conns) = hbbs_rx.recv() => {
+                    if conns.contains(&self.inner.id) {
+                        bail!("Closed manually by the web console");
+                    }
+                }
+            }
+        }
+    }
+
     async fn send_permission(&mut self, permission: Permission, enabled: bool) {
         let mut misc = Misc::new();
         misc.set_permission_info(PermissionInfo {
@@ -979,6 +1149,24 @@ impl Connection {
         true
     }
 
+    // Whether the logins with credentials are allowed by the access schedule now, the login falls
+    // back to click-to-approve if not. `None` if the login is rejected.
+    async fn check_access_schedule(&mut self) -> Option<bool> {
+        if password::in_access_schedule() {
+            return Some(true);
+        }
+        if password::schedule_fallback() == ScheduleFallback::Reject {
+            self.send_login_error(crate::client::LOGIN_MSG_OUTSIDE_ACCESS_SCHEDULE)
+                .await;
+            Self::post_alarm_audit(
+                AlarmAuditType::OutsideAccessSchedule,
+                json!({ "ip": self.ip }),
+            );
+            return None;
+        }
+        Some(false)
+    }
+
     async fn on_open(&mut self, addr: SocketAddr) -> bool {
         log::debug!("#{} Connection opened from {}.", self.inner.id, addr);
         if !self.check_whitelist(&addr).await {
@@ -1094,7 +1282,16 @@ impl Connection {
         if self.authorized {
             return;
         }
-        if self.require_2fa.is_some() && !self.is_recent_session(true) && !self.from_switch {
+        if self.require_second_factor() && !self.is_recent_session(true) && !self.from_switch {
+            if self.require_security_key {
+                self.security_key_nonce = crate::auth_2fa::new_security_key_nonce();
+                let mut msg_out = Message::new();
+                msg_out.set_auth_2fa(Auth2FA {
+                    nonce: self.security_key_nonce.clone(),
+                    ..Default::default()
+                });
+                self.send(msg_out).await;
+            }
             self.require_2fa.as_ref().map(|totp| {
                 let bot = crate::auth_2fa::TelegramBot::get();
                 let bot = match bot {
@@ -1125,10 +1322,32 @@ impl Connection {
             self.send_login_error(crate::client::REQUIRE_2FA).await;
             return;
         }
+        if self.reverse_port_forward && self.reverse_port_forward_listener.is_none() {
+            // without reuse, a port of a running local service can not be taken over
+            let addr = self.port_forward_address.clone();
+            match hbb_common::tcp::new_listener(&addr, false).await {
+                Ok(listener) => {
+                    self.reverse_port_forward_listener = Some(listener);
+                }
+                Err(err) => {
+                    self.send_login_error(format!("Failed to listen on {}: {}", addr, err))
+                        .await;
+                    return;
+                }
+            }
+        }
+        if let Some(code) = self.access_code.take() {
+            // taken by another login meanwhile
+            if Config::take_access_code(&code).is_none() {
+                self.send_login_error(crate::client::LOGIN_MSG_PASSWORD_WRONG)
+                    .await;
+                return;
+            }
+        }
         self.authorized = true;
         let (conn_type, auth_conn_type) = if self.file_transfer.is_some() {
             (1, AuthConnType::FileTransfer)
-        } else if self.port_forward_socket.is_some() {
+        } else if self.is_port_forward() {
             (2, AuthConnType::PortForward)
         } else {
             (0, AuthConnType::Remote)
@@ -1146,6 +1365,17 @@ impl Connection {
         self.post_conn_audit(
             json!({"peer": ((&self.lr.my_id, &self.lr.my_name)), "type": conn_type}),
         );
+        if !self.is_port_forward()
+            && config::option2bool(
+                "allow-auto-record-incoming",
+                &Config::get_option("allow-auto-record-incoming"),
+            )
+        {
+            match event_recorder::EventRecorder::new(&self.lr.my_id, &self.lr.my_name) {
+                Ok(recorder) => self.event_recorder = Some(recorder),
+                Err(err) => log::error!("Failed to start event recording: {}", err),
+            }
+        }
         #[allow(unused_mut)]
         let mut username = crate::platform::get_active_username();
         let mut res = LoginResponse::new();
@@ -1216,7 +1446,7 @@ impl Connection {
             pi.platform_additions = serde_json::to_string(&platform_additions).unwrap_or("".into());
         }
 
-        if self.port_forward_socket.is_some() {
+        if self.is_port_forward() {
             let mut msg_out = Message::new();
             res.set_peer_info(pi);
             msg_out.set_login_response(res);
@@ -1224,7 +1454,7 @@ impl Connection {
             return;
         }
         #[cfg(target_os = "linux")]
-        if !self.file_transfer.is_some() && !self.port_forward_socket.is_some() {
+        if !self.file_transfer.is_some() && !self.is_port_forward() {
             let mut msg = "".to_string();
             if crate::platform::linux::is_login_screen_wayland() {
                 msg = crate::client::LOGIN_SCREEN_WAYLAND.to_owned()
@@ -1356,7 +1586,7 @@ impl Connection {
     }
 
     fn try_sub_services(&mut self) {
-        let is_remote = self.file_transfer.is_none() && self.port_forward_socket.is_none();
+        let is_remote = self.file_transfer.is_none() && !self.is_port_forward();
         if is_remote && !self.services_subed {
             self.services_subed = true;
             if let Some(s) = self.server.upgrade() {
@@ -1458,6 +1688,11 @@ impl Connection {
         self.file && self.enable_file_transfer
     }
 
+    #[inline]
+    fn is_port_forward(&self) -> bool {
+        self.port_forward_socket.is_some() || self.reverse_port_forward || self.port_forward_tunnels
+    }
+
     fn try_start_cm(&mut self, peer_id: String, name: String, authorized: bool) {
         self.send_to_cm(ipc::Data::Login {
             id: self.inner.id(),
@@ -1569,7 +1804,80 @@ impl Connection {
                 return true;
             }
         }
-        false
+        self.validate_access_code()
+    }
+
+    // Access codes are not kept in the recent sessions, the scope would be lost on reconnection.
+    fn validate_access_code(&mut self) -> bool {
+        let Some(code) = Config::get_access_codes()
+            .into_iter()
+            .find(|c| self.validate_one_password(c.code.clone()))
+        else {
+            return false;
+        };
+        if self.is_port_forward() || self.file_transfer.is_some() && !code.file_transfer {
+            log::info!("Access code out of scope");
+            return false;
+        }
+        self.access_code = Some(code.code.clone());
+        log::info!(
+            "Access code used, view only: {}, file transfer: {}, clipboard: {}",
+            code.view_only,
+            code.file_transfer,
+            code.clipboard
+        );
+        self.restrict_permissions(code.view_only, code.file_transfer, code.clipboard);
+        true
+    }
+
+    // A key not in the authorized keys falls back to the password.
+    fn validate_public_key(&mut self) -> bool {
+        let Some(auth) = self.lr.public_key_auth.as_ref() else {
+            return false;
+        };
+        if auth.pk.is_empty() {
+            return false;
+        }
+        let Some(key) = password::authorized_keys()
+            .into_iter()
+            .find(|k| k.pk[..] == auth.pk[..])
+        else {
+            return false;
+        };
+        if !password::verify_login_challenge(
+            &auth.pk,
+            &auth.signature,
+            &self.hash.challenge,
+            &self.lr.username,
+        ) {
+            log::warn!("Invalid signature of authorized key {}", key.comment);
+            return false;
+        }
+        if self.is_port_forward() && key.view_only
+            || self.file_transfer.is_some() && !key.file_transfer
+        {
+            log::info!("Authorized key {} out of scope", key.comment);
+            return false;
+        }
+        log::info!(
+            "Authorized key {} used, view only: {}, file transfer: {}, clipboard: {}",
+            key.comment,
+            key.view_only,
+            key.file_transfer,
+            key.clipboard
+        );
+        self.restrict_permissions(key.view_only, key.file_transfer, key.clipboard);
+        true
+    }
+
+    fn restrict_permissions(&mut self, view_only: bool, file_transfer: bool, clipboard: bool) {
+        if view_only {
+            self.keyboard = false;
+            self.restart = false;
+            self.block_input = false;
+        }
+        self.file &= file_transfer;
+        self.clipboard &= clipboard;
     }
 
     fn is_recent_session(&mut self, tfa: bool) -> bool {
@@ -1625,6 +1933,11 @@ impl Connection {
         }
     }
 
+    #[inline]
+    fn require_second_factor(&self) -> bool {
+        self.require_2fa.is_some() || self.require_security_key
+    }
+
     #[inline]
     fn enable_trusted_devices() -> bool {
         config::option2bool(
@@ -1638,7 +1951,7 @@ impl Connection {
         if let Some(o) = lr.option.as_ref() {
             self.options_in_login = Some(o.clone());
         }
-        if self.require_2fa.is_some() && !lr.hwid.is_empty() && Self::enable_trusted_devices() {
+        if self.require_second_factor() && !lr.hwid.is_empty() && Self::enable_trusted_devices() {
             let devices = Config::get_trusted_devices();
             if let Some(device) = devices.iter().find(|d| d.hwid == lr.hwid) {
                 if !device.outdate()
@@ -1648,6 +1961,7 @@ impl Connection {
                 {
                     log::info!("2FA bypassed by trusted devices");
                     self.require_2fa = None;
+                    self.require_security_key = false;
                 }
             }
         }
@@ -1704,6 +2018,16 @@ impl Connection {
                     }
                     self.file_transfer = Some((ft.dir, ft.show_hidden));
                 }
+                Some(login_request::Union::PortForward(pf)) if pf.tunnels => {
+                    if !Connection::permission("enable-tunnel") {
+                        self.send_login_error("No permission of IP tunneling").await;
+                        sleep(1.).await;
+                        return false;
+                    }
+                    // the targets are connected as the controller opens them
+                    self.port_forward_address = "rules".to_owned();
+                    self.port_forward_tunnels = true;
+                }
                 Some(login_request::Union::PortForward(mut pf)) => {
                     if !Connection::permission("enable-tunnel") {
                         self.send_login_error("No permission of IP tunneling").await;
@@ -1738,6 +2062,21 @@ impl Connection {
                         }
                     }
                 }
+                Some(login_request::Union::ReversePortForward(rpf)) => {
+                    if !Connection::permission("enable-tunnel") {
+                        self.send_login_error("No permission of IP tunneling").await;
+                        sleep(1.).await;
+                        return false;
+                    }
+                    if rpf.port <= 0 || rpf.port > 65535 {
+                        self.send_login_error("Invalid port of reverse port forwarding")
+                            .await;
+                        return false;
+                    }
+                    // only local processes can use the tunnel, like ssh -R
+                    self.port_forward_address = format!("127.0.0.1:{}", rpf.port);
+                    self.reverse_port_forward = true;
+                }
                 _ => {
                     if !self.check_privacy_mode_on().await {
                         return false;
@@ -1762,6 +2101,26 @@ impl Connection {
                 return true;
             }
 
+            let click_only = (password::approve_mode() == ApproveMode::Click
+                && !(crate::platform::is_prelogin()
+                    && crate::get_builtin_option(keys::OPTION_ALLOW_LOGON_SCREEN_PASSWORD) == "Y"))
+                || password::approve_mode() == ApproveMode::Both && !password::has_valid_password();
+            // the schedule only restricts the logins with a password, an access code or a key
+            let has_credential = !lr.password.is_empty()
+                || lr
+                    .public_key_auth
+                    .as_ref()
+                    .map_or(false, |auth| !auth.pk.is_empty());
+            let in_schedule = if click_only || !has_credential {
+                true
+            } else {
+                let Some(in_schedule) = self.check_access_schedule().await else {
+                    sleep(1.).await;
+                    return false;
+                };
+                in_schedule
+            };
+
             if !hbb_common::is_ip_str(&lr.username)
                 && !hbb_common::is_domain_port_str(&lr.username)
                 && lr.username != Config::get_id()
@@ -1769,11 +2128,7 @@ impl Connection {
                 self.send_login_error(crate::client::LOGIN_MSG_OFFLINE)
                     .await;
                 return false;
-            } else if (password::approve_mode() == ApproveMode::Click
-                && !(crate::platform::is_prelogin()
-                    && crate::get_builtin_option(keys::OPTION_ALLOW_LOGON_SCREEN_PASSWORD) == "Y"))
-                || password::approve_mode() == ApproveMode::Both && !password::has_valid_password()
-            {
+            } else if click_only || !in_schedule {
                 self.try_start_cm(lr.my_id, lr.my_name, false);
                 if hbb_common::get_version_number(&lr.version)
                     >= hbb_common::get_version_number("1.2.0")
@@ -1791,6 +2146,15 @@ impl Connection {
                 } else {
                     self.send_login_error(err_msg).await;
                 }
+            } else if self.validate_public_key() {
+                if err_msg.is_empty() {
+                    #[cfg(target_os = "linux")]
+                    self.linux_headless_handle.wait_desktop_cm_ready().await;
+                    self.send_logon_response().await;
+                    self.try_start_cm(lr.my_id, lr.my_name, self.authorized);
+                } else {
+                    self.send_login_error(err_msg).await;
+                }
             } else if lr.password.is_empty() {
                 if err_msg.is_empty() {
                     self.try_start_cm(lr.my_id, lr.my_name, false);
@@ -1801,12 +2165,11 @@ impl Connection {
                     .await;
                 }
             } else {
-                let (failure, res) = self.check_failure(0).await;
-                if !res {
+                if !self.check_failure(login_failure::KIND_PASSWORD).await {
                     return true;
                 }
                 if !self.validate_password() {
-                    self.update_failure(failure, false, 0);
+                    self.update_failure(false, login_failure::KIND_PASSWORD);
                     if err_msg.is_empty() {
                         self.send_login_error(crate::client::LOGIN_MSG_PASSWORD_WRONG)
                             .await;
@@ -1818,7 +2181,7 @@ impl Connection {
                         .await;
                     }
                 } else {
-                    self.update_failure(failure, true, 0);
+                    self.update_failure(true, login_failure::KIND_PASSWORD);
                     if err_msg.is_empty() {
                         #[cfg(target_os = "linux")]
                         self.linux_headless_handle.wait_desktop_cm_ready().await;
@@ -1830,37 +2193,56 @@ impl Connection {
                 }
             }
         } else if let Some(message::Union::Auth2fa(tfa)) = msg.union {
-            let (failure, res) = self.check_failure(1).await;
-            if !res {
+            if !self.check_failure(login_failure::KIND_2FA).await {
                 return true;
             }
-            if let Some(totp) = self.require_2fa.as_ref() {
-                if let Ok(res) = totp.check_current(&tfa.code) {
-                    if res {
-                        self.update_failure(failure, true, 1);
-                        self.require_2fa.take();
-                        raii::AuthedConnID::set_session_2fa(self.session_key());
-                        self.send_logon_response().await;
-                        self.try_start_cm(
-                            self.lr.my_id.to_owned(),
-                            self.lr.my_name.to_owned(),
-                            self.authorized,
-                        );
-                        if !tfa.hwid.is_empty() && Self::enable_trusted_devices() {
-                            Config::add_trusted_device(TrustedDevice {
-                                hwid: tfa.hwid,
-                                time: hbb_common::get_time(),
-                                id: self.lr.my_id.clone(),
-                                name: self.lr.my_name.clone(),
-                                platform: self.lr.my_platform.clone(),
-                            });
-                        }
-                    } else {
-                        self.update_failure(failure, false, 1);
-                        self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
-                            .await;
+            // an unknown security key is no answer, the code is checked instead
+            let mut res = None;
+            if !tfa.signature.is_empty() {
+                res = crate::auth_2fa::verify_security_key(
+                    &self.security_key_nonce,
+                    &self.lr.username,
+                    &tfa,
+                );
+                if res.is_some() {
+                    self.security_key_nonce.clear();
+                }
+            }
+            if res.is_none() && !tfa.code.is_empty() {
+                if let Some(totp) = self.require_2fa.as_ref() {
+                    res = totp.check_current(&tfa.code).ok();
+                }
+            }
+            if let Some(res) = res {
+                if res {
+                    self.update_failure(true, login_failure::KIND_2FA);
+                    self.require_2fa.take();
+                    self.require_security_key = false;
+                    raii::AuthedConnID::set_session_2fa(self.session_key());
+                    self.send_logon_response().await;
+                    self.try_start_cm(
+                        self.lr.my_id.to_owned(),
+                        self.lr.my_name.to_owned(),
+                        self.authorized,
+                    );
+                    if !tfa.hwid.is_empty() && Self::enable_trusted_devices() {
+                        Config::add_trusted_device(TrustedDevice {
+                            hwid: tfa.hwid,
+                            time: hbb_common::get_time(),
+                            id: self.lr.my_id.clone(),
+                            name: self.lr.my_name.clone(),
+                            platform: self.lr.my_platform.clone(),
+                        });
                     }
+                } else {
+                    self.update_failure(false, login_failure::KIND_2FA);
+                    self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
+                        .await;
                 }
+            } else {
+                // e.g. a code typed into an old client while only security keys are configured
+                self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
+                    .await;
             }
         } else if let Some(message::Union::TestDelay(t)) = msg.union {
             if t.from_client {
@@ -1905,9 +2287,25 @@ impl Connection {
                 }
             }
         } else if self.authorized {
-            if self.port_forward_socket.is_some() {
+            if self.is_port_forward() {
                 return true;
             }
+            if let Some(recorder) = self.event_recorder.as_mut() {
+                recorder.write_message(&msg);
+            }
+            if self.peer_keyboard_enabled()
+                && matches!(
+                    msg.union,
+                    Some(message::Union::MouseEvent(_))
+                        | Some(message::Union::KeyEvent(_))
+                        | Some(message::Union::PointerDeviceEvent(_))
+                )
+            {
+                video_service::VIDEO_QOS
+                    .lock()
+                    .unwrap()
+                    .user_input(self.inner.id());
+            }
             match msg.union {
                 #[allow(unused_mut)]
                 Some(message::Union::MouseEvent(mut me)) => {
@@ -2292,6 +2690,11 @@ impl Connection {
                             Some(file_action::Union::SendConfirm(r)) => {
                                 if let Some(job) = fs::get_job(r.id, &mut self.read_jobs) {
                                     job.confirm(&r);
+                                } else if r.request_signatures {
+                                    self.send_fs(ipc::FS::GetSignatures {
+                                        id: r.id,
+                                        file_num: r.file_num,
+                                    });
                                 }
                             }
                             Some(file_action::Union::Rename(r)) => {
@@ -2321,6 +2724,8 @@ impl Connection {
                             file_num: block.file_num,
                             data: block.data,
                             compressed: block.compressed,
+                            blk_id: block.blk_id,
+                            copy_blks: block.copy_blks,
                         });
                     }
                     Some(file_response::Union::Done(d)) => {
@@ -2335,6 +2740,8 @@ impl Connection {
                         file_size: d.file_size,
                         last_modified: d.last_modified,
                         is_upload: true,
+                        can_resume: d.can_resume,
+                        can_delta: d.can_delta,
                     }),
                     Some(file_response::Union::Error(e)) => {
                         self.send_fs(ipc::FS::WriteError {
@@ -2431,7 +2838,8 @@ impl Connection {
                         if !self.disable_audio {
                             // Drop the audio sender previously.
                             drop(std::mem::replace(&mut self.audio_sender, None));
-                            self.audio_sender = Some(start_audio_thread());
+                            self.audio_sender =
+                                Some(start_audio_thread(scrap::record::RecordSession::Incoming));
                             self.audio_sender
                                 .as_ref()
                                 .map(|a| allow_err!(a.send(MediaData::AudioFormat(format))));
@@ -2464,6 +2872,8 @@ impl Connection {
                             crate::plugin::handle_client_event(&p.id, &self.lr.my_id, &p.content);
                         self.send(msg).await;
                     }
+                    #[cfg(not(target_os = "ios"))]
+                    Some(misc::Union::WakeOnLan(wol)) => self.handle_wake_on_lan(wol).await,
                     Some(misc::Union::AutoAdjustFps(fps)) => video_service::VIDEO_QOS
                         .lock()
                         .unwrap()
@@ -2540,61 +2950,91 @@ impl Connection {
         true
     }
 
-    fn update_failure(&self, (mut failure, time): ((i32, i32, i32), i32), remove: bool, i: usize) {
-        if remove {
-            if failure.0 != 0 {
-                LOGIN_FAILURES[i].lock().unwrap().remove(&self.ip);
+    fn update_failure(&self, success: bool, kind: usize) {
+        for lockout in login_failure::update(kind, &self.ip, &self.lr.my_id, success) {
+            log::warn!("Login failures reach the threshold: {:?}", lockout);
+            // the alarms of the older versions, for the audit servers not knowing `LoginLockout`
+            if lockout.locked() {
+                let legacy = if lockout.failures() > 30 {
+                    AlarmAuditType::ExceedThirtyAttempts
+                } else {
+                    AlarmAuditType::SixAttemptsWithinOneMinute
+                };
+                Self::post_alarm_audit(
+                    legacy,
+                    json!({
+                                "ip": self.ip,
+                                "id": self.lr.my_id.clone(),
+                                "name": self.lr.my_name.clone(),
+                    }),
+                );
             }
-            return;
-        }
-        if failure.0 == time {
-            failure.1 += 1;
-            failure.2 += 1;
-        } else {
-            failure.0 = time;
-            failure.1 = 1;
-            failure.2 += 1;
-        }
-        LOGIN_FAILURES[i]
-            .lock()
-            .unwrap()
-            .insert(self.ip.clone(), failure);
-    }
-
-    async fn check_failure(&mut self, i: usize) -> (((i32, i32, i32), i32), bool) {
-        let failure = LOGIN_FAILURES[i]
-            .lock()
-            .unwrap()
-            .get(&self.ip)
-            .map(|x| x.clone())
-            .unwrap_or((0, 0, 0));
-        let time = (get_time() / 60_000) as i32;
-        let res = if failure.2 > 30 {
-            self.send_login_error("Too many wrong attempts").await;
             Self::post_alarm_audit(
-                AlarmAuditType::ExceedThirtyAttempts,
+                AlarmAuditType::LoginLockout,
                 json!({
                             "ip": self.ip,
                             "id": self.lr.my_id.clone(),
                             "name": self.lr.my_name.clone(),
+                            "source": lockout.source,
+                            "locked": lockout.locked(),
+                            "until": lockout.until,
+                            "lockouts": lockout.lockouts,
+                            "failures": lockout.failures(),
                 }),
             );
-            false
-        } else if time == failure.0 && failure.1 > 6 {
-            self.send_login_error("Please try 1 minute later").await;
-            Self::post_alarm_audit(
-                AlarmAuditType::SixAttemptsWithinOneMinute,
-                json!({
-                            "ip": self.ip,
-                            "id": self.lr.my_id.clone(),
-                            "name": self.lr.my_name.clone(),
-                }),
-            );
-            false
-        } else {
-            true
+        }
+    }
+
+    async fn check_failure(&mut self, kind: usize) -> bool {
+        if let Some(until) = login_failure::locked_until(kind, &self.ip) {
+            let minutes = (until - get_time() + 59_999) / 60_000;
+            self.send_login_error(format!(
+                "Too many wrong attempts, please try again in {} minute(s)",
+                minutes.max(1)
+            ))
+            .await;
+            return false;
+        }
+        true
+    }
+
+    fn read_jobs_transferred(&self) -> u64 {
+        self.read_jobs.iter().map(|job| job.transferred()).sum()
+    }
+
+    // A viewer in best effort, whose frames are delayed too much, skips to the next periodic key
+    // frame. No key frame is requested, which would be sent to all the viewers.
+    fn skip_video_frame(&mut self, instant: Instant, msg: &Message) -> bool {
+        const MAX_LATENCY: Duration = Duration::from_millis(1000);
+        let Some(message::Union::VideoFrame(vf)) = &msg.union else {
+            return false;
+        };
+        let display = vf.display as usize;
+        let key = match &vf.union {
+            Some(video_frame::Union::Vp8s(f))
+            | Some(video_frame::Union::Vp9s(f))
+            | Some(video_frame::Union::Av1s(f))
+            | Some(video_frame::Union::H264s(f))
+            | Some(video_frame::Union::H265s(f)) => f.frames.iter().any(|e| e.key),
+            _ => false,
         };
-        ((failure, time), res)
+        if key {
+            self.lagging_displays.remove(&display);
+            return false;
+        }
+        if !self.lagging_displays.contains(&display) {
+            if instant.elapsed() < MAX_LATENCY
+                || !video_service::VIDEO_QOS
+                    .lock()
+                    .unwrap()
+                    .is_best_effort(self.inner.id())
+            {
+                return false;
+            }
+            log::debug!("Viewer {} lags on display {}", self.inner.id(), display);
+            self.lagging_displays.insert(display);
+        }
+        true
     }
 
     fn refresh_video_display(&self, display: Option<usize>) {
@@ -2711,6 +3151,46 @@ impl Connection {
         }
     }
 
+    // Relay the magic packets for the controller, whose devices are asleep on the LAN of this one.
+    #[cfg(not(target_os = "ios"))]
+    async fn handle_wake_on_lan(&mut self, wol: WakeOnLan) {
+        const MAX_MACS: usize = 16;
+        const MIN_INTERVAL: Duration = Duration::from_secs(5);
+        let text = if !config::option2bool(
+            keys::OPTION_ALLOW_WOL_RELAY,
+            &Config::get_option(keys::OPTION_ALLOW_WOL_RELAY),
+        ) || self.file_transfer.is_some()
+            || self.is_port_forward()
+        {
+            "Wake-on-LAN relay is not allowed by the remote side.".to_owned()
+        } else if self
+            .last_wake_on_lan
+            .map(|t| t.elapsed() < MIN_INTERVAL)
+            .unwrap_or_default()
+        {
+            "Wake-on-LAN is requested too frequently, please try again later.".to_owned()
+        } else {
+            self.last_wake_on_lan = Some(Instant::now());
+            // the macs without an ip, sent by older controllers, are ignored
+            let targets: Vec<(String, String)> =
+                wol.ips.into_iter().zip(wol.macs).take(MAX_MACS).collect();
+            log::info!("Relay wol of {:?} for {}", targets, self.lr.my_id);
+            if crate::lan::send_wol_subnet(&targets) > 0 {
+                "Wake-on-LAN packets are sent by the remote side.".to_owned()
+            } else {
+                "No MAC address to wake up in the networks of the remote side.".to_owned()
+            }
+        };
+        let mut msg_out = Message::new();
+        msg_out.set_message_box(MessageBox {
+            msgtype: "custom-nocancel-nook-hasclose".to_owned(),
+            title: "Wake-on-LAN".to_owned(),
+            text,
+            ..Default::default()
+        });
+        self.send(msg_out).await;
+    }
+
     #[cfg(windows)]
     async fn toggle_virtual_display(&mut self, t: ToggleVirtualDisplay) {
         let make_msg = |text: String| {
@@ -3140,6 +3620,7 @@ impl Connection {
         let data = ipc::Data::Close;
         self.tx_to_cm.send(data).ok();
         self.port_forward_socket.take();
+        self.reverse_port_forward_listener.take();
     }
 
     // The `reason` should be consistent with `check_if_retry` if not empty
@@ -3185,7 +3666,7 @@ impl Connection {
     fn portable_check(&mut self) {
         if self.portable.is_installed
             || self.file_transfer.is_some()
-            || self.port_forward_socket.is_some()
+            || self.is_port_forward()
             || !self.keyboard
         {
             return;
@@ -3461,8 +3942,10 @@ async fn start_ipc(
                         if let Data::FS(ipc::FS::WriteBlock{id,
                             file_num,
                             data,
-                            compressed}) = data {
-                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed})).await?;
+                            compressed,
+                            blk_id,
+                            copy_blks}) = data {
+                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed, blk_id, copy_blks})).await?;
                                 stream.send_raw(data).await?;
                         } else {
                             stream.send(&data).await?;
@@ -3489,8 +3972,12 @@ fn try_activate_screen() {
 
 pub enum AlarmAuditType {
     IpWhitelist = 0,
+    // Still posted with `LoginLockout` for compatibility, they were the fixed thresholds of the
+    // login failures: more than 30 in total, and more than 6 within a minute.
     ExceedThirtyAttempts = 1,
     SixAttemptsWithinOneMinute = 2,
+    OutsideAccessSchedule = 3,
+    LoginLockout = 4,
 }
 
 pub enum FileAuditType {
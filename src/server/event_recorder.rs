@@ -0,0 +1,391 @@
+// Companion of the incoming video recording, which records the input, clipboard and file
+// actions of the controller as json lines, one event per line with a unix timestamp in ms.
+// `make_player` renders a recorded video with these events as an overlay.
+// The typed text and the clipboard content, which may be passwords, are masked unless
+// `OPTION_ALLOW_RECORD_INPUT_TEXT` is enabled.
+
+use super::*;
+use crate::common::input::*;
+use hbb_common::{
+    chrono,
+    config::{self, keys},
+    protobuf::EnumOrUnknown,
+};
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_CLIPBOARD_TEXT_LEN: usize = 4096;
+
+pub struct EventRecorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    written: bool,
+    last_flush: Instant,
+    record_text: bool,
+}
+
+impl EventRecorder {
+    pub fn new(peer_id: &str, peer_name: &str) -> ResultType<Self> {
+        #[cfg(windows)]
+        let root = crate::platform::is_root();
+        #[cfg(not(windows))]
+        let root = false;
+        let dir = PathBuf::from(crate::ui_interface::video_save_directory(root));
+        if !dir.exists() {
+            std::fs::create_dir_all(&dir)?;
+        }
+        // the same naming as the video files, so they are listed together
+        let path = dir.join(format!(
+            "incoming_{}{}events.jsonl",
+            Config::get_id(),
+            chrono::Local::now().format("_%Y%m%d%H%M%S%3f_")
+        ));
+        let writer = BufWriter::new(File::create(&path)?);
+        scrap::record::set_recording(&path.to_string_lossy(), true);
+        let mut recorder = Self {
+            writer,
+            path,
+            written: false,
+            last_flush: Instant::now(),
+            record_text: config::option2bool(
+                keys::OPTION_ALLOW_RECORD_INPUT_TEXT,
+                &Config::get_option(keys::OPTION_ALLOW_RECORD_INPUT_TEXT),
+            ),
+        };
+        let displays: Vec<Value> = display_service::get_sync_displays()
+            .iter()
+            .map(|d| json!({"x": d.x, "y": d.y, "width": d.width, "height": d.height}))
+            .collect();
+        recorder.write(json!({
+            "type": "start",
+            "peer_id": peer_id,
+            "peer_name": peer_name,
+            "displays": displays,
+            // the time in the names of the video files is local
+            "utc_offset": chrono::Local::now().offset().local_minus_utc(),
+        }));
+        recorder.written = false;
+        log::info!("Recording events to {:?}", recorder.path);
+        Ok(recorder)
+    }
+
+    pub fn write_message(&mut self, msg: &Message) {
+        match &msg.union {
+            Some(message::Union::MouseEvent(me)) => {
+                let action = match me.mask & 0x7 {
+                    MOUSE_TYPE_MOVE => "move",
+                    MOUSE_TYPE_DOWN => "down",
+                    MOUSE_TYPE_UP => "up",
+                    MOUSE_TYPE_WHEEL => "wheel",
+                    MOUSE_TYPE_TRACKPAD => "trackpad",
+                    _ => "other",
+                };
+                self.write(json!({
+                    "type": "mouse",
+                    "action": action,
+                    "buttons": me.mask >> 3,
+                    "x": me.x,
+                    "y": me.y,
+                    "modifiers": modifiers_to_json(&me.modifiers),
+                }));
+            }
+            Some(message::Union::KeyEvent(ke)) => {
+                let mut event = json!({
+                    "type": "key",
+                    "down": ke.down,
+                    "press": ke.press,
+                    "modifiers": modifiers_to_json(&ke.modifiers),
+                });
+                match &ke.union {
+                    Some(key_event::Union::ControlKey(ck)) => {
+                        event["control_key"] = json!(format!("{:?}", ck.enum_value_or_default()));
+                    }
+                    Some(key_event::Union::Chr(_))
+                    | Some(key_event::Union::Unicode(_))
+                    | Some(key_event::Union::Seq(_))
+                        if !self.record_text =>
+                    {
+                        event["masked"] = json!(true);
+                    }
+                    Some(key_event::Union::Chr(chr)) => {
+                        event["chr"] = json!(chr);
+                    }
+                    Some(key_event::Union::Unicode(unicode)) => {
+                        event["text"] = json!(std::char::from_u32(*unicode)
+                            .map(|c| c.to_string())
+                            .unwrap_or_default());
+                    }
+                    Some(key_event::Union::Seq(seq)) => {
+                        event["text"] = json!(seq);
+                    }
+                    _ => {}
+                }
+                self.write(event);
+            }
+            Some(message::Union::Clipboard(cb)) => {
+                self.write(clipboard_to_json(cb, self.record_text));
+            }
+            Some(message::Union::MultiClipboards(mcb)) => {
+                for cb in mcb.clipboards.iter() {
+                    self.write(clipboard_to_json(cb, self.record_text));
+                }
+            }
+            Some(message::Union::FileAction(fa)) => {
+                let (action, path) = match &fa.union {
+                    Some(file_action::Union::ReadDir(rd)) => ("read_dir", rd.path.clone()),
+                    Some(file_action::Union::Send(s)) => ("send", s.path.clone()),
+                    Some(file_action::Union::Receive(r)) => ("receive", r.path.clone()),
+                    Some(file_action::Union::Create(c)) => ("create_dir", c.path.clone()),
+                    Some(file_action::Union::RemoveDir(rd)) => ("remove_dir", rd.path.clone()),
+                    Some(file_action::Union::RemoveFile(rf)) => ("remove_file", rf.path.clone()),
+                    Some(file_action::Union::Rename(r)) => {
+                        ("rename", format!("{} -> {}", r.path, r.new_name))
+                    }
+                    _ => return,
+                };
+                self.write(json!({
+                    "type": "file",
+                    "action": action,
+                    "path": path,
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    fn write(&mut self, mut event: Value) {
+        event["ts"] = json!(chrono::Utc::now().timestamp_millis());
+        if let Err(err) = writeln!(self.writer, "{}", event) {
+            log::error!("Failed to write event record: {}", err);
+            return;
+        }
+        self.written = true;
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.writer.flush().ok();
+            self.last_flush = Instant::now();
+        }
+    }
+}
+
+impl Drop for EventRecorder {
+    fn drop(&mut self) {
+        self.writer.flush().ok();
+        scrap::record::set_recording(&self.path.to_string_lossy(), false);
+        if !self.written {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+fn modifiers_to_json(modifiers: &[EnumOrUnknown<ControlKey>]) -> Value {
+    json!(modifiers
+        .iter()
+        .map(|m| format!("{:?}", m.enum_value_or_default()))
+        .collect::<Vec<_>>())
+}
+
+fn clipboard_to_json(cb: &Clipboard, record_text: bool) -> Value {
+    let content = if cb.compress {
+        hbb_common::compress::decompress(&cb.content)
+    } else {
+        cb.content.to_vec()
+    };
+    let mut event = json!({
+        "type": "clipboard",
+        "format": format!("{:?}", cb.format.enum_value_or_default()),
+        "size": content.len(),
+    });
+    if !record_text {
+        event["masked"] = json!(true);
+    } else if cb.format.enum_value() == Ok(ClipboardFormat::Text) {
+        let text = String::from_utf8_lossy(&content);
+        event["text"] = json!(text
+            .chars()
+            .take(MAX_CLIPBOARD_TEXT_LEN)
+            .collect::<String>());
+    }
+    event
+}
+
+// (local time in ms of the first frame, display index) from the file name of a recorded video
+fn parse_video_name(name: &str) -> Option<(i64, usize)> {
+    let mut start = None;
+    let mut display = 0;
+    for part in name.split(&['_', '.'][..]) {
+        if part.len() == 17 && part.chars().all(|c| c.is_ascii_digit()) {
+            let t = chrono::NaiveDateTime::parse_from_str(part, "%Y%m%d%H%M%S%3f").ok()?;
+            start = Some(t.and_utc().timestamp_millis());
+        } else if let Some(idx) = part.strip_prefix("display") {
+            display = idx.parse().ok()?;
+        }
+    }
+    Some((start?, display))
+}
+
+/// Write an html player next to `video`, which plays it with the events of `events` drawn over
+/// it. The path of the player is returned.
+pub fn make_player(video: &str, events: &str) -> ResultType<String> {
+    let video = Path::new(video);
+    let name = video
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let Some((start, display)) = parse_video_name(&name) else {
+        bail!("Failed to get the start time from the file name {}", name);
+    };
+    let mut origin = (0, 0);
+    let mut utc_offset = chrono::Local::now().offset().local_minus_utc() as i64;
+    let mut list = Vec::new();
+    for line in BufReader::new(File::open(events)?).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let event: Value = serde_json::from_str(&line)?;
+        if event["type"] == "start" {
+            let d = &event["displays"][display];
+            origin = (d["x"].as_i64().unwrap_or(0), d["y"].as_i64().unwrap_or(0));
+            utc_offset = event["utc_offset"].as_i64().unwrap_or(utc_offset);
+        } else {
+            list.push(event);
+        }
+    }
+    // escape `</script>` in the embedded json
+    let escape = |v: Value| v.to_string().replace("</", "<\\/");
+    let html = PLAYER_HTML
+        .replace("{title}", &name.replace('<', "&lt;"))
+        .replace("{video}", &escape(json!(name)))
+        .replace("{start}", &(start - utc_offset * 1000).to_string())
+        .replace("{origin}", &format!("[{}, {}]", origin.0, origin.1))
+        .replace("{events}", &escape(Value::Array(list)));
+    let path = video.with_extension("html");
+    std::fs::write(&path, html)?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+const PLAYER_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body { margin: 0; display: flex; background: #222; color: #ddd; font: 13px monospace; }
+#player { position: relative; }
+video { display: block; max-width: 75vw; max-height: 100vh; }
+canvas { position: absolute; left: 0; top: 0; pointer-events: none; }
+#log { flex: 1; height: 100vh; overflow: auto; padding: 4px; }
+</style>
+</head>
+<body>
+<div id="player"><video id="video" controls></video><canvas id="overlay"></canvas></div>
+<div id="log"></div>
+<script>
+const START = {start};
+const ORIGIN = {origin};
+const EVENTS = {events};
+const video = document.getElementById('video');
+const canvas = document.getElementById('overlay');
+const log = document.getElementById('log');
+video.src = {video};
+
+// number of events which happened before t
+function upto(t) {
+  let lo = 0, hi = EVENTS.length;
+  while (lo < hi) {
+    const mid = (lo + hi) >> 1;
+    if (EVENTS[mid].ts <= t) lo = mid + 1; else hi = mid;
+  }
+  return lo;
+}
+
+function describe(e) {
+  const mods = e.modifiers && e.modifiers.length ? e.modifiers.join('+') + '+' : '';
+  switch (e.type) {
+    case 'mouse': return 'mouse ' + e.action + ' ' + mods + e.buttons + ' (' + e.x + ', ' + e.y + ')';
+    case 'key': return 'key ' + (e.down || e.press ? 'down ' : 'up ') + mods + (e.masked ? '*' : (e.control_key || e.text || e.chr));
+    case 'clipboard': return 'clipboard ' + e.format + ' ' + e.size + ' bytes' + (e.text ? ': ' + e.text : '');
+    case 'file': return 'file ' + e.action + ' ' + e.path;
+  }
+  return JSON.stringify(e);
+}
+
+let shown = -1;
+function render() {
+  const t = START + video.currentTime * 1000;
+  const n = upto(t);
+  canvas.width = video.clientWidth;
+  canvas.height = video.clientHeight;
+  const sx = canvas.width / (video.videoWidth || 1);
+  const sy = canvas.height / (video.videoHeight || 1);
+  const ctx = canvas.getContext('2d');
+  for (let i = n - 1; i >= 0; i--) {
+    const e = EVENTS[i];
+    if (e.type != 'mouse') continue;
+    const click = e.action == 'down' && t - e.ts < 500;
+    ctx.strokeStyle = click ? 'red' : 'yellow';
+    ctx.lineWidth = 2;
+    ctx.beginPath();
+    ctx.arc((e.x - ORIGIN[0]) * sx, (e.y - ORIGIN[1]) * sy, click ? 14 : 6, 0, 2 * Math.PI);
+    ctx.stroke();
+    break;
+  }
+  if (n != shown) {
+    shown = n;
+    log.textContent = '';
+    EVENTS.slice(0, n)
+      .filter(e => e.type != 'mouse' || e.action == 'down')
+      .slice(-500)
+      .forEach(e => {
+        const div = document.createElement('div');
+        div.textContent = new Date(e.ts).toLocaleTimeString() + ' ' + describe(e);
+        log.appendChild(div);
+      });
+    log.scrollTop = log.scrollHeight;
+  }
+  requestAnimationFrame(render);
+}
+requestAnimationFrame(render);
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_video_name() {
+        let (start, display) =
+            parse_video_name("incoming_123456789_20240102030405678_display1_vp9.webm").unwrap();
+        assert_eq!(display, 1);
+        assert_eq!(
+            chrono::DateTime::from_timestamp_millis(start)
+                .unwrap()
+                .format("%Y%m%d%H%M%S%3f")
+                .to_string(),
+            "20240102030405678"
+        );
+        assert!(parse_video_name("incoming_123456789_display0_vp9.webm").is_none());
+    }
+
+    #[test]
+    fn test_clipboard_to_json() {
+        let cb = Clipboard {
+            content: "secret".as_bytes().to_vec().into(),
+            format: ClipboardFormat::Text.into(),
+            ..Default::default()
+        };
+        let event = clipboard_to_json(&cb, false);
+        assert_eq!(event["size"], 6);
+        assert_eq!(event["masked"], true);
+        assert!(event.get("text").is_none());
+        assert_eq!(clipboard_to_json(&cb, true)["text"], "secret");
+    }
+}
@@ -0,0 +1,336 @@
+// Failed logins tracked per ip and per peer id, persisted so that a restart does not reset them.
+// Every `MAX_FAILURES` consecutive failures of an ip lock it out, the lockout doubles each time up
+// to `MAX_LOCKOUT`. The peer id is chosen by the client, locking it out would let anyone lock a
+// controller out, so its failures are only counted for the alarms and the lockouts list.
+// A successful login or a manual unlock clears the source, otherwise it is forgotten
+// `FORGET_AFTER` its last failure.
+
+use hbb_common::{
+    config::{self, Config},
+    get_time, log,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex};
+
+const FILE: &str = "login_failures.toml";
+const MAX_FAILURES: u32 = 5;
+const BASE_LOCKOUT: i64 = 60 * 1000;
+const MAX_LOCKOUT: i64 = 24 * 60 * 60 * 1000;
+const FORGET_AFTER: i64 = 7 * 24 * 60 * 60 * 1000;
+
+// The password and the second factor are tracked separately, so that the password can not reset
+// the failures of the second factor.
+pub const KIND_PASSWORD: usize = 0;
+pub const KIND_2FA: usize = 1;
+
+lazy_static::lazy_static! {
+    static ref FAILURES: Mutex<Option<LoginFailures>> = Default::default();
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+struct Failure {
+    // consecutive failures since the last lockout
+    #[serde(default)]
+    failures: u32,
+    #[serde(default)]
+    lockouts: u32,
+    #[serde(default)]
+    locked_until: i64,
+    #[serde(default)]
+    last_failure: i64,
+}
+
+impl Failure {
+    fn outdate(&self, now: i64) -> bool {
+        self.locked_until <= now && self.last_failure + FORGET_AFTER < now
+    }
+
+    // Returns true if the failures reach the threshold, which starts a new lockout if `lock`.
+    fn fail(&mut self, now: i64, lock: bool) -> bool {
+        self.last_failure = now;
+        self.failures += 1;
+        if self.failures < MAX_FAILURES {
+            return false;
+        }
+        self.failures = 0;
+        if lock {
+            let lockout = BASE_LOCKOUT
+                .saturating_mul(1 << self.lockouts.min(20))
+                .min(MAX_LOCKOUT);
+            self.locked_until = now + lockout;
+        }
+        self.lockouts += 1;
+        true
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Lockout {
+    // "ip:<ip>" or "id:<peer id>"
+    pub source: String,
+    pub kind: usize,
+    // 0 if the source is not locked, i.e. a peer id
+    pub until: i64,
+    // the times the failures of the source reached the threshold
+    pub lockouts: u32,
+}
+
+impl Lockout {
+    // The failures of the source up to this lockout.
+    pub fn failures(&self) -> u32 {
+        self.lockouts.saturating_mul(MAX_FAILURES)
+    }
+
+    pub fn locked(&self) -> bool {
+        self.until > 0
+    }
+}
+
+// Only the ips are locked out.
+fn is_locking(source: &str) -> bool {
+    !source.starts_with("id:")
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LoginFailures {
+    #[serde(default)]
+    password: HashMap<String, Failure>,
+    #[serde(default)]
+    tfa: HashMap<String, Failure>,
+}
+
+impl LoginFailures {
+    fn load() -> Self {
+        let mut failures: Self = config::load_path(Config::path(FILE));
+        failures.retain(get_time());
+        failures
+    }
+
+    fn store(&self) {
+        if let Err(e) = config::store_path(Config::path(FILE), self) {
+            log::error!("Failed to store login failures: {}", e);
+        }
+    }
+
+    fn map(&mut self, kind: usize) -> &mut HashMap<String, Failure> {
+        if kind == KIND_2FA {
+            &mut self.tfa
+        } else {
+            &mut self.password
+        }
+    }
+
+    fn retain(&mut self, now: i64) {
+        self.password.retain(|_, f| !f.outdate(now));
+        self.tfa.retain(|_, f| !f.outdate(now));
+    }
+
+    fn locked_until(&mut self, kind: usize, sources: &[String], now: i64) -> Option<i64> {
+        let map = self.map(kind);
+        sources
+            .iter()
+            .filter(|s| is_locking(s))
+            .filter_map(|s| map.get(s))
+            .map(|f| f.locked_until)
+            .filter(|until| *until > now)
+            .max()
+    }
+
+    // Returns the sources whose failures reach the threshold by this failure.
+    fn fail(&mut self, kind: usize, sources: &[String], now: i64) -> Vec<Lockout> {
+        let map = self.map(kind);
+        sources
+            .iter()
+            .filter_map(|source| {
+                let lock = is_locking(source);
+                let failure = map.entry(source.clone()).or_default();
+                failure.fail(now, lock).then(|| Lockout {
+                    source: source.clone(),
+                    kind,
+                    until: if lock { failure.locked_until } else { 0 },
+                    lockouts: failure.lockouts,
+                })
+            })
+            .collect()
+    }
+
+    fn succeed(&mut self, kind: usize, sources: &[String]) -> bool {
+        let map = self.map(kind);
+        let len = map.len();
+        map.retain(|source, _| !sources.contains(source));
+        map.len() != len
+    }
+
+    fn unlock(&mut self, source: &str) -> bool {
+        let removed = self.password.remove(source).is_some();
+        self.tfa.remove(source).is_some() || removed
+    }
+
+    // The ips locked out, and the peer ids whose failures have reached the threshold.
+    fn lockouts(&self, now: i64) -> Vec<Lockout> {
+        [(KIND_PASSWORD, &self.password), (KIND_2FA, &self.tfa)]
+            .into_iter()
+            .flat_map(|(kind, map)| {
+                map.iter().filter_map(move |(source, f)| {
+                    let lock = is_locking(source);
+                    let listed = if lock {
+                        f.locked_until > now
+                    } else {
+                        f.lockouts > 0
+                    };
+                    listed.then(|| Lockout {
+                        source: source.clone(),
+                        kind,
+                        until: if lock { f.locked_until } else { 0 },
+                        lockouts: f.lockouts,
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+fn sources(ip: &str, id: &str) -> Vec<String> {
+    let mut sources = vec![format!("ip:{}", ip)];
+    if !id.is_empty() {
+        sources.push(format!("id:{}", id));
+    }
+    sources
+}
+
+fn with<T>(f: impl FnOnce(&mut LoginFailures) -> (T, bool)) -> T {
+    let mut lock = FAILURES.lock().unwrap();
+    let failures = lock.get_or_insert_with(LoginFailures::load);
+    let (res, store) = f(failures);
+    if store {
+        failures.store();
+    }
+    res
+}
+
+// The time in ms until which the ip is locked out.
+pub fn locked_until(kind: usize, ip: &str) -> Option<i64> {
+    with(|f| (f.locked_until(kind, &sources(ip, ""), get_time()), false))
+}
+
+// Returns the sources whose failures reach the threshold by a failure, the ips are locked out.
+pub fn update(kind: usize, ip: &str, id: &str, success: bool) -> Vec<Lockout> {
+    let sources = sources(ip, id);
+    with(|f| {
+        if success {
+            (vec![], f.succeed(kind, &sources))
+        } else {
+            let now = get_time();
+            f.retain(now);
+            (f.fail(kind, &sources, now), true)
+        }
+    })
+}
+
+pub fn get_lockouts() -> Vec<Lockout> {
+    with(|f| (f.lockouts(get_time()), false))
+}
+
+pub fn get_lockouts_json() -> String {
+    serde_json::to_string(&get_lockouts()).unwrap_or_default()
+}
+
+pub fn unlock(source: &str) {
+    log::info!("Unlock login source: {}", source);
+    with(|f| ((), f.unlock(source)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout() {
+        let mut f = LoginFailures::default();
+        let sources = sources("1.2.3.4", "");
+        assert_eq!(sources, vec!["ip:1.2.3.4".to_owned()]);
+        let mut now = 1_000_000;
+        for _ in 0..MAX_FAILURES - 1 {
+            assert!(f.fail(KIND_PASSWORD, &sources, now).is_empty());
+        }
+        assert_eq!(f.locked_until(KIND_PASSWORD, &sources, now), None);
+        let lockouts = f.fail(KIND_PASSWORD, &sources, now);
+        assert_eq!(lockouts.len(), 1);
+        assert_eq!(lockouts[0].source, "ip:1.2.3.4");
+        assert_eq!(lockouts[0].lockouts, 1);
+        assert_eq!(
+            f.locked_until(KIND_PASSWORD, &sources, now),
+            Some(now + BASE_LOCKOUT)
+        );
+        // the kinds and the other sources are not affected
+        assert_eq!(f.locked_until(KIND_2FA, &sources, now), None);
+        assert_eq!(
+            f.locked_until(KIND_PASSWORD, &["ip:5.6.7.8".to_owned()], now),
+            None
+        );
+        assert_eq!(f.lockouts(now).len(), 1);
+
+        // the lockout doubles
+        now += BASE_LOCKOUT;
+        assert_eq!(f.locked_until(KIND_PASSWORD, &sources, now), None);
+        for _ in 0..MAX_FAILURES {
+            f.fail(KIND_PASSWORD, &sources, now);
+        }
+        assert_eq!(
+            f.locked_until(KIND_PASSWORD, &sources, now),
+            Some(now + 2 * BASE_LOCKOUT)
+        );
+        for _ in 0..20 {
+            for _ in 0..MAX_FAILURES {
+                f.fail(KIND_PASSWORD, &sources, now);
+            }
+        }
+        assert_eq!(
+            f.locked_until(KIND_PASSWORD, &sources, now),
+            Some(now + MAX_LOCKOUT)
+        );
+
+        assert!(f.unlock("ip:1.2.3.4"));
+        assert!(!f.unlock("ip:1.2.3.4"));
+        assert_eq!(f.locked_until(KIND_PASSWORD, &sources, now), None);
+        assert!(f.lockouts(now).is_empty());
+        for _ in 0..MAX_FAILURES {
+            f.fail(KIND_PASSWORD, &sources, now);
+        }
+        assert!(f.locked_until(KIND_PASSWORD, &sources, now).is_some());
+        assert!(f.succeed(KIND_PASSWORD, &sources));
+        assert!(!f.succeed(KIND_PASSWORD, &sources));
+        assert!(f.lockouts(now).is_empty());
+
+        f.fail(KIND_2FA, &sources, now);
+        f.retain(now + FORGET_AFTER + 1);
+        assert!(f.tfa.is_empty());
+    }
+
+    #[test]
+    fn test_id_not_locked() {
+        let mut f = LoginFailures::default();
+        let sources = sources("1.2.3.4", "123456789");
+        assert_eq!(sources[1], "id:123456789");
+        let now = 1_000_000;
+        for _ in 0..MAX_FAILURES - 1 {
+            assert!(f.fail(KIND_PASSWORD, &sources, now).is_empty());
+        }
+        let lockouts = f.fail(KIND_PASSWORD, &sources, now);
+        assert_eq!(lockouts.len(), 2);
+        assert!(lockouts[0].locked());
+        assert_eq!(lockouts[1].source, "id:123456789");
+        assert!(!lockouts[1].locked());
+        assert_eq!(lockouts[1].failures(), MAX_FAILURES);
+        // another ip with the same id is not locked out, but the id is listed
+        let other = ["ip:5.6.7.8".to_owned(), "id:123456789".to_owned()];
+        assert_eq!(f.locked_until(KIND_PASSWORD, &other, now), None);
+        assert_eq!(f.lockouts(now).len(), 2);
+        // the id stays listed after the lockout of the ip ends
+        let lockouts = f.lockouts(now + BASE_LOCKOUT);
+        assert_eq!(lockouts.len(), 1);
+        assert!(!lockouts[0].locked());
+        assert!(f.unlock("id:123456789"));
+        assert!(f.lockouts(now + BASE_LOCKOUT).is_empty());
+    }
+}
@@ -1,9 +1,16 @@
 use super::*;
+use hbb_common::config::keys;
 use scrap::codec::Quality;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 pub const FPS: u32 = 30;
 pub const MIN_FPS: u32 = 1;
 pub const MAX_FPS: u32 = 120;
+// The user who sent the latest input within this time is in control.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(30);
+// The part of the bandwidth cap left for the audio and the other messages.
+const NON_VIDEO_KBPS: u32 = 128;
+// The lowest bitrate ratio a cap can lower the quality to.
+const MIN_CAPPED_RATIO: u32 = 10;
 trait Percent {
     fn as_percent(&self) -> u32;
 }
@@ -28,6 +35,33 @@ struct Delay {
     slower_than_old_state: Option<bool>,
 }
 
+/// Token bucket of a bandwidth cap, which may go into debt, e.g. by a large video frame.
+#[derive(Default, Debug, Copy, Clone)]
+struct BandwidthBucket {
+    // bytes
+    tokens: f64,
+    last: Option<Instant>,
+}
+
+impl BandwidthBucket {
+    fn refill(&mut self, kbps: u32) {
+        let rate = kbps as f64 * 1000. / 8.;
+        let now = Instant::now();
+        let elapsed = self.last.map_or(1., |t| (now - t).as_secs_f64());
+        self.last = Some(now);
+        // at most a burst of one second
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        self.tokens -= bytes as f64;
+    }
+}
+
+fn get_bandwidth_limit(key: &str) -> u32 {
+    Config::get_option(key).trim().parse().unwrap_or(0)
+}
+
 #[derive(Default, Debug, Copy, Clone)]
 struct UserData {
     auto_adjust_fps: Option<u32>, // reserve for compatibility
@@ -36,6 +70,8 @@ struct UserData {
     delay: Option<Delay>,
     response_delayed: bool,
     record: bool,
+    last_input: Option<Instant>,
+    bandwidth: BandwidthBucket,
 }
 
 pub struct VideoQoS {
@@ -44,6 +80,9 @@ pub struct VideoQoS {
     users: HashMap<i32, UserData>,
     bitrate_store: u32,
     support_abr: HashMap<usize, bool>,
+    bandwidth: BandwidthBucket,
+    // the option, which is checked for every frame, is reloaded on refresh
+    controller_priority: bool,
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -82,6 +121,8 @@ impl Default for VideoQoS {
             users: Default::default(),
             bitrate_store: 0,
             support_abr: Default::default(),
+            bandwidth: Default::default(),
+            controller_priority: Default::default(),
         }
     }
 }
@@ -128,7 +169,126 @@ impl VideoQoS {
         Config::get_option("enable-abr") != "N" && self.support_abr.iter().all(|e| *e.1)
     }
 
+    // When the controller has priority, the fps and quality follow the user in control only, and
+    // the other viewers get the frames in best effort. There is still one stream per display, the
+    // viewers are not encoded in separate quality tiers or simulcast layers: a slow controller
+    // lowers the quality for all, and a lagging viewer skips the frames until the next periodic
+    // key frame, see `get_keyframe_interval` of the video service.
+    fn load_controller_priority() -> bool {
+        use hbb_common::config::{keys::OPTION_ALLOW_CONTROLLER_PRIORITY as OPTION, option2bool};
+        option2bool(OPTION, &Config::get_option(OPTION))
+    }
+
+    fn controller(&self) -> Option<i32> {
+        self.users
+            .iter()
+            .filter_map(|(id, u)| u.last_input.map(|t| (*id, t)))
+            .filter(|(_, t)| t.elapsed() < CONTROL_TIMEOUT)
+            .max_by_key(|(_, t)| *t)
+            .map(|(id, _)| id)
+    }
+
+    // The users whose fps, quality and delay are taken into account.
+    fn qos_users(&self) -> Vec<UserData> {
+        if self.controller_priority {
+            if let Some(user) = self.controller().and_then(|id| self.users.get(&id)) {
+                return vec![*user];
+            }
+        }
+        self.users.values().copied().collect()
+    }
+
+    /// Whether the frames of the user are sent in best effort, which means the video service
+    /// does not wait for them, and they may be dropped until the next key frame.
+    pub fn is_best_effort(&self, id: i32) -> bool {
+        self.controller_priority && self.controller().map_or(false, |c| c != id)
+    }
+
+    /// Whether there may be viewers in best effort, for whom the encoder makes periodic key frames.
+    pub fn has_best_effort(&self) -> bool {
+        self.controller_priority && self.users.len() > 1
+    }
+
+    // The video bitrate of a display allowed by the bandwidth caps, all the users receive the same
+    // frames of all the displays.
+    fn video_bandwidth_cap(&self) -> Option<u32> {
+        let users = std::cmp::max(self.users.len(), 1) as u32;
+        let displays = std::cmp::max(self.support_abr.len(), 1) as u32;
+        let global = get_bandwidth_limit(keys::OPTION_BANDWIDTH_LIMIT) / users;
+        let conn = get_bandwidth_limit(keys::OPTION_CONN_BANDWIDTH_LIMIT);
+        let cap = [global, conn].into_iter().filter(|x| *x > 0).min()?;
+        Some(cap.saturating_sub(NON_VIDEO_KBPS).max(cap / 2) / displays)
+    }
+
+    /// The quality limited by the bandwidth caps, for a display of the size.
+    pub fn capped_quality(&self, width: usize, height: usize) -> Quality {
+        let Some(cap) = self.video_bandwidth_cap() else {
+            return self.quality;
+        };
+        let ratio = match self.quality {
+            Quality::Best => 150,
+            Quality::Balanced => 66,
+            Quality::Low => 50,
+            Quality::Custom(b) => b,
+        };
+        let base = scrap::codec::base_bitrate(width as _, height as _);
+        let max_ratio = (cap as u64 * 100 / base as u64) as u32;
+        if ratio <= max_ratio {
+            self.quality
+        } else {
+            Quality::Custom(std::cmp::max(max_ratio, MIN_CAPPED_RATIO))
+        }
+    }
+
+    /// Account the bytes sent to the user against the bandwidth caps.
+    pub fn user_sent(&mut self, id: i32, bytes: usize) {
+        let global = get_bandwidth_limit(keys::OPTION_BANDWIDTH_LIMIT);
+        if global > 0 {
+            self.bandwidth.refill(global);
+            self.bandwidth.consume(bytes);
+        }
+        let conn = get_bandwidth_limit(keys::OPTION_CONN_BANDWIDTH_LIMIT);
+        if conn > 0 {
+            if let Some(user) = self.users.get_mut(&id) {
+                user.bandwidth.refill(conn);
+                user.bandwidth.consume(bytes);
+            }
+        }
+    }
+
+    /// Whether the bandwidth caps allow the user to be sent more data now, e.g. file blocks,
+    /// the video and audio are sent anyway.
+    pub fn user_can_send(&mut self, id: i32) -> bool {
+        let global = get_bandwidth_limit(keys::OPTION_BANDWIDTH_LIMIT);
+        if global > 0 {
+            self.bandwidth.refill(global);
+            if self.bandwidth.tokens < 0. {
+                return false;
+            }
+        }
+        let conn = get_bandwidth_limit(keys::OPTION_CONN_BANDWIDTH_LIMIT);
+        if conn > 0 {
+            if let Some(user) = self.users.get_mut(&id) {
+                user.bandwidth.refill(conn);
+                if user.bandwidth.tokens < 0. {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Record the input of the user, who is in control then.
+    pub fn user_input(&mut self, id: i32) {
+        let old = self.controller();
+        self.users.entry(id).or_default().last_input = Some(Instant::now());
+        if old != Some(id) && self.controller_priority {
+            self.refresh(None);
+        }
+    }
+
     pub fn refresh(&mut self, typ: Option<RefreshType>) {
+        self.controller_priority = Self::load_controller_priority();
         // fps
         let user_fps = |u: &UserData| {
             // custom_fps
@@ -156,10 +316,10 @@ impl VideoQoS {
             }
             return fps;
         };
-        let mut fps = self
-            .users
+        let users = self.qos_users();
+        let mut fps = users
             .iter()
-            .map(|(_, u)| user_fps(u))
+            .map(user_fps)
             .filter(|u| *u >= MIN_FPS)
             .min()
             .unwrap_or(FPS);
@@ -170,10 +330,9 @@ impl VideoQoS {
 
         // quality
         // latest image quality
-        let latest_quality = self
-            .users
+        let latest_quality = users
             .iter()
-            .map(|(_, u)| u.quality)
+            .map(|u| u.quality)
             .filter(|q| *q != None)
             .max_by(|a, b| a.unwrap_or_default().0.cmp(&b.unwrap_or_default().0))
             .unwrap_or_default()
@@ -185,10 +344,9 @@ impl VideoQoS {
         let abr_enabled = self.in_vbr_state();
         if abr_enabled && typ != Some(RefreshType::SetImageQuality) {
             // max delay
-            let delay = self
-                .users
+            let delay = users
                 .iter()
-                .map(|u| u.1.delay)
+                .map(|u| u.delay)
                 .filter(|d| d.is_some())
                 .max_by(|a, b| {
                     (a.unwrap_or_default().state as u32).cmp(&(b.unwrap_or_default().state as u32))
@@ -45,6 +45,8 @@ use hbb_common::{
 };
 #[cfg(feature = "hwcodec")]
 use scrap::hwcodec::{HwRamEncoder, HwRamEncoderConfig};
+#[cfg(feature = "openh264")]
+use scrap::openh264::OpenH264EncoderConfig;
 #[cfg(feature = "vram")]
 use scrap::vram::{VRamEncoder, VRamEncoderConfig};
 #[cfg(not(windows))]
@@ -52,6 +54,7 @@ use scrap::Capturer;
 use scrap::{
     aom::AomEncoderConfig,
     codec::{Encoder, EncoderCfg, Quality},
+    lossless::LosslessTiles,
     record::{Recorder, RecorderContext},
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
     CodecFormat, Display, EncodeInput, TraitCapturer,
@@ -100,7 +103,11 @@ impl VideoFrameController {
         self.send_conn_ids.clear();
     }
 
-    fn set_send(&mut self, tm: Instant, conn_ids: HashSet<i32>) {
+    fn set_send(&mut self, tm: Instant, mut conn_ids: HashSet<i32>) {
+        // don't wait for the viewers in best effort
+        let qos = VIDEO_QOS.lock().unwrap();
+        conn_ids.retain(|id| !qos.is_best_effort(*id));
+        drop(qos);
         if !conn_ids.is_empty() {
             self.cur = tm;
             self.send_conn_ids = conn_ids;
@@ -415,18 +422,24 @@ fn run(vs: VideoService) -> ResultType<()> {
     let mut video_qos = VIDEO_QOS.lock().unwrap();
     video_qos.refresh(None);
     let mut spf;
-    let mut quality = video_qos.quality();
+    let mut quality = video_qos.capped_quality(c.width, c.height);
     let record_incoming = config::option2bool(
         "allow-auto-record-incoming",
         &Config::get_option("allow-auto-record-incoming"),
     );
     let client_record = video_qos.record();
+    let best_effort = video_qos.has_best_effort();
+    let keyframe_interval = get_keyframe_interval(
+        client_record || record_incoming,
+        best_effort,
+        video_qos.fps(),
+    );
     drop(video_qos);
     let (mut encoder, encoder_cfg, codec_format, use_i444, recorder) = match setup_encoder(
         &c,
         display_idx,
         quality,
-        client_record,
+        keyframe_interval,
         record_incoming,
         last_portable_service_running,
     ) {
@@ -444,7 +457,7 @@ fn run(vs: VideoService) -> ResultType<()> {
                 &c,
                 display_idx,
                 quality,
-                client_record,
+                keyframe_interval,
                 record_incoming,
                 last_portable_service_running,
             )?
@@ -463,6 +476,11 @@ fn run(vs: VideoService) -> ResultType<()> {
         .unwrap()
         .set_support_abr(display_idx, encoder.support_abr());
     log::info!("initial quality: {quality:?}");
+    let mut lossless = if Encoder::use_lossless_tiles() {
+        Some(LosslessTiles::new(c.width, c.height))
+    } else {
+        None
+    };
 
     if sp.is_option_true(OPTION_REFRESH) {
         sp.set_option_bool(OPTION_REFRESH, false);
@@ -496,9 +514,10 @@ fn run(vs: VideoService) -> ResultType<()> {
 
         let mut video_qos = VIDEO_QOS.lock().unwrap();
         spf = video_qos.spf();
-        if quality != video_qos.quality() {
-            log::debug!("quality: {:?} -> {:?}", quality, video_qos.quality());
-            quality = video_qos.quality();
+        let capped_quality = video_qos.capped_quality(c.width, c.height);
+        if quality != capped_quality {
+            log::debug!("quality: {:?} -> {:?}", quality, capped_quality);
+            quality = capped_quality;
             if encoder.support_changing_quality() {
                 allow_err!(encoder.set_quality(quality));
                 video_qos.store_bitrate(encoder.bitrate());
@@ -513,6 +532,21 @@ fn run(vs: VideoService) -> ResultType<()> {
             log::info!("switch due to record changed");
             bail!("SWITCH");
         }
+        if best_effort != video_qos.has_best_effort() {
+            log::info!("switch due to best effort changed");
+            bail!("SWITCH");
+        }
+        if best_effort {
+            // keep the time between the key frames when the fps changes a lot
+            let interval =
+                get_keyframe_interval(client_record || record_incoming, true, video_qos.fps());
+            if let (Some(new), Some(old)) = (interval, keyframe_interval) {
+                if new > old * 2 || new * 2 < old {
+                    log::info!("switch due to key frame interval {} -> {}", old, new);
+                    bail!("SWITCH");
+                }
+            }
+        }
         drop(video_qos);
 
         if sp.is_option_true(OPTION_REFRESH) {
@@ -537,6 +571,10 @@ fn run(vs: VideoService) -> ResultType<()> {
             log::info!("switch due to i444 changed");
             bail!("SWITCH");
         }
+        if Encoder::use_lossless_tiles() != lossless.is_some() {
+            log::info!("switch due to lossless tiles changed");
+            bail!("SWITCH");
+        }
         #[cfg(all(windows, feature = "vram"))]
         if c.is_gdi() && encoder.input_texture() {
             log::info!("changed to gdi when using vram");
@@ -567,7 +605,12 @@ fn run(vs: VideoService) -> ResultType<()> {
         let res = match c.frame(spf) {
             Ok(frame) => {
                 repeat_encode_counter = 0;
-                if frame.valid() {
+                // skip the frames without damage
+                if frame.valid() && frame.damage().map_or(true, |d| !d.is_empty()) {
+                    encoder.set_damage(frame.damage());
+                    if let Some(tiles) = lossless.as_mut() {
+                        tiles.update(&frame);
+                    }
                     let frame = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data)?;
                     let send_conn_ids = handle_one_frame(
                         display_idx,
@@ -580,6 +623,7 @@ fn run(vs: VideoService) -> ResultType<()> {
                         &mut first_frame,
                         capture_width,
                         capture_height,
+                        &mut lossless,
                     )?;
                     frame_controller.set_send(now, send_conn_ids);
                 }
@@ -638,6 +682,7 @@ fn run(vs: VideoService) -> ResultType<()> {
                             &mut first_frame,
                             capture_width,
                             capture_height,
+                            &mut lossless,
                         )?;
                         frame_controller.set_send(now, send_conn_ids);
                     }
@@ -664,6 +709,14 @@ fn run(vs: VideoService) -> ResultType<()> {
             }
         }
 
+        // the tiles of the regions static for a while, drawn over the frame just sent
+        if let Some(msg) = lossless
+            .as_mut()
+            .and_then(|tiles| tiles.next_message(display_idx))
+        {
+            sp.send_video_frame(msg);
+        }
+
         let mut fetched_conn_ids = HashSet::new();
         let timeout_millis = 3_000u64;
         let wait_begin = Instant::now();
@@ -705,11 +758,29 @@ impl Drop for Raii {
     }
 }
 
+// https://www.wowza.com/community/t/the-correct-keyframe-interval-in-obs-studio/95162
+const RECORD_KEYFRAME_INTERVAL: usize = 240;
+// The longest time the viewers in best effort wait for a key frame when they lag.
+const BEST_EFFORT_KEYFRAME_SECS: u32 = 4;
+
+// The periodic key frames are for the recordings and the viewers in best effort, whose interval
+// is kept in time, not to freeze them for long at a low fps.
+fn get_keyframe_interval(record: bool, best_effort: bool, fps: u32) -> Option<usize> {
+    if best_effort {
+        let frames = (BEST_EFFORT_KEYFRAME_SECS * fps) as usize;
+        Some(frames.clamp(1, RECORD_KEYFRAME_INTERVAL))
+    } else if record {
+        Some(RECORD_KEYFRAME_INTERVAL)
+    } else {
+        None
+    }
+}
+
 fn setup_encoder(
     c: &CapturerInfo,
     display_idx: usize,
     quality: Quality,
-    client_record: bool,
+    keyframe_interval: Option<usize>,
     record_incoming: bool,
     last_portable_service_running: bool,
 ) -> ResultType<(
@@ -723,7 +794,7 @@ fn setup_encoder(
         &c,
         display_idx,
         quality,
-        client_record || record_incoming,
+        keyframe_interval,
         last_portable_service_running,
     );
     Encoder::set_fallback(&encoder_cfg);
@@ -738,7 +809,7 @@ fn get_encoder_config(
     c: &CapturerInfo,
     _display_idx: usize,
     quality: Quality,
-    record: bool,
+    keyframe_interval: Option<usize>,
     _portable_service: bool,
 ) -> EncoderCfg {
     #[cfg(all(windows, feature = "vram"))]
@@ -748,8 +819,6 @@ fn get_encoder_config(
     }
     #[cfg(feature = "vram")]
     Encoder::update(scrap::codec::EncodingUpdate::Check);
-    // https://www.wowza.com/community/t/the-correct-keyframe-interval-in-obs-studio/95162
-    let keyframe_interval = if record { Some(240) } else { None };
     let negotiated_codec = Encoder::negotiated_codec();
     match negotiated_codec {
         CodecFormat::H264 | CodecFormat::H265 => {
@@ -775,6 +844,15 @@ fn get_encoder_config(
                     keyframe_interval,
                 });
             }
+            #[cfg(feature = "openh264")]
+            if negotiated_codec == CodecFormat::H264 {
+                return EncoderCfg::OPENH264(OpenH264EncoderConfig {
+                    width: c.width,
+                    height: c.height,
+                    quality,
+                    keyframe_interval,
+                });
+            }
             EncoderCfg::VPX(VpxEncoderConfig {
                 width: c.width as _,
                 height: c.height as _,
@@ -832,7 +910,7 @@ fn get_recorder(record_incoming: bool, display: usize) -> Arc<Mutex<Option<Recor
             display,
             tx,
         })
-        .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
+        .map_or(Default::default(), Recorder::into_shared)
     } else {
         Default::default()
     };
@@ -911,6 +989,7 @@ fn handle_one_frame(
     first_frame: &mut bool,
     width: usize,
     height: usize,
+    lossless: &mut Option<LosslessTiles>,
 ) -> ResultType<HashSet<i32>> {
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
@@ -928,6 +1007,14 @@ fn handle_one_frame(
         Ok(mut vf) => {
             *encode_fail_counter = 0;
             vf.display = display as _;
+            if let Some(tiles) = lossless.as_mut() {
+                if scrap::lossless::is_key_frame(&vf) {
+                    // the client clears its tiles with a key frame
+                    tiles.reset();
+                } else if let Some(msg) = tiles.take_invalidated(display) {
+                    sp.send_video_frame(msg);
+                }
+            }
             let mut msg = Message::new();
             msg.set_video_frame(vf);
             recorder
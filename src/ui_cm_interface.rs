@@ -15,7 +15,7 @@ use hbb_common::{
     fs::{self, get_string, new_send_confirm, DigestCheckResult},
     log,
     message_proto::*,
-    protobuf::Message as _,
+    protobuf::{Message as _, MessageField},
     tokio::{
         self,
         sync::mpsc::{self, UnboundedSender},
@@ -424,9 +424,9 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                     self.cm.new_message(self.conn_id, text);
                                 }
                                 Data::FS(mut fs) => {
-                                    if let ipc::FS::WriteBlock { id, file_num, data: _, compressed } = fs {
+                                    if let ipc::FS::WriteBlock { id, file_num, data: _, compressed, blk_id, copy_blks } = fs {
                                         if let Ok(bytes) = self.stream.next_raw().await {
-                                            fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed};
+                                            fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed, blk_id, copy_blks};
                                             handle_fs(fs, &mut write_jobs, &self.tx, Some(&tx_log)).await;
                                         }
                                     } else {
@@ -828,6 +828,8 @@ async fn handle_fs(
             file_num,
             data,
             compressed,
+            blk_id,
+            copy_blks,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
                 if let Err(err) = job
@@ -836,6 +838,8 @@ async fn handle_fs(
                         file_num,
                         data,
                         compressed,
+                        blk_id,
+                        copy_blks,
                         ..Default::default()
                     })
                     .await
@@ -850,6 +854,8 @@ async fn handle_fs(
             file_size,
             last_modified,
             is_upload,
+            can_resume,
+            can_delta,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
                 let mut req = FileTransferSendConfirmRequest {
@@ -863,6 +869,8 @@ async fn handle_fs(
                     file_num,
                     last_modified,
                     file_size,
+                    can_resume,
+                    can_delta,
                     ..Default::default()
                 };
                 if let Some(file) = job.files().get(file_num as usize) {
@@ -878,6 +886,8 @@ async fn handle_fs(
                                 DigestCheckResult::NeedConfirm(mut digest) => {
                                     // upload to server, but server has the same file, request
                                     digest.is_upload = is_upload;
+                                    // the signatures are only hashed once the overwrite is confirmed
+                                    digest.can_delta = can_delta;
                                     let mut msg_out = Message::new();
                                     let mut fr = FileResponse::new();
                                     fr.set_digest(digest);
@@ -885,6 +895,7 @@ async fn handle_fs(
                                     send_raw(msg_out, &tx);
                                 }
                                 DigestCheckResult::NoSuchFile => {
+                                    req.set_offset_blk(job.prepare_resume(&digest));
                                     let msg_out = new_send_confirm(req);
                                     send_raw(msg_out, &tx);
                                 }
@@ -897,6 +908,30 @@ async fn handle_fs(
                 }
             }
         }
+        ipc::FS::GetSignatures { id, file_num } => {
+            if let Some(job) = fs::get_job(id, write_jobs) {
+                if let Some(file) = job.files().get(file_num as usize) {
+                    let path = get_string(&job.join(&file.name));
+                    let tx = tx.clone();
+                    // hashing a large file must not block the ipc loop
+                    tokio::spawn(async move {
+                        let signatures = fs::get_block_signatures(path).await;
+                        let mut msg_out = Message::new();
+                        let mut fr = FileResponse::new();
+                        fr.set_digest(FileTransferDigest {
+                            id,
+                            file_num,
+                            is_upload: true,
+                            // always set, so that the read side can tell the answer apart
+                            signatures: MessageField::some(signatures.unwrap_or_default()),
+                            ..Default::default()
+                        });
+                        msg_out.set_file_response(fr);
+                        send_raw(msg_out, &tx);
+                    });
+                }
+            }
+        }
         ipc::FS::Rename { id, path, new_name } => {
             rename_file(path, new_name, id, tx).await;
         }
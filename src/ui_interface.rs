@@ -746,6 +746,7 @@ pub fn get_lan_peers() -> Vec<HashMap<&'static str, String>> {
                 ("username", peer.username.clone()),
                 ("hostname", peer.hostname.clone()),
                 ("platform", peer.platform.clone()),
+                ("verified", peer.verified.to_string()),
             ])
         })
         .collect()
@@ -1442,6 +1443,13 @@ pub fn generate2fa() -> String {
     crate::auth_2fa::generate2fa()
 }
 
+pub fn get_authenticator_public_key() -> String {
+    crate::auth_2fa::get_authenticator_public_key().unwrap_or_else(|e| {
+        log::error!("Failed to create authenticator: {}", e);
+        "".to_owned()
+    })
+}
+
 pub fn verify2fa(code: String) -> bool {
     let res = crate::auth_2fa::verify2fa(code);
     if res {
@@ -1526,6 +1534,73 @@ pub fn clear_trusted_devices() {
     ipc::clear_trusted_devices();
 }
 
+// The expiry and the scope of the codes, not the codes.
+#[cfg(feature = "flutter")]
+pub fn get_access_codes() -> String {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    return Config::get_access_codes_json();
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return ipc::get_access_codes();
+}
+
+// Returns the new code, it is shown once to be handed over.
+#[cfg(feature = "flutter")]
+pub fn generate_access_code(
+    minutes: u32,
+    view_only: bool,
+    file_transfer: bool,
+    clipboard: bool,
+) -> String {
+    let code = hbb_common::password_security::new_access_code(
+        minutes,
+        view_only,
+        file_transfer,
+        clipboard,
+    );
+    let res = code.code.clone();
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    Config::add_access_code(code);
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    ipc::add_access_code(code);
+    res
+}
+
+// The line to add to the authorized keys of the peers.
+#[cfg(feature = "flutter")]
+pub fn get_controller_public_key() -> String {
+    use hbb_common::sodiumoxide::base64;
+    let (_, pk) = LocalConfig::get_controller_key_pair();
+    format!(
+        "ed25519 {} {}",
+        base64::encode(pk, base64::Variant::Original),
+        crate::username()
+    )
+}
+
+#[cfg(feature = "flutter")]
+pub fn clear_access_codes() {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    Config::clear_access_codes();
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    ipc::clear_access_codes();
+}
+
+#[cfg(feature = "flutter")]
+pub fn get_login_lockouts() -> String {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    return crate::server::login_failure::get_lockouts_json();
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return ipc::get_login_lockouts();
+}
+
+#[cfg(feature = "flutter")]
+pub fn unlock_login_source(source: String) {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    crate::server::login_failure::unlock(&source);
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    ipc::unlock_login_source(source);
+}
+
 #[cfg(feature = "flutter")]
 pub fn max_encrypt_len() -> usize {
     hbb_common::config::ENCRYPT_MAX_LEN
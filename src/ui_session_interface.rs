@@ -700,6 +700,30 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    // Ask the peer, which is on the same LAN as the asleep `id`, to wake it up.
+    pub fn send_wol(&self, id: &str) {
+        let targets = crate::lan::get_wol_targets(id);
+        if targets.is_empty() {
+            self.msgbox(
+                "custom-nocancel-nook-hasclose",
+                "Wake-on-LAN",
+                "No MAC address of the device is known, please discover it in the LAN first.",
+                "",
+            );
+            return;
+        }
+        let mut misc = Misc::new();
+        let (ips, macs) = targets.into_iter().unzip();
+        misc.set_wake_on_lan(WakeOnLan {
+            macs,
+            ips,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     pub fn send_chat(&self, text: String) {
         let mut misc = Misc::new();
         misc.set_chat_message(ChatMessage {
@@ -1765,6 +1789,32 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
             );
             log::info!("Remote rdp port: {}", port);
             start_one_port_forward(handler, 0, "".to_owned(), port, receiver, &key, &token).await;
+        } else if handler.args.len() == 1 {
+            let path = handler.args[0].clone();
+            match crate::port_forward::load_rules(&path) {
+                Ok(rules) => {
+                    if let Err(err) = crate::port_forward::listen_rules(
+                        handler.get_id(),
+                        handler.password.clone(),
+                        rules,
+                        handler.clone(),
+                        receiver,
+                        &key,
+                        &token,
+                        handler.lc.clone(),
+                    )
+                    .await
+                    {
+                        handler.on_error(&format!("Port forward rules failed: {}", err));
+                    }
+                }
+                Err(err) => {
+                    handler.on_error(&format!(
+                        "Failed to load port forward rules {}: {}",
+                        path, err
+                    ));
+                }
+            }
         } else if handler.args.len() == 0 {
             let pfs = handler.lc.read().unwrap().port_forwards.clone();
             let mut queues = HashMap::<i32, mpsc::UnboundedSender<Data>>::new();
@@ -1817,7 +1867,7 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
                 || handler.args[2].parse::<i32>().unwrap_or(0) <= 0
                 || port <= 0
             {
-                handler.on_error("Invalid arguments, usage:<br><br> rustdesk --port-forward remote-id listen-port remote-host remote-port");
+                handler.on_error("Invalid arguments, usage:<br><br> rustdesk --port-forward remote-id listen-port remote-host remote-port<br>rustdesk --port-forward remote-id rules-file");
             }
             let remote_host = handler.args[1].clone();
             let remote_port = handler.args[2].parse::<i32>().unwrap_or(0);
@@ -1860,6 +1910,8 @@ async fn start_one_port_forward<T: InvokeUiSession>(
         handler.lc.clone(),
         remote_host,
         remote_port,
+        crate::port_forward::ForwardMode::Tcp,
+        "".to_owned(),
     )
     .await
     {